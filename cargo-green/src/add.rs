@@ -1,6 +1,17 @@
+use std::sync::LazyLock;
+
 use serde::{Deserialize, Serialize};
 
-use crate::network::Network;
+use camino::Utf8Path;
+
+use crate::{cross::linker, image_uri::ImageUri, network::Network};
+
+/// [`xx`](https://github.com/tonistiigi/xx) cross-compilation helpers, pre-locked
+///
+/// TODO: pin major + lock by pulling
+pub(crate) static XX_IMAGE: LazyLock<ImageUri> = LazyLock::new(|| {
+    ImageUri::try_new("docker-image://docker.io/tonistiigi/xx:1.6.1@sha256:923441d7c25f1e2eb5789f82d987693c47b8ed987c4ab3b075d6ed2b5d6779a3").unwrap()
+});
 
 macro_rules! ENV_ADD_APK {
     () => {
@@ -14,7 +25,7 @@ macro_rules! ENV_ADD_APT {
     };
 }
 
-#[derive(Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 #[serde(default)]
 #[serde(deny_unknown_fields)]
 #[serde(rename_all = "kebab-case")]
@@ -50,21 +61,81 @@ impl Add {
     }
 
     // TODO: finer package installs per os/distro
-    pub(crate) fn as_block(&self, last: &str) -> (Network, String) {
+    /// With `for_build_platform`, packages are installed for `$BUILDPLATFORM` instead of `$TARGETPLATFORM`.
+    pub(crate) fn as_block(&self, last: &str, for_build_platform: bool) -> (Network, String) {
         if self.is_empty() {
             let block = format!("\n{last}\n", last = last.trim());
             return (Network::None, block);
         }
 
-        // TODO: pin major + lock by pulling
-        const XX: &str = "docker.io/tonistiigi/xx:1.6.1@sha256:923441d7c25f1e2eb5789f82d987693c47b8ed987c4ab3b075d6ed2b5d6779a3";
-
-        // NOTE: `ARG TARGETPLATFORM` is needed by xx
         let block = format!(
             r#"
-FROM --platform=$BUILDPLATFORM {XX} AS xx
+FROM --platform=$BUILDPLATFORM {xx} AS xx
 {last}
-ARG TARGETPLATFORM
+{install}"#,
+            xx = XX_IMAGE.noscheme(),
+            last = last.trim(),
+            install = if for_build_platform { self.as_host_install() } else { self.as_install() },
+        );
+
+        // TODO: switch to colon-separated values because apt-satisfy may use commas
+        //   cc https://askubuntu.com/a/1516406/719302 + find the whole grammar
+        // > satisfy satisfies dependency strings, as used in Build-Depends.
+        // > It also handles conflicts, by prefixing an argument with "Conflicts: ".
+
+        // TODO: lock package resolving indexes to snaphots (date = base image's?)
+        // https://github.com/reproducible-containers/repro-sources-list.sh/blob/39fbf150e3a5062d4c6b9a241f25af133e7cb6f0/repro-sources-list.sh
+        // https://github.com/reproducible-containers/repro-get/blob/fcc0f1b7907fc0543d10b6934f1ef3a963bcd9c7/examples/gcc/Dockerfile
+        (Network::Default, block) // TODO: pull Network::None using ADDs
+    }
+
+    /// Installs packages from within a stage that follows the `xx` one (see `as_block`)
+    #[must_use]
+    pub(crate) fn as_install(&self) -> String {
+        // NOTE: `ARG TARGETPLATFORM` is needed by xx
+        self.install("TARGETPLATFORM", "")
+    }
+
+    /// Installs packages for the machine running the build, even when cross-compiling
+    #[must_use]
+    fn as_host_install(&self) -> String {
+        // NOTE: BuildKit-provided platform ARGs cannot be given another value
+        self.install("BUILDPLATFORM", " && export TARGETPLATFORM=$BUILDPLATFORM \\\n")
+    }
+
+    /// Installs packages (and a linker) for `$TARGETPLATFORM`, with apk or apt as the distro has.
+    ///
+    /// `xx-cc` sets up `clang` (installed for `$BUILDPLATFORM`) to target `$TARGETPLATFORM`,
+    /// then gets linked as `/usr/local/bin/{triple}-clang` (see [`linker`](crate::cross::linker)).
+    #[must_use]
+    pub(crate) fn as_cross_install(&self) -> String {
+        format!(
+            r#"ARG TARGETPLATFORM
+RUN \
+  --mount=from=xx,source=/usr/bin/xx-apk,dst=/usr/bin/xx-apk \
+  --mount=from=xx,source=/usr/bin/xx-apt,dst=/usr/bin/xx-apt-get \
+  --mount=from=xx,source=/usr/bin/xx-cargo,dst=/usr/bin/xx-cargo \
+  --mount=from=xx,source=/usr/bin/xx-cc,dst=/usr/bin/xx-clang \
+  --mount=from=xx,source=/usr/bin/xx-info,dst=/usr/bin/xx-info \
+    set -eux \
+ && if command -v apk >/dev/null 2>&1; then \
+                                                          xx-apk     add     --no-cache                 '{apk}'; \
+    else \
+      xx-apt-get update && DEBIAN_FRONTEND=noninteractive xx-apt-get install --no-install-recommends -y '{apt}'; \
+    fi \
+ && xx-clang --setup-target-triple \
+ && ln -s "$(command -v "$(xx-clang --print-target-triple)-clang")" {linker}
+"#,
+            apk = quote_pkgs(&self.apk),
+            apt = quote_pkgs(&self.apt),
+            linker =
+                Utf8Path::new("/usr/local/bin").join(linker("$(xx-cargo --print-target-triple)")),
+        )
+    }
+
+    fn install(&self, arg: &str, export: &str) -> String {
+        format!(
+            r#"ARG {arg}
 RUN \
   --mount=from=xx,source=/usr/bin/xx-apk,dst=/usr/bin/xx-apk \
   --mount=from=xx,source=/usr/bin/xx-apt,dst=/usr/bin/xx-apt-get \
@@ -79,26 +150,15 @@ RUN \
   --mount=from=xx,source=/usr/bin/xx-verify,dst=/usr/bin/xx-verify \
   --mount=from=xx,source=/usr/bin/xx-windres,dst=/usr/bin/xx-windres \
     set -eux \
- && if command -v apk >/dev/null 2>&1; then \
+{export} && if command -v apk >/dev/null 2>&1; then \
                                                           xx-apk     add     --no-cache                 '{apk}'; \
     else \
       xx-apt-get update && DEBIAN_FRONTEND=noninteractive xx-apt-get satisfy --no-install-recommends -y '{apt}'; \
     fi
 "#,
-            last = last.trim(),
             apk = quote_pkgs(&self.apk),
             apt = quote_pkgs(&self.apt),
-        );
-
-        // TODO: switch to colon-separated values because apt-satisfy may use commas
-        //   cc https://askubuntu.com/a/1516406/719302 + find the whole grammar
-        // > satisfy satisfies dependency strings, as used in Build-Depends.
-        // > It also handles conflicts, by prefixing an argument with "Conflicts: ".
-
-        // TODO: lock package resolving indexes to snaphots (date = base image's?)
-        // https://github.com/reproducible-containers/repro-sources-list.sh/blob/39fbf150e3a5062d4c6b9a241f25af133e7cb6f0/repro-sources-list.sh
-        // https://github.com/reproducible-containers/repro-get/blob/fcc0f1b7907fc0543d10b6934f1ef3a963bcd9c7/examples/gcc/Dockerfile
-        (Network::Default, block) // TODO: pull Network::None using ADDs
+        )
     }
 }
 
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{
+    backend::{Building, RunnerBackend},
+    build::BuildRequest,
+    du::Du,
+    green::Green,
+    image_uri::ImageUri,
+};
+
+/// `docker buildx`, with its image store and a managed `docker-container` builder
+#[derive(Debug)]
+pub(crate) struct Docker;
+
+#[async_trait(?Send)]
+impl RunnerBackend for Docker {
+    fn build(&self, green: &Green, req: &BuildRequest<'_>) -> Result<Building> {
+        let mut cmd = green.cmd()?;
+        cmd.arg("build");
+        let (call, envs) = green.with_docker_args(&mut cmd, req);
+        Building::spawn(cmd, call, envs)
+    }
+
+    async fn inspect_image(&self, green: &Green, img: &ImageUri) -> Result<Option<ImageUri>> {
+        green.inspect_image_store(img).await
+    }
+
+    async fn fetch_digest(&self, green: &Green, img: &ImageUri) -> Result<ImageUri> {
+        green.lock_from_registry(img).await
+    }
+
+    async fn disk_usage(&self, green: &Green) -> Result<Vec<Du>> {
+        green.runner_du().await
+    }
+
+    async fn push(&self, green: &Green, img: &str) -> Result<()> {
+        green.push_all_tags(img).await
+    }
+
+    async fn prune(&self, green: &Green, all: bool) -> Result<()> {
+        green.runner_prune(all).await
+    }
+
+    async fn setup_builder(&self, green: &mut Green, env: Option<String>) -> Result<()> {
+        green.setup_buildx_builder(env).await
+    }
+
+    async fn inspect_builder(&self, green: &mut Green) -> Result<()> {
+        green.inspect_buildx_builder().await
+    }
+
+    async fn show_builder(&self, green: &Green) -> Result<String> {
+        green.show_buildx_builder().await
+    }
+
+    async fn create_builder(&self, green: &mut Green, name: &str) -> Result<()> {
+        green.create_buildx_builder(name).await
+    }
+
+    async fn remove_builder(&self, green: &Green, name: &str, keep_state: bool) -> Result<()> {
+        green.remove_buildx_builder(name, keep_state).await
+    }
+}
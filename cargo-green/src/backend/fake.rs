@@ -0,0 +1,223 @@
+//! A [`RunnerBackend`] answering from a script, so builds can run without a daemon.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Cursor,
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use camino::Utf8PathBuf;
+use indexmap::IndexSet;
+use tokio::{
+    io::{AsyncWriteExt, duplex},
+    spawn,
+};
+use tokio_tar::Builder as TarBuilder;
+
+use crate::{
+    backend::{Building, Reader, RunnerBackend},
+    build::{BuildRequest, ERRCODE, STDERR, STDOUT},
+    cache::result::header_for,
+    du::Du,
+    green::Green,
+    image_uri::ImageUri,
+    md::{BuildContext, DIESES},
+    stage::Stage,
+};
+
+/// What a build was asked to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Built {
+    /// As piped to the runner
+    pub(crate) containerfile: String,
+    pub(crate) target: Stage,
+    pub(crate) contexts: IndexSet<BuildContext>,
+    pub(crate) out_dir: Option<Utf8PathBuf>,
+}
+
+/// What a build answers
+#[derive(Debug, Default)]
+pub(crate) struct Scripted {
+    /// Files `rustc` wrote, relative to its `--out-dir`
+    pub(crate) files: Vec<(String, Vec<u8>)>,
+    /// `rustc`'s STDOUT
+    pub(crate) stdout: String,
+    /// `rustc`'s STDERR
+    pub(crate) stderr: String,
+    /// `rustc`'s exit code, when it failed
+    pub(crate) errcode: Option<i32>,
+    /// The runner's own output
+    pub(crate) logs: String,
+    /// The runner's exit code
+    pub(crate) code: i32,
+}
+
+type Script = Box<dyn Fn(&Built) -> Scripted + Send + Sync>;
+
+/// Records every call, answering builds through its script.
+pub(crate) struct Fake {
+    script: Script,
+    /// The local image store
+    images: Vec<ImageUri>,
+    /// What registries serve, by unlocked image
+    digests: HashMap<String, String>,
+    pub(crate) builds: Mutex<Vec<Built>>,
+    pub(crate) pushed: Mutex<Vec<String>>,
+    /// Whether each prune was asked for `--all`
+    pub(crate) pruned: Mutex<Vec<bool>>,
+}
+
+impl fmt::Debug for Fake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fake")
+            .field("images", &self.images)
+            .field("digests", &self.digests)
+            .field("builds", &self.builds)
+            .field("pushed", &self.pushed)
+            .field("pruned", &self.pruned)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Fake {
+    pub(crate) fn new(script: impl Fn(&Built) -> Scripted + Send + Sync + 'static) -> Self {
+        Self {
+            script: Box::new(script),
+            images: vec![],
+            digests: [].into(),
+            builds: Mutex::new(vec![]),
+            pushed: Mutex::new(vec![]),
+            pruned: Mutex::new(vec![]),
+        }
+    }
+
+    /// Puts a locked image in the local image store
+    pub(crate) fn with_image(mut self, img: &str) -> Self {
+        self.images.push(ImageUri::try_new(img).expect("locked image"));
+        self
+    }
+
+    /// Has registries serve `img` at `digest`
+    pub(crate) fn with_digest(mut self, img: &str, digest: &str) -> Self {
+        self.digests.insert(img.to_owned(), digest.to_owned());
+        self
+    }
+
+    /// Has `green` build through this fake, which is returned for inspecting its records
+    pub(crate) fn into_green(self, green: Green) -> (Green, Arc<Self>) {
+        let fake = Arc::new(self);
+        (green.with_backend(fake.clone()), fake)
+    }
+}
+
+/// Tarballs files the way a BuildKit `--output=type=tar` would
+async fn tarball(target: &Stage, scripted: &Scripted) -> Result<Vec<u8>> {
+    let mut ar = TarBuilder::new(vec![]);
+    let rustc = [(STDOUT, &scripted.stdout), (STDERR, &scripted.stderr)];
+    let errcode = scripted.errcode.map(|code| code.to_string());
+    let stdio = rustc
+        .into_iter()
+        .chain(errcode.iter().map(|code| (ERRCODE, code)))
+        .filter(|(_, txt)| !txt.is_empty())
+        .map(|(name, txt)| (format!("{target}-{name}"), txt.as_bytes()));
+    let files = scripted.files.iter().map(|(name, data)| (name.to_owned(), data.as_slice()));
+    for (name, data) in files.chain(stdio) {
+        let mut header = header_for(&name, data.len())?;
+        header.set_mode(0o644);
+        header.set_cksum();
+        ar.append(&header, data).await.map_err(|e| anyhow!("Failed tarring {name}: {e}"))?;
+    }
+    ar.into_inner().await.map_err(|e| anyhow!("Failed finishing tarball: {e}"))
+}
+
+#[async_trait(?Send)]
+impl RunnerBackend for Fake {
+    fn build(&self, _: &Green, req: &BuildRequest<'_>) -> Result<Building> {
+        let containerfile = std::fs::read_to_string(req.containerfile)
+            .map_err(|e| anyhow!("Failed reading {}: {e}", req.containerfile))?
+            .lines()
+            .filter(|line| !line.starts_with(DIESES))
+            .map(|line| format!("{line}\n"))
+            .collect();
+        let built = Built {
+            containerfile,
+            target: req.target.to_owned(),
+            contexts: req.contexts.to_owned(),
+            out_dir: req.out_dir.map(ToOwned::to_owned),
+        };
+        let scripted = (self.script)(&built);
+        self.builds.lock().unwrap().push(built);
+
+        let stdout = req.out_dir.map(|_| {
+            let (mut tx, rx) = duplex(1 << 16);
+            let target = req.target.to_owned();
+            let scripted = Scripted { logs: String::new(), ..scripted };
+            spawn(async move {
+                let tarball = tarball(&target, &scripted).await.expect("tarball");
+                tx.write_all(&tarball).await.expect("piping tarball");
+            });
+            Box::new(rx) as Reader
+        });
+
+        let code = scripted.code;
+        Ok(Building {
+            call: format!("fake build --target={}", req.target),
+            envs: String::new(),
+            pid: 0,
+            stdin: None,
+            stdout,
+            stderr: Some(Box::new(Cursor::new(scripted.logs.into_bytes()))),
+            status: Box::pin(async move { Ok(ExitStatus::from_raw(code << 8)) }),
+        })
+    }
+
+    async fn inspect_image(&self, _: &Green, img: &ImageUri) -> Result<Option<ImageUri>> {
+        Ok(self.images.iter().find(|cached| cached.unlocked() == *img).cloned())
+    }
+
+    async fn fetch_digest(&self, _: &Green, img: &ImageUri) -> Result<ImageUri> {
+        let Some(digest) = self.digests.get(img.as_str()) else {
+            bail!("Failed getting digest for {img}: no such image")
+        };
+        Ok(img.lock(digest))
+    }
+
+    async fn disk_usage(&self, _: &Green) -> Result<Vec<Du>> {
+        Ok(vec![])
+    }
+
+    async fn push(&self, _: &Green, img: &str) -> Result<()> {
+        self.pushed.lock().unwrap().push(img.to_owned());
+        Ok(())
+    }
+
+    async fn prune(&self, _: &Green, all: bool) -> Result<()> {
+        self.pruned.lock().unwrap().push(all);
+        Ok(())
+    }
+
+    async fn setup_builder(&self, _: &mut Green, _: Option<String>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn inspect_builder(&self, _: &mut Green) -> Result<()> {
+        Ok(())
+    }
+
+    async fn show_builder(&self, _: &Green) -> Result<String> {
+        Ok("fake".to_owned())
+    }
+
+    async fn create_builder(&self, _: &mut Green, _: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn remove_builder(&self, _: &Green, _: &str, _: bool) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,431 @@
+//! What each runner does when asked to build, inspect, lock, measure, push or manage a builder.
+
+use std::{fmt::Debug, future::Future, io, pin::Pin, process::ExitStatus, sync::Arc};
+
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use log::info;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    process::Command,
+};
+
+use crate::{
+    build::BuildRequest, du::Du, ext::CommandExt, green::Green, image_uri::ImageUri, runner::Runner,
+};
+
+mod buildx;
+#[cfg(test)]
+pub(crate) mod fake;
+
+pub(crate) use buildx::Docker;
+
+pub(crate) type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// A started build
+pub(crate) struct Building {
+    /// The call, for showing and reproducing the build
+    pub(crate) call: String,
+    /// Its environment, for showing and reproducing the build
+    pub(crate) envs: String,
+    pub(crate) pid: u32,
+    /// Takes the containerfile, when the backend reads it from there
+    pub(crate) stdin: Option<Writer>,
+    /// Streams the tarball'd result, when building to an output directory
+    pub(crate) stdout: Option<Reader>,
+    /// Progress and errors
+    pub(crate) stderr: Option<Reader>,
+    pub(crate) status: Pin<Box<dyn Future<Output = io::Result<ExitStatus>> + Send>>,
+}
+
+impl Building {
+    pub(crate) fn spawn(mut cmd: Command, call: String, envs: String) -> Result<Self> {
+        let mut child = cmd.spawn().map_err(|e| anyhow!("Failed starting `{call}`: {e}"))?;
+        let stdin = child.stdin.take().map(|x| Box::new(x) as Writer);
+        let stdout = child.stdout.take().map(|x| Box::new(x) as Reader);
+        let stderr = child.stderr.take().map(|x| Box::new(x) as Reader);
+        let pid = child.id().unwrap_or_default();
+        let status = Box::pin(async move { child.wait().await });
+        Ok(Self { call, envs, pid, stdin, stdout, stderr, status })
+    }
+}
+
+/// Everything [`Green`] needs from a runner.
+///
+/// `green` is passed along for its settings: the backend itself holds no state.
+#[async_trait(?Send)]
+pub(crate) trait RunnerBackend: Debug {
+    /// Starts building `req.target` of `req.containerfile`
+    fn build(&self, green: &Green, req: &BuildRequest<'_>) -> Result<Building>;
+
+    /// Looks for the digest of `img` in the local image store
+    async fn inspect_image(&self, green: &Green, img: &ImageUri) -> Result<Option<ImageUri>>;
+
+    /// Locks `img` to the digest its registry currently serves
+    async fn fetch_digest(&self, green: &Green, img: &ImageUri) -> Result<ImageUri>;
+
+    /// Lists the images pulled in the builder's cache
+    async fn disk_usage(&self, green: &Green) -> Result<Vec<Du>>;
+
+    /// Pushes every local tag of `img`
+    async fn push(&self, green: &Green, img: &str) -> Result<()>;
+
+    /// Frees the builder's cache (`all`: internal and frontend records too)
+    async fn prune(&self, green: &Green, all: bool) -> Result<()>;
+
+    /// Creates, re-creates or checks the builder named by `$BUILDX_BUILDER`
+    async fn setup_builder(&self, green: &mut Green, env: Option<String>) -> Result<()>;
+
+    /// Reads the builder's container ID and data directory
+    async fn inspect_builder(&self, green: &mut Green) -> Result<()>;
+
+    /// Describes the builder, for humans
+    async fn show_builder(&self, green: &Green) -> Result<String>;
+
+    async fn create_builder(&self, green: &mut Green, name: &str) -> Result<()>;
+
+    async fn remove_builder(&self, green: &Green, name: &str, keep_state: bool) -> Result<()>;
+}
+
+/// A backend standing in for the runner's own, see [`Green::with_backend`].
+///
+/// Holds no settings, so it is ignored when comparing [`Green`]s.
+#[derive(Debug, Default)]
+pub(crate) struct BackendOverride(Option<Arc<dyn RunnerBackend>>);
+
+impl PartialEq for BackendOverride {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for BackendOverride {}
+
+impl Green {
+    /// Has `backend` answer instead of the runner's own, e.g. a fake one
+    #[cfg(test)]
+    #[must_use]
+    pub(crate) fn with_backend(mut self, backend: Arc<dyn RunnerBackend>) -> Self {
+        self.backend_override = BackendOverride(Some(backend));
+        self
+    }
+
+    #[must_use]
+    pub(crate) fn backend(&self) -> Arc<dyn RunnerBackend> {
+        if let Some(ref backend) = self.backend_override.0 {
+            return backend.clone();
+        }
+        match self.runner {
+            Runner::Docker => Arc::new(Docker),
+            Runner::Podman => Arc::new(Podman),
+            Runner::Buildkitd => Arc::new(Buildkitd),
+            Runner::Nerdctl => Arc::new(Nerdctl),
+            Runner::None => Arc::new(NoRunner),
+        }
+    }
+}
+
+/// `podman` builds in-process through Buildah, see [`crate::podman`]
+#[derive(Debug)]
+pub(crate) struct Podman;
+
+#[async_trait(?Send)]
+impl RunnerBackend for Podman {
+    fn build(&self, green: &Green, req: &BuildRequest<'_>) -> Result<Building> {
+        let mut cmd = green.cmd()?;
+        cmd.arg("build");
+        let (call, envs) = green.with_podman_args(&mut cmd, req)?;
+        Building::spawn(cmd, call, envs)
+    }
+
+    async fn inspect_image(&self, green: &Green, img: &ImageUri) -> Result<Option<ImageUri>> {
+        green.inspect_image_store(img).await
+    }
+
+    async fn fetch_digest(&self, green: &Green, img: &ImageUri) -> Result<ImageUri> {
+        green.lock_from_registry(img).await
+    }
+
+    async fn disk_usage(&self, green: &Green) -> Result<Vec<Du>> {
+        info!(
+            "Skipping inspecting builder cache (runner:{}): pulls land in the image store",
+            green.runner
+        );
+        Ok(vec![])
+    }
+
+    async fn push(&self, green: &Green, img: &str) -> Result<()> {
+        green.push_podman_tags(img).await
+    }
+
+    async fn prune(&self, green: &Green, _: bool) -> Result<()> {
+        bail!("Runner {} has no builder cache: prune its image store instead", green.runner)
+    }
+
+    async fn setup_builder(&self, green: &mut Green, env: Option<String>) -> Result<()> {
+        if let Some(name) = env.filter(|name| !name.is_empty()) {
+            bail!("Runner {} has no builders: unset ${}={name:?}", green.runner, BUILDX_BUILDER!())
+        }
+        if let Some(ref img) = green.builder.image {
+            bail!("Runner {} has no builder to run ${}={img:?}", green.runner, ENV_BUILDER_IMAGE!())
+        }
+        info!("Skipping builder setup (runner:{})", green.runner);
+        Ok(())
+    }
+
+    async fn inspect_builder(&self, _: &mut Green) -> Result<()> {
+        Ok(())
+    }
+
+    async fn show_builder(&self, green: &Green) -> Result<String> {
+        let mut cmd = green.cmd()?;
+        cmd.arg("info");
+
+        let (succeeded, stdout, stderr) = cmd.exec().await?;
+        if !succeeded {
+            let stderr = String::from_utf8_lossy(&stderr);
+            bail!("Failed to describe podman: {stderr}")
+        }
+        Ok(String::from_utf8_lossy(&stdout).into_owned())
+    }
+
+    async fn create_builder(&self, green: &mut Green, _: &str) -> Result<()> {
+        bail!("Runner {} has no builder to manage: it builds in-process", green.runner)
+    }
+
+    async fn remove_builder(&self, green: &Green, _: &str, _: bool) -> Result<()> {
+        bail!("Runner {} has no builder to manage: it builds in-process", green.runner)
+    }
+}
+
+/// Calls a standalone BuildKit daemon's client, `buildctl`, see [`crate::buildctl`]
+#[derive(Debug)]
+pub(crate) struct Buildkitd;
+
+#[async_trait(?Send)]
+impl RunnerBackend for Buildkitd {
+    fn build(&self, green: &Green, req: &BuildRequest<'_>) -> Result<Building> {
+        let mut cmd = green.cmd()?;
+        cmd.arg("build");
+        let (call, envs) = green.with_buildctl_args(&mut cmd, req);
+        Building::spawn(cmd, call, envs)
+    }
+
+    async fn inspect_image(&self, green: &Green, _: &ImageUri) -> Result<Option<ImageUri>> {
+        info!("Skipping inspecting image cache (runner:{}): no image store", green.runner);
+        Ok(None)
+    }
+
+    async fn fetch_digest(&self, green: &Green, img: &ImageUri) -> Result<ImageUri> {
+        green.lock_from_registry(img).await
+    }
+
+    async fn disk_usage(&self, green: &Green) -> Result<Vec<Du>> {
+        green.runner_du().await
+    }
+
+    async fn push(&self, green: &Green, _: &str) -> Result<()> {
+        bail!("Runner {} has no image store to push from", green.runner)
+    }
+
+    async fn prune(&self, green: &Green, all: bool) -> Result<()> {
+        green.runner_prune(all).await
+    }
+
+    async fn setup_builder(&self, green: &mut Green, _: Option<String>) -> Result<()> {
+        info!("Skipping builder setup (runner:{})", green.runner);
+        Ok(())
+    }
+
+    async fn inspect_builder(&self, _: &mut Green) -> Result<()> {
+        Ok(())
+    }
+
+    async fn show_builder(&self, green: &Green) -> Result<String> {
+        let mut cmd = green.cmd()?;
+        cmd.args(["debug", "workers"]);
+
+        let (succeeded, stdout, stderr) = cmd.exec().await?;
+        if !succeeded {
+            let stderr = String::from_utf8_lossy(&stderr);
+            bail!("Failed to list workers: {stderr}")
+        }
+        Ok(String::from_utf8_lossy(&stdout).into_owned())
+    }
+
+    async fn create_builder(&self, green: &mut Green, _: &str) -> Result<()> {
+        bail!("Runner {} has no builder to manage", green.runner)
+    }
+
+    async fn remove_builder(&self, green: &Green, _: &str, _: bool) -> Result<()> {
+        bail!("Runner {} has no builder to manage", green.runner)
+    }
+}
+
+/// `nerdctl` over containerd, see [`crate::nerdctl`]
+#[derive(Debug)]
+pub(crate) struct Nerdctl;
+
+#[async_trait(?Send)]
+impl RunnerBackend for Nerdctl {
+    fn build(&self, green: &Green, req: &BuildRequest<'_>) -> Result<Building> {
+        let mut cmd = green.cmd()?;
+        cmd.arg("build");
+        let (call, envs) = green.with_nerdctl_args(&mut cmd, req)?;
+        Building::spawn(cmd, call, envs)
+    }
+
+    async fn inspect_image(&self, green: &Green, img: &ImageUri) -> Result<Option<ImageUri>> {
+        green.inspect_image_store(img).await
+    }
+
+    async fn fetch_digest(&self, green: &Green, img: &ImageUri) -> Result<ImageUri> {
+        green.lock_from_registry(img).await
+    }
+
+    async fn disk_usage(&self, green: &Green) -> Result<Vec<Du>> {
+        info!("Skipping inspecting builder cache (runner:{}): no `du`", green.runner);
+        Ok(vec![])
+    }
+
+    async fn push(&self, green: &Green, img: &str) -> Result<()> {
+        green.push_all_tags(img).await
+    }
+
+    async fn prune(&self, green: &Green, all: bool) -> Result<()> {
+        green.runner_prune(all).await
+    }
+
+    async fn setup_builder(&self, green: &mut Green, _: Option<String>) -> Result<()> {
+        info!("Skipping builder setup (runner:{})", green.runner);
+        Ok(())
+    }
+
+    async fn inspect_builder(&self, _: &mut Green) -> Result<()> {
+        Ok(())
+    }
+
+    async fn show_builder(&self, green: &Green) -> Result<String> {
+        bail!("Runner {} has no builder to manage: it uses its namespace's buildkitd", green.runner)
+    }
+
+    async fn create_builder(&self, green: &mut Green, _: &str) -> Result<()> {
+        bail!("Runner {} has no builder to manage: it uses its namespace's buildkitd", green.runner)
+    }
+
+    async fn remove_builder(&self, green: &Green, _: &str, _: bool) -> Result<()> {
+        bail!("Runner {} has no builder to manage: it uses its namespace's buildkitd", green.runner)
+    }
+}
+
+/// Builds nothing: `rustc` runs locally, reusing results built elsewhere
+#[derive(Debug)]
+pub(crate) struct NoRunner;
+
+#[async_trait(?Send)]
+impl RunnerBackend for NoRunner {
+    fn build(&self, green: &Green, _: &BuildRequest<'_>) -> Result<Building> {
+        bail!("BUG: build() called with runner {}", green.runner)
+    }
+
+    async fn inspect_image(&self, green: &Green, _: &ImageUri) -> Result<Option<ImageUri>> {
+        info!("Skipping inspecting image cache (runner:{})", green.runner);
+        Ok(None)
+    }
+
+    async fn fetch_digest(&self, _: &Green, img: &ImageUri) -> Result<ImageUri> {
+        info!("Skipping fetching image digest (runner:{})", Runner::None);
+        Ok(img.to_owned())
+    }
+
+    async fn disk_usage(&self, green: &Green) -> Result<Vec<Du>> {
+        info!("Skipping inspecting builder cache (runner:{})", green.runner);
+        Ok(vec![])
+    }
+
+    async fn push(&self, green: &Green, _: &str) -> Result<()> {
+        bail!("Runner {} cannot push", green.runner)
+    }
+
+    async fn prune(&self, green: &Green, _: bool) -> Result<()> {
+        bail!("Runner {} has no builder", green.runner)
+    }
+
+    async fn setup_builder(&self, green: &mut Green, _: Option<String>) -> Result<()> {
+        info!("Skipping builder setup (runner:{})", green.runner);
+        Ok(())
+    }
+
+    async fn inspect_builder(&self, _: &mut Green) -> Result<()> {
+        Ok(())
+    }
+
+    async fn show_builder(&self, green: &Green) -> Result<String> {
+        bail!("Runner {} has no builder", green.runner)
+    }
+
+    async fn create_builder(&self, green: &mut Green, _: &str) -> Result<()> {
+        bail!("Runner {} has no builder", green.runner)
+    }
+
+    async fn remove_builder(&self, green: &Green, _: &str, _: bool) -> Result<()> {
+        bail!("Runner {} has no builder", green.runner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::fake::{Fake, Scripted},
+        image_uri::{SYNTAX_IMAGE, SYNTAX_IMAGE_LOCKED},
+        stage::RUST,
+    };
+
+    const DIGEST: &str = "sha256:27086352fd5e1907ea2b934eb1023f217c5ae087992eb59fde121dce9c9ff21e";
+
+    #[tokio::test]
+    async fn locks_images() {
+        let rust = ImageUri::try_new("docker-image://docker.io/library/rust:1-slim").unwrap();
+        let fake = Fake::new(|_| Scripted::default())
+            .with_image(rust.lock(DIGEST).as_str())
+            .with_digest(SYNTAX_IMAGE.as_str(), SYNTAX_IMAGE_LOCKED.digest());
+        let (green, _) = fake.into_green(Green::default());
+
+        assert_eq!(green.maybe_lock_image(&rust).await.unwrap(), rust.lock(DIGEST));
+        assert_eq!(green.fetch_digest(&SYNTAX_IMAGE).await.unwrap(), *SYNTAX_IMAGE_LOCKED);
+        assert!(green.fetch_digest(&rust).await.is_err());
+    }
+
+    #[test]
+    fn reports_failed_builds() {
+        let fake = Fake::new(|_| Scripted {
+            logs: "ERROR: failed to solve: process did not complete successfully".to_owned(),
+            code: 1,
+            ..Default::default()
+        });
+        let (green, fake) = fake.into_green(Green::default());
+
+        let containerfile =
+            std::env::temp_dir().join(format!("{}.Dockerfile", uuid::Uuid::new_v4()));
+        let containerfile: camino::Utf8PathBuf = containerfile.try_into().unwrap();
+        std::fs::write(&containerfile, "FROM scratch AS rust-base\n").unwrap();
+
+        // Same value as other tests: it is read once per process
+        let err = temp_env::with_var("CARGO_TARGET_DIR", Some("/some/path/"), || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(green.build_cacheonly(&containerfile, &RUST))
+                .unwrap_err()
+        });
+        assert!(err.to_string().starts_with("retried 5 times: Runner failed."), "{err}");
+        std::fs::remove_file(&containerfile).unwrap();
+
+        let builds = fake.builds.lock().unwrap();
+        assert_eq!(builds.len(), 2, "one for the TUI, one to catch errors");
+        assert_eq!(builds[0].containerfile, "FROM scratch AS rust-base\n");
+    }
+}
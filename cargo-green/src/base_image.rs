@@ -1,4 +1,4 @@
-use std::sync::LazyLock;
+use std::{collections::HashSet, sync::LazyLock};
 
 use anyhow::{Result, anyhow, bail};
 use camino::Utf8Path;
@@ -9,7 +9,7 @@ use crate::{
     add::Add,
     image_uri::ImageUri,
     network::Network,
-    rustup::{CHECKSUMS, VERSION},
+    rustup::{CHECKSUMS, Dist, ToolchainFile, VERSION, maybe_get_local_host_triple},
     stage::RST,
     target_dir::replace_carefully,
 };
@@ -26,6 +26,12 @@ macro_rules! ENV_WITH_NETWORK {
     };
 }
 
+macro_rules! ENV_ALSO_RUN {
+    () => {
+        "CARGOGREEN_ALSO_RUN"
+    };
+}
+
 macro_rules! ENV_COMPONENTS {
     () => {
         "CARGOGREEN_COMPONENTS"
@@ -56,6 +62,11 @@ pub(crate) struct BaseImage {
     #[serde(rename = "base-image")]
     pub(crate) image: ImageUri,
 
+    #[doc = include_str!(concat!("../docs/",ENV_ALSO_RUN!(),".md"))]
+    #[serde(rename = "also-run")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) also_run: Vec<String>,
+
     /// Computed base stage. Not user-settable.
     #[doc(hidden)]
     pub(crate) image_inline: String,
@@ -66,6 +77,7 @@ impl Default for BaseImage {
         Self {
             with_network: Network::default(),
             image: BASE_IMAGE.clone(),
+            also_run: vec![],
             image_inline: "".to_owned(),
         }
     }
@@ -77,35 +89,85 @@ impl BaseImage {
     pub(crate) fn make_block(
         &self,
         toolchain: &str,
+        toolchain_file: &ToolchainFile,
         components: &[String],
+        dists: &[Dist],
         add: &Add,
+        cross: bool,
     ) -> Result<Self> {
         // TODO: multiplatformify (using auto ARG.s?)
         let host = maybe_get_local_host_triple(toolchain)?;
 
-        let Some(checksum) = CHECKSUMS.get(&host) else {
-            bail!("Unhandled rustup host {host:?} please report to {REPO}")
-        };
-
         let image = self.image.clone();
 
+        let profile = toolchain_file.profile();
+
+        let components = toolchain_file.components(components);
         let components = if !components.is_empty() {
             format!(" --component {}", components.join(","))
         } else {
             "".to_owned()
         };
 
+        let targets = if !toolchain_file.targets.is_empty() {
+            format!(" --target {}", toolchain_file.targets.join(","))
+        } else {
+            "".to_owned()
+        };
+
         // Rewrite host cargo/rustc so the base_image ones can be used
         // Also, propagate RUSTUP_TOOLCHAIN so Rustup skips looking for rust-toolchain.toml
         //   If you are trying to install a package that requires a specific nightly feature or a very new stable version,
         //   you must ensure your active toolchain meets those requirements before running the install command.
         //   Cargo won't auto-switch for you based on the dependency tree.
 
+        let (with_network, fetch, install) = if dists.is_empty() {
+            let Some(checksum) = CHECKSUMS.get(&host) else {
+                bail!("Unhandled rustup host {host:?} please report to {REPO}")
+            };
+            let fetch = format!(
+                r#"ADD --chmod=0144 --checksum=sha256:{checksum} \
+  https://static.rust-lang.org/rustup/archive/{VERSION}/{host}/rustup-init /rustup-init"#
+            );
+            let install = format!(
+                r#"RUN \
+  --mount=from=rustup-{toolchain},source=/rustup-init,dst=/rustup-init \
+    set -eux \
+ && /rustup-init --verbose -y --no-modify-path --profile {profile} --default-toolchain {toolchain} --default-host {host}{components}{targets} \
+ && chmod -R a+w $RUSTUP_HOME $CARGO_HOME"#
+            );
+            (Network::Default, fetch, install) // rustup-init requires network
+        } else {
+            // Tarballs pinned by the channel manifest: no network needed to install them
+            let fetch = dists
+                .iter()
+                .map(|Dist { url, sha256 }| {
+                    format!("ADD --unpack --checksum=sha256:{sha256} \\\n  {url} /")
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            let install = format!(
+                r#"RUN \
+  --network=none \
+  --mount=from=rustup-{toolchain},dst=/rustup-dist \
+    set -eux \
+ && mkdir -p $RUSTUP_HOME/toolchains/$RUSTUP_TOOLCHAIN $CARGO_HOME/bin \
+ && for dist in /rustup-dist/*/; do \
+      for component in $(cat $dist/components); do \
+        cp -R $dist$component/. $RUSTUP_HOME/toolchains/$RUSTUP_TOOLCHAIN/; \
+      done; \
+    done \
+ && rm -f $RUSTUP_HOME/toolchains/$RUSTUP_TOOLCHAIN/manifest.in \
+ && ln -s $RUSTUP_HOME/toolchains/$RUSTUP_TOOLCHAIN/bin/* $CARGO_HOME/bin/ \
+ && chmod -R a+w $RUSTUP_HOME $CARGO_HOME"#
+            );
+            (Network::None, fetch, install)
+        };
+
         let rustup_block = format!(
             r#"
 FROM scratch AS rustup-{toolchain}
-ADD --chmod=0144 --checksum=sha256:{checksum} \
-  https://static.rust-lang.org/rustup/archive/{VERSION}/{host}/rustup-init /rustup-init
+{fetch}
 FROM --platform=$BUILDPLATFORM {base} AS {RST}
 SHELL {shell:?}
 ENV       CARGO_HOME={CARGO_HOME} \
@@ -114,34 +176,19 @@ ENV       CARGO_HOME={CARGO_HOME} \
 ENV CARGO=$RUSTUP_HOME/toolchains/$RUSTUP_TOOLCHAIN/bin/cargo \
     RUSTC=$RUSTUP_HOME/toolchains/$RUSTUP_TOOLCHAIN/bin/rustc \
      PATH=$CARGO_HOME/bin:$PATH
-RUN \
-  --mount=from=rustup-{toolchain},source=/rustup-init,dst=/rustup-init \
-    set -eux \
- && /rustup-init --verbose -y --no-modify-path --profile minimal --default-toolchain {toolchain} --default-host {host}{components} \
- && chmod -R a+w $RUSTUP_HOME $CARGO_HOME
+{install}
 "#,
             shell = ["/bin/sh", "-eux", "-c"],
             base = image.noscheme(),
         );
-        let with_network = Network::Default; // rustup-init requires network TODO: turn rustup-init calls into ADDs
 
-        // have buildkit call rustc with `--target $(adapted $TARGETPLATFORM)`, if not given `--target`
-        // `adapted` translates buildkit platform format to rustc's
-        //
-        // maybe that's too naive
-        //   do more research with `cargo cross`
+        // When cross-compiling (`--target`), builds run on $BUILDPLATFORM for $TARGETPLATFORM:
+        // the toolchain and default packages are the host's, while the linker and
+        // added packages are the target's (through xx).
         //
         // Use https://github.com/search?q=repo%3Across-rs/cross%20path%3Adockerfile&type=code images as auto base image?
         //
         // osx https://github.com/tonistiigi/xx?tab=readme-ov-file#external-sdk-support
-        //
-        // https://github.com/tonistiigi/xx?tab=readme-ov-file#rust
-        // xx-cargo
-        //
-        // RUN apk add clang lld
-        // ARG TARGETPLATFORM
-        // RUN cargo build --target=$(xx-cargo --print-target-triple) --release --target-dir ./build && \
-        //     xx-verify ./build/$(xx-cargo --print-target-triple)/release/hello_cargo
 
         // TODO: find a way to install packages without requiring Network (ie using only ADDs)
         // TODO: lock distro packages we install, somehow.
@@ -149,17 +196,72 @@ RUN \
         //   https://github.com/reproducible-containers/repro-pkg-cache
         //   https://github.com/reproducible-containers/repro-get
 
-        let (_with_network, image_inline) = Add {
+        let defaults = Add {
             // From https://github.com/rust-lang/docker-rust/blob/d14e1ad7efeb270012b1a7e88fea699b1d1082f2/nightly/alpine3.20/Dockerfile
             apk: vec!["ca-certificates".to_owned(), "gcc".to_owned()],
             // From https://github.com/rust-lang/docker-rust/blob/d14e1ad7efeb270012b1a7e88fea699b1d1082f2/nightly/bullseye/slim/Dockerfile
             apt: vec!["ca-certificates".to_owned(), "gcc".to_owned(), "libc6-dev".to_owned()],
+        };
+        let (add_network, mut image_inline) = if cross {
+            // xx-cc's cross-compiler, see https://github.com/tonistiigi/xx#rust
+            let clang = vec!["clang".to_owned(), "lld".to_owned()];
+            let host = Add { apk: clang.clone(), apt: clang };
+            let (add_network, mut image_inline) =
+                defaults.union(&host).as_block(&rustup_block, true);
+            let target = Add {
+                apk: vec!["gcc".to_owned(), "musl-dev".to_owned()],
+                apt: vec!["gcc".to_owned(), "libc6-dev".to_owned()],
+            };
+            image_inline.push_str(&target.union(add).as_cross_install());
+            (add_network, image_inline)
+        } else {
+            defaults.union(add).as_block(&rustup_block, false)
+        };
+        let with_network = if add_network == Network::None { with_network } else { add_network };
+
+        for cmd in &self.also_run {
+            if cmd.contains('\n') {
+                image_inline.push_str(&format!("RUN <<{HEREDOC}\n{cmd}\n{HEREDOC}\n"));
+            } else {
+                image_inline.push_str(&format!("RUN {cmd}\n"));
+            }
         }
-        .union(add)
-        .as_block(&rustup_block);
 
-        Ok(Self { with_network, image, image_inline })
+        Ok(Self { with_network, image, also_run: self.also_run.clone(), image_inline })
+    }
+}
+
+/// Delimiter of `also-run` multi-line commands
+const HEREDOC: &str = "ALSO_RUN";
+
+/// Reads a JSON array of commands, so multi-line ones fit in an environment variable
+pub(crate) fn parse_also_run(val: &str) -> Result<Vec<String>> {
+    serde_json::from_str(val).map_err(|e| anyhow!("must be a JSON array of strings: {e}"))
+}
+
+#[must_use]
+pub(crate) fn show_also_run(also_run: &[String]) -> String {
+    serde_json::to_string(also_run).expect("strings serialize")
+}
+
+pub(crate) fn check_also_run(field: &[String], origin: &str) -> Result<()> {
+    if field.iter().any(|x| x.is_empty() || x.trim() != x) {
+        bail!("{origin} contains empty commands or leading/trailing whitespace")
+    }
+
+    if field.len() != field.iter().collect::<HashSet<_>>().len() {
+        bail!("{origin} contains duplicates")
     }
+
+    for cmd in field {
+        if cmd.lines().any(|line| line.trim() == HEREDOC) {
+            bail!("{origin} must not contain a {HEREDOC:?} line: {cmd:?}")
+        }
+        if !cmd.contains('\n') && cmd.ends_with('\\') {
+            bail!("{origin} must not end with a line continuation: {cmd:?}")
+        }
+    }
+    Ok(())
 }
 
 pub(crate) fn rewrite_cargo_home(cargo_home: &Utf8Path, path: &str) -> String {
@@ -187,19 +289,6 @@ fn test_rewrite_rustup_home() {
     );
 }
 
-fn maybe_get_local_host_triple(toolchain: &str) -> Result<String> {
-    use std::str::FromStr;
-
-    let toolchain = rustup_toolchain_manifest::Toolchain::from_str(toolchain)
-        .map_err(|e| anyhow!("Failed parsing $RUSTUP_TOOLCHAIN={toolchain:?}: {e}"))?;
-
-    if let Some(host) = toolchain.host.map(|h| h.target_triple) {
-        Ok(host.to_owned())
-    } else {
-        rustc_host::from_cli().map_err(|e| anyhow!("Failed getting local host triple: {e}"))
-    }
-}
-
 #[cfg(test)]
 #[test_case::test_matrix(["1.80.0-x86_64-unknown-linux-gnu", "nightly-2025-09-14-aarch64-apple-darwin"])]
 fn base_make_block(toolchain: &str) {
@@ -208,7 +297,9 @@ fn base_make_block(toolchain: &str) {
     assert!(base.image_inline.is_empty());
     assert_eq!(base.with_network, Network::None);
 
-    let res = base.make_block(toolchain, &[], &Add::default()).unwrap();
+    let res = base
+        .make_block(toolchain, &ToolchainFile::default(), &[], &[], &Add::default(), false)
+        .unwrap();
     assert_eq!(res.image, base_image);
     assert!(
         res.image_inline.contains(&format!(" {} ", base_image.noscheme())),
@@ -217,3 +308,197 @@ fn base_make_block(toolchain: &str) {
     );
     assert_eq!(res.with_network, Network::Default);
 }
+
+#[cfg(test)]
+#[test]
+fn base_make_block_also_run() {
+    let also_run = vec!["echo hi".to_owned(), "set -eux\ncd /tmp\ntrue".to_owned()];
+    let base = BaseImage { also_run: also_run.clone(), ..Default::default() };
+
+    let res = base
+        .make_block(
+            "1.80.0-x86_64-unknown-linux-gnu",
+            &ToolchainFile::default(),
+            &[],
+            &[],
+            &Add::default(),
+            false,
+        )
+        .unwrap();
+    assert_eq!(res.also_run, also_run);
+    assert!(
+        res.image_inline
+            .ends_with("\nRUN echo hi\nRUN <<ALSO_RUN\nset -eux\ncd /tmp\ntrue\nALSO_RUN\n"),
+        "In {}",
+        res.image_inline
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn base_make_block_toolchain_file() {
+    let toolchain_file = ToolchainFile {
+        channel: Some("1.80.0".to_owned()),
+        profile: Some("default".to_owned()),
+        components: vec!["rustfmt".to_owned(), "llvm-tools".to_owned()],
+        targets: vec!["wasm32-unknown-unknown".to_owned()],
+    };
+    let components = vec!["llvm-tools".to_owned(), "rust-src".to_owned()];
+
+    let res = BaseImage::default()
+        .make_block(
+            "1.80.0-x86_64-unknown-linux-gnu",
+            &toolchain_file,
+            &components,
+            &[],
+            &Add::default(),
+            false,
+        )
+        .unwrap();
+    assert!(
+        res.image_inline.contains(" --profile default --default-toolchain 1.80.0-x86_64-unknown-linux-gnu --default-host x86_64-unknown-linux-gnu --component llvm-tools,rust-src,rustfmt --target wasm32-unknown-unknown \\\n"),
+        "In {}",
+        res.image_inline
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn base_make_block_dists() {
+    let dists = vec![
+        Dist {
+            url: "https://static.rust-lang.org/dist/cargo.tar.xz".to_owned(),
+            sha256: "c4".to_owned(),
+        },
+        Dist {
+            url: "https://static.rust-lang.org/dist/rustc.tar.xz".to_owned(),
+            sha256: "c7".to_owned(),
+        },
+    ];
+    let res = BaseImage::default()
+        .make_block(
+            "1.80.0-x86_64-unknown-linux-gnu",
+            &ToolchainFile::default(),
+            &[],
+            &dists,
+            &Add::default(),
+            false,
+        )
+        .unwrap();
+    assert!(!res.image_inline.contains("rustup-init"), "In {}", res.image_inline);
+    assert!(
+        res.image_inline.contains(
+            "
+FROM scratch AS rustup-1.80.0-x86_64-unknown-linux-gnu
+ADD --unpack --checksum=sha256:c4 \\
+  https://static.rust-lang.org/dist/cargo.tar.xz /
+ADD --unpack --checksum=sha256:c7 \\
+  https://static.rust-lang.org/dist/rustc.tar.xz /
+FROM "
+        ),
+        "In {}",
+        res.image_inline
+    );
+    assert!(res.image_inline.contains("RUN \\\n  --network=none \\\n"), "In {}", res.image_inline);
+    // OS packages still need the network
+    assert_eq!(res.with_network, Network::Default);
+}
+
+#[cfg(test)]
+#[test]
+fn base_make_block_cross() {
+    let add = Add { apk: vec![], apt: vec!["libssl-dev".to_owned()] };
+
+    let res = BaseImage::default()
+        .make_block(
+            "1.80.0-x86_64-unknown-linux-gnu",
+            &ToolchainFile::default(),
+            &[],
+            &[],
+            &add,
+            true,
+        )
+        .unwrap();
+    assert!(
+        res.image_inline.contains(
+            "satisfy --no-install-recommends -y 'ca-certificates' 'clang' 'gcc' 'libc6-dev' 'lld'; \\\n"
+        ),
+        "In {}",
+        res.image_inline
+    );
+    assert!(
+        res.image_inline.contains(" && export TARGETPLATFORM=$BUILDPLATFORM \\\n"),
+        "In {}",
+        res.image_inline
+    );
+    assert!(
+        res.image_inline.contains(
+            "xx-apt-get install --no-install-recommends -y 'gcc' 'libc6-dev' 'libssl-dev'; \\\n"
+        ),
+        "In {}",
+        res.image_inline
+    );
+    assert!(
+        res.image_inline
+            .contains("xx-apk     add     --no-cache                 'gcc' 'musl-dev'; \\\n"),
+        "In {}",
+        res.image_inline
+    );
+    assert!(
+        res.image_inline.ends_with(
+            " && xx-clang --setup-target-triple \\\n && ln -s \"$(command -v \"$(xx-clang --print-target-triple)-clang\")\" /usr/local/bin/$(xx-cargo --print-target-triple)-clang\n"
+        ),
+        "In {}",
+        res.image_inline
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn base_make_block_cross_alpine() {
+    let add = Add { apk: vec!["openssl-dev".to_owned()], apt: vec![] };
+
+    let res = BaseImage::default()
+        .make_block(
+            "1.80.0-x86_64-unknown-linux-gnu",
+            &ToolchainFile::default(),
+            &[],
+            &[],
+            &add,
+            true,
+        )
+        .unwrap();
+    assert!(
+        res.image_inline.contains("xx-apk     add     --no-cache                 'ca-certificates' 'clang' 'gcc' 'lld'; \\\n"),
+        "In {}",
+        res.image_inline
+    );
+    assert!(
+        res.image_inline.contains(
+            "xx-apk     add     --no-cache                 'gcc' 'musl-dev' 'openssl-dev'; \\\n"
+        ),
+        "In {}",
+        res.image_inline
+    );
+}
+
+#[cfg(test)]
+#[test_case::test_case(&[" echo hi"], "whitespace"; "untrimmed")]
+#[test_case::test_case(&[""], "empty"; "empty")]
+#[test_case::test_case(&["echo hi", "echo hi"], "duplicates"; "duplicates")]
+#[test_case::test_case(&["cat <<ALSO_RUN\nhi\nALSO_RUN"], "ALSO_RUN"; "heredoc delimiter")]
+#[test_case::test_case(&["echo hi \\"], "continuation"; "continuation")]
+fn bad_also_run(field: &[&str], reason: &str) {
+    let field: Vec<_> = field.iter().map(ToString::to_string).collect();
+    let err = check_also_run(&field, "also-run").unwrap_err().to_string();
+    assert!(err.contains(reason), "In: {err}");
+}
+
+#[test]
+fn also_run_round_trips() {
+    let also_run = vec!["echo hi".to_owned(), "set -eux\ncd /tmp\necho 'a;b'".to_owned()];
+    let shown = show_also_run(&also_run);
+    assert!(!shown.contains('\n'), "In: {shown}");
+    assert_eq!(parse_also_run(&shown).unwrap(), also_run);
+    assert!(parse_also_run("echo hi").is_err());
+}
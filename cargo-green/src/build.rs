@@ -19,9 +19,9 @@ use indexmap::{IndexMap, IndexSet};
 use log::{debug, info, warn};
 use tokio::{
     fs::File,
-    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
     join,
-    process::{ChildStderr, ChildStdin, ChildStdout, Command},
+    process::Command,
     spawn,
     sync::oneshot::{self, Sender},
     time::{error::Elapsed, timeout},
@@ -31,6 +31,7 @@ use tokio_tar::{Archive as TarArchive, Entry as TarEntry, EntryType, Header as T
 
 use crate::{
     PKG,
+    backend::Building,
     base_image::un_rewrite_cargo_home,
     cache::result::{ResultWriter, assert_tarball_header, extract_just},
     dirs::Dirs,
@@ -38,9 +39,9 @@ use crate::{
     r#final::is_primary,
     green::Green,
     md::{BuildContext, DIESES},
+    progress::{self, Phase},
     rechrome,
     retrier::Retrier,
-    runner::Runner,
     stage::Stage,
     target_dir::un_virtual_target_dir_str,
 };
@@ -62,6 +63,20 @@ pub(crate) const STDOUT: &str = "stdout";
 // the timestamp is resolved from the metadata of files in the TAR archive or from the Last-Modified header of the URL #6602
 pub(crate) const SOURCE_DATE_EPOCH: u64 = 42;
 
+/// What to build, and where its result goes
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BuildRequest<'a> {
+    pub(crate) containerfile: &'a Utf8Path,
+    pub(crate) target: &'a Stage,
+    pub(crate) contexts: &'a IndexSet<BuildContext>,
+    /// Where to untar the result, if anything is to be exported at all
+    pub(crate) out_dir: Option<&'a Utf8Path>,
+    /// Where to export the runner's cache to
+    pub(crate) export: Option<&'a Utf8Path>,
+    /// Whether the runner shows its own progress UI
+    pub(crate) tui: bool,
+}
+
 impl Green {
     pub(crate) async fn build_cacheonly(
         &self,
@@ -129,6 +144,7 @@ impl Green {
         let _ = fwd_stdout(&out_buf, "➤", &self.cargo_home);
         let _ = fwd_stderr(&err_buf, "✖", &self.cargo_home);
         info!("reused {} files from {src}", written.len());
+        progress::report(Phase::Reused);
 
         if let Some(code) = errcode
             && code != 0
@@ -147,38 +163,26 @@ impl Green {
         export: Option<&Utf8Path>,
         tui: bool,
     ) -> (String, String, Effects, Option<ResultWriter>, Result<()>) {
-        assert!(!self.runner.is_none(), "build() called with Runner::None");
+        let req = BuildRequest { containerfile, target, contexts, out_dir, export, tui };
+        let backend = self.backend();
 
         let mut retrier = Retrier::with_max_attempts(5);
         loop {
-            let mut cmd = match self.cmd() {
-                Ok(cmd) => cmd,
+            let building = match backend.build(self, &req) {
+                Ok(building) => building,
                 Err(e) => return ("".to_owned(), "".to_owned(), Effects::default(), None, Err(e)),
             };
-            cmd.arg("build");
-
-            let (call, envs) = self.with_docker_args(
-                &mut cmd,
-                containerfile,
-                target,
-                contexts,
-                out_dir,
-                export,
-                tui,
-            );
+            let (call, envs) = (building.call.clone(), building.envs.clone());
 
             let mut effects = Effects::default();
-            let (status, result) = match self
-                .run_build(&mut effects, cmd, &call, containerfile, target, out_dir, tui)
-                .await
-            {
+            let (status, result) = match self.run_build(&mut effects, building, &req).await {
                 Ok((status, result)) => (status, result),
                 Err(e) => return (call, envs, effects, None, Err(e)),
             };
 
             // Something is very wrong here. Try to be helpful by logging some info about runner config:
             if !status.success() {
-                let (retryme, e) = effects.try_to_help(&self.runner, self.cargo_home.as_str());
+                let (retryme, e) = effects.try_to_help(self.cargo_home.as_str());
                 if retryme && retrier.continues() {
                     retrier.backoff("build", e).await;
                     continue;
@@ -191,18 +195,12 @@ impl Green {
         }
     }
 
-    #[expect(clippy::too_many_arguments)]
-    fn with_docker_args(
+    pub(crate) fn with_docker_args(
         &self,
         cmd: &mut Command,
-        containerfile: &Utf8Path,
-        target: &Stage,
-        contexts: &IndexSet<BuildContext>,
-        out_dir: Option<&Utf8Path>,
-        export: Option<&Utf8Path>,
-        tui: bool,
+        req: &BuildRequest<'_>,
     ) -> (String, String) {
-        //TODO: if allowing additional-build-arguments, deny: --build-arg=BUILDKIT_SYNTAX=
+        let BuildRequest { containerfile, target, contexts, out_dir, export, tui } = *req;
 
         if self.repro() {
             cmd.arg("--no-cache");
@@ -264,7 +262,7 @@ impl Green {
 
         cmd.arg(format!("--network={}", self.base.with_network));
 
-        cmd.arg("--platform=local");
+        cmd.arg(format!("--platform={}", self.platform()));
         cmd.arg("--pull=false");
         cmd.arg(format!("--target={target}"));
 
@@ -299,6 +297,8 @@ impl Green {
             cmd.arg(format!("--build-context={name}={uri}"));
         }
 
+        cmd.args(&self.additional_build_arguments);
+
         cmd.arg("-").stdin(Stdio::piped()); // Pass Dockerfile via STDIN, this way there's no default filesystem context.
         if out_dir.is_some() {
             cmd.stdout(Stdio::piped());
@@ -312,7 +312,9 @@ impl Green {
         let envs = cmd.envs_string(&self.runner.buildnoop_envs());
         if !tui {
             info!("Starting `{envs} {call} <{containerfile}`");
-            eprintln!("Starting `{envs} {call} <{containerfile}`");
+            if !progress::is_shown() {
+                eprintln!("Starting `{envs} {call} <{containerfile}`");
+            }
         }
         let call = call
             .split_whitespace()
@@ -329,45 +331,39 @@ impl Green {
         (call, envs)
     }
 
-    #[expect(clippy::too_many_arguments)]
     async fn run_build(
         &self,
         effects: &mut Effects,
-        mut cmd: Command,
-        call: &str,
-        containerfile: &Utf8Path,
-        target: &Stage,
-        out_dir: Option<&Utf8Path>,
-        tui: bool,
+        mut building: Building,
+        &BuildRequest { containerfile, target, out_dir, tui, .. }: &BuildRequest<'_>,
     ) -> Result<(ExitStatus, Option<ResultWriter>)> {
-        let start = Instant::now();
-        let mut child = cmd.spawn().map_err(|e| anyhow!("Failed starting `{call}`: {e}"))?;
+        let call = building.call.as_str();
 
-        spawn({
+        if let Some(stdin) = building.stdin.take() {
             let containerfile = containerfile.to_owned();
-            let stdin = child.stdin.take().expect("started");
-            async move { send_containerfile(stdin, containerfile).await }
-        });
+            spawn(async move { send_containerfile(stdin, containerfile).await });
+        }
 
         // ---
 
-        let pid = child.id().unwrap_or_default();
-        info!("Started as pid={pid} in {:?}", start.elapsed());
+        info!("Started as pid={}", building.pid);
 
         let (tx_err, mut rx_err) = oneshot::channel();
 
         let (handles, tee_err) = if let Some(out_dir) = out_dir {
+            progress::report(Phase::Building);
+
             let dbg_out = spawn({
                 let target = target.to_owned();
                 let out_dir = out_dir.to_owned();
                 let dirs = self.dirs.clone();
                 let cargo_home = self.cargo_home.to_string();
-                let stdout = child.stdout.take().expect("started");
+                let stdout = building.stdout.take().expect("started");
                 async move { build_stdout(stdout, target, out_dir, dirs, cargo_home).await }
             });
 
             let dbg_err = spawn({
-                let stderr = child.stderr.take().expect("started");
+                let stderr = building.stderr.take().expect("started");
                 async move { build_stderr(stderr, Some(tx_err)).await }
             });
 
@@ -376,7 +372,7 @@ impl Green {
             (None, None) // stdio inherited
         } else {
             let tee_err = spawn({
-                let stderr = child.stderr.take().expect("started");
+                let stderr = building.stderr.take().expect("started");
                 async move { tee_stderr(stderr).await }
             });
 
@@ -393,7 +389,7 @@ impl Green {
 
         let (secs, res) = {
             let start = Instant::now();
-            let res = child.wait().await;
+            let res = building.status.await;
             (start.elapsed(), res)
         };
         let status = res.map_err(|e| anyhow!("Failed calling `{call}`: {e}"))?;
@@ -413,7 +409,6 @@ impl Green {
         let Some((dbg_out, dbg_err)) = handles else {
             let Some(tee_err) = tee_err else { return Ok((status, None)) };
             let joined = timeout(SOME_TIME, tee_err).await;
-            drop(child);
             match joined {
                 Ok(Ok(err_buf)) => {
                     // Keep STDERR around so Effects::try_to_help can match on errors even for cacheonly
@@ -426,7 +421,6 @@ impl Green {
             return Ok((status, None));
         };
         let joined = join!(timeout(SOME_TIME, dbg_out), timeout(SOME_TIME, dbg_err));
-        drop(child);
 
         match joined {
             (Err(Elapsed { .. }), _) | (_, Err(Elapsed { .. })) => {
@@ -467,7 +461,7 @@ pub(crate) struct Effects {
 }
 
 impl Effects {
-    fn try_to_help(&self, runner: &Runner, cargo_home: &str) -> (bool, Error) {
+    fn try_to_help(&self, cargo_home: &str) -> (bool, Error) {
         let rewrite = |msg: &str| {
             let msg = un_virtual_target_dir_str(msg);
             un_rewrite_cargo_home(&msg, cargo_home)
@@ -514,11 +508,8 @@ impl Effects {
             false,
             anyhow!(
                 "Runner failed.{logs}\n{stdout}\n{stderr}\n
-Please report an issue along with information from the following:
-* {runner} buildx version
-* {runner} info
-* {runner} buildx ls
-* cargo green supergreen env
+Please report an issue along with the output of:
+    cargo green supergreen doctor --bundle
 ",
                 stdout = self.stdout.iter().map(|x| rewrite(x)).collect::<Vec<_>>().join("\n"),
                 stderr = self.stderr.iter().map(|x| rewrite(x)).collect::<Vec<_>>().join("\n"),
@@ -527,7 +518,10 @@ Please report an issue along with information from the following:
     }
 }
 
-async fn send_containerfile(mut stdin: ChildStdin, containerfile: Utf8PathBuf) -> Result<()> {
+async fn send_containerfile(
+    mut stdin: impl AsyncWrite + Unpin,
+    containerfile: Utf8PathBuf,
+) -> Result<()> {
     let reader = File::open(&containerfile)
         .await
         .map_err(|e| anyhow!("Failed opening (RO) {containerfile}: {e}"))?;
@@ -546,7 +540,7 @@ async fn send_containerfile(mut stdin: ChildStdin, containerfile: Utf8PathBuf) -
 }
 
 async fn build_stdout(
-    stdout: ChildStdout,
+    stdout: impl AsyncRead + Unpin,
     target: Stage,
     out_dir: Utf8PathBuf,
     dirs: Option<Dirs>,
@@ -560,6 +554,7 @@ async fn build_stdout(
         .read_to_end(&mut buf)
         .await
         .map_err(|e| anyhow!("Failed getting all the buffer: {e}"))?;
+    progress::report(Phase::Untarring);
     debug!("produced {target} {}B 0x{}", buf.len(), sha256::digest(&buf));
     if let Some(ref mut result) = result {
         result.add_tarball(&buf).await?;
@@ -697,12 +692,16 @@ fn strip_ansi_escapes(line: &str) -> String {
         .replace("\\u001b[38;5;9m", "")
 }
 
-async fn build_stderr(stderr: ChildStderr, mut tx_err: Option<Sender<String>>) -> Result<()> {
+async fn build_stderr(
+    stderr: impl AsyncRead + Unpin,
+    mut tx_err: Option<Sender<String>>,
+) -> Result<()> {
     let mut lines = BufReader::new(stderr).lines();
 
     let mut details: BTreeMap<String, String> = [].into();
     let mut dones = 0;
     let mut cacheds = 0;
+    let mut phase = Phase::Building;
     while let Ok(Some(line)) = lines.next_line().await {
         let line = strip_ansi_escapes(&line);
         if line.is_empty() {
@@ -710,6 +709,13 @@ async fn build_stderr(stderr: ChildStderr, mut tx_err: Option<Sender<String>>) -
         }
         info!("✖ {line}");
 
+        if let Some(new) = progress::phase_of(&line)
+            && new != phase
+        {
+            phase = new;
+            progress::report(phase);
+        }
+
         // Capture some approximate stats the runner gives us
 
         if line.starts_with("ERROR: ")
@@ -740,7 +746,7 @@ async fn build_stderr(stderr: ChildStderr, mut tx_err: Option<Sender<String>>) -
 }
 
 /// Keep a ~1MB rolling text buffer of stderr for try_to_help
-async fn tee_stderr(stderr: ChildStderr) -> String {
+async fn tee_stderr(stderr: impl AsyncRead + Unpin) -> String {
     let mut lines = BufReader::new(stderr).lines();
     let mut ring = VecDeque::new();
     while let Ok(Some(line)) = lines.next_line().await {
@@ -0,0 +1,283 @@
+//! Builds by calling BuildKit's command-line client, `buildctl`, once per crate: it speaks
+//! the daemon's control API (Solve, Status, DiskUsage, Prune, ..) over `$BUILDKIT_HOST`.
+//!
+//! <https://github.com/moby/buildkit#quick-start>
+
+use anyhow::{Result, bail};
+use log::info;
+use tokio::process::Command;
+
+use crate::{
+    build::BuildRequest,
+    ext::CommandExt,
+    green::Green,
+    md::BuildContext,
+    network::Network,
+    progress,
+    runner::{BUILDKIT_HOST, DOCKER_CERT_PATH},
+};
+
+impl Green {
+    /// Flags that come before `buildctl`'s subcommand
+    pub(crate) fn buildctl_global_args(&self, cmd: &mut Command) {
+        // Same file names as `$DOCKER_CERT_PATH`: ca.pem, cert.pem & key.pem
+        if let Some(dir) = self.runner_envs.get(DOCKER_CERT_PATH) {
+            cmd.arg(format!("--tlsdir={dir}"));
+        }
+    }
+
+    /// The `buildctl build` equivalent of [`Self::with_docker_args`]
+    pub(crate) fn with_buildctl_args(
+        &self,
+        cmd: &mut Command,
+        req: &BuildRequest<'_>,
+    ) -> (String, String) {
+        let BuildRequest { containerfile, target, contexts, out_dir, export, tui } = *req;
+
+        cmd.arg("--frontend=dockerfile.v0");
+        cmd.arg(format!("--progress={}", if tui { "auto" } else { "plain" }));
+
+        if self.repro() {
+            cmd.arg("--no-cache");
+        }
+
+        for img in self.cache.from_images.iter().chain(self.cache.images.iter()) {
+            let img = img.noscheme();
+            cmd.arg(format!("--import-cache=type=registry,ref={img}"));
+        }
+        for img in self.cache.to_images.iter().chain(self.cache.images.iter()) {
+            let img = img.noscheme();
+            cmd.arg(format!("--export-cache=type=registry,ref={img},mode=max,ignore-error=false"));
+        }
+
+        match self.base.with_network {
+            Network::Default => {}
+            Network::None => {
+                cmd.arg("--opt=force-network-mode=none");
+            }
+            Network::Host => {
+                cmd.arg("--opt=force-network-mode=host").arg("--allow=network.host");
+            }
+        }
+
+        let platform = self.platform();
+        if platform != "local" {
+            cmd.arg(format!("--opt=platform={platform}"));
+        }
+        cmd.arg(format!("--opt=target={target}"));
+
+        // No reading a Dockerfile from STDIN here: the frontend only fetches this one file.
+        let dir = containerfile.parent().unwrap_or(containerfile);
+        let filename = containerfile.file_name().unwrap_or_default();
+        cmd.arg(format!("--local=dockerfile={dir}"));
+        cmd.arg(format!("--opt=filename={filename}"));
+
+        for BuildContext { name, uri } in contexts {
+            cmd.arg(format!("--local={name}={uri}"));
+            cmd.arg(format!("--opt=context:{name}=local:{name}"));
+        }
+
+        if out_dir.is_some() {
+            cmd.arg("--output=type=tar,dest=-");
+        }
+        // else: no exporter, only filling the cache
+
+        if let Some(dst) = export {
+            cmd.arg(buildctl_cache_arg(&self.builder.export_arg(dst)));
+        }
+        if let Some(ref dirs) = self.dirs
+            && self.cachebuildkit()
+            && let Some(src) = dirs.runner_cache(target)
+        {
+            cmd.arg(buildctl_cache_arg(&self.builder.import_arg(&src)));
+        }
+
+        cmd.args(buildctl_build_args(&self.additional_build_arguments));
+
+        if out_dir.is_some() {
+            cmd.stdout(std::process::Stdio::piped());
+            cmd.stderr(std::process::Stdio::piped());
+        } else if !tui {
+            cmd.stderr(std::process::Stdio::piped());
+        }
+
+        let call = cmd.show();
+        let envs = cmd.envs_string(&self.runner.buildnoop_envs());
+        if !tui {
+            info!("Starting `{envs} {call}`");
+            if !progress::is_shown() {
+                eprintln!("Starting `{envs} {call}`");
+            }
+        }
+        let call = call
+            .split_whitespace()
+            .filter(|flag| {
+                !flag.starts_with("--import-cache=") && !flag.starts_with("--export-cache=")
+            })
+            .filter(|flag| !flag.starts_with("--opt=target=") && *flag != "--no-cache")
+            .map(|flag| match flag {
+                _ if flag.starts_with("--output=") => "--output=type=local,dest=.",
+                _ if flag.starts_with("--local=dockerfile=") => "--local=dockerfile=.",
+                _ if flag.starts_with("--opt=filename=") => "--opt=filename=THIS_FILE",
+                _ => flag,
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace(cmd.as_std().get_program().to_str().unwrap(), "buildctl");
+
+        (call, envs)
+    }
+}
+
+/// Translates buildx's `--cache-from=`/`--cache-to=` into `buildctl`'s flags
+fn buildctl_cache_arg(arg: &str) -> String {
+    if let Some(rhs) = arg.strip_prefix("--cache-from=") {
+        return format!("--import-cache={rhs}");
+    }
+    if let Some(rhs) = arg.strip_prefix("--cache-to=") {
+        return format!("--export-cache={rhs}");
+    }
+    arg.to_owned()
+}
+
+/// Translates buildx flags of `additional-build-arguments` into `buildctl build`'s.
+///
+/// `--secret`, `--ssh` and `--allow` are spelled the same.
+fn buildctl_build_args(args: &[String]) -> Vec<String> {
+    let (mut translated, mut hosts, mut ulimits) = (vec![], vec![], vec![]);
+    for arg in args {
+        if let Some(host) = arg.strip_prefix("--add-host=") {
+            // buildx accepts HOST:IP, the frontend only HOST=IP
+            hosts.push(if host.contains('=') {
+                host.to_owned()
+            } else {
+                host.replacen(':', "=", 1)
+            });
+        } else if let Some(ulimit) = arg.strip_prefix("--ulimit=") {
+            ulimits.push(ulimit);
+        } else if let Some((name, src)) =
+            arg.strip_prefix("--build-context=").and_then(|ctx| ctx.split_once('='))
+        {
+            if src.contains("://") {
+                translated.push(format!("--opt=context:{name}={src}"));
+            } else {
+                translated.push(format!("--local={name}={src}"));
+                translated.push(format!("--opt=context:{name}=local:{name}"));
+            }
+        } else {
+            translated.push(arg.clone());
+        }
+    }
+    // The frontend reads these as comma-separated lists
+    if !hosts.is_empty() {
+        translated.push(format!("--opt=add-hosts={}", hosts.join(",")));
+    }
+    if !ulimits.is_empty() {
+        translated.push(format!("--opt=ulimit={}", ulimits.join(",")));
+    }
+    translated
+}
+
+/// `buildctl` needs to know where the daemon listens.
+pub(crate) fn check_buildkit_host(green: &Green) -> Result<()> {
+    if !green.runner_envs.contains_key(BUILDKIT_HOST) {
+        bail!(
+            "Runner {} requires ${BUILDKIT_HOST} (e.g. unix:///run/buildkit/buildkitd.sock, tcp://host:1234 or docker-container://buildkitd)",
+            green.runner
+        )
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        image_uri::ImageUri,
+        runner::Runner,
+        stage::{RUST, Stage},
+    };
+
+    #[test]
+    fn buildctl_args() {
+        let mut green = Green { runner: Runner::Buildkitd, ..Default::default() };
+        green.cache.images = vec![ImageUri::try_new("docker-image://my.org/team/cache").unwrap()];
+        green.base.with_network = Network::None;
+
+        let name = Stage::new("input_src").unwrap();
+        let contexts = [BuildContext { name, uri: "/some/src".into() }].into();
+
+        let mut cmd = Command::new("buildctl");
+        cmd.arg("build");
+        let req = BuildRequest {
+            containerfile: "/target/debug/x.Dockerfile".into(),
+            target: &RUST,
+            contexts: &contexts,
+            out_dir: Some("/target/debug/deps".into()),
+            export: None,
+            tui: false,
+        };
+        let (call, _) = green.with_buildctl_args(&mut cmd, &req);
+
+        let args: Vec<_> = cmd.as_std().get_args().map(|arg| arg.to_str().unwrap()).collect();
+        pretty_assertions::assert_eq!(
+            args,
+            [
+                "build",
+                "--frontend=dockerfile.v0",
+                "--progress=plain",
+                "--import-cache=type=registry,ref=my.org/team/cache",
+                "--export-cache=type=registry,ref=my.org/team/cache,mode=max,ignore-error=false",
+                "--opt=force-network-mode=none",
+                "--opt=target=rust-base",
+                "--local=dockerfile=/target/debug",
+                "--opt=filename=x.Dockerfile",
+                "--local=input_src=/some/src",
+                "--opt=context:input_src=local:input_src",
+                "--output=type=tar,dest=-",
+            ]
+        );
+        assert_eq!(
+            call,
+            "buildctl build --frontend=dockerfile.v0 --progress=plain --opt=force-network-mode=none --local=dockerfile=. --opt=filename=THIS_FILE --local=input_src=/some/src --opt=context:input_src=local:input_src --output=type=local,dest=."
+        );
+    }
+
+    #[test]
+    fn build_args() {
+        let args = [
+            "--secret=id=aws,src=/root/.aws/credentials",
+            "--add-host=my.host:10.0.0.2",
+            "--build-context=vendored=/some/dir",
+            "--ulimit=nofile=1024:1024",
+            "--build-context=alpine=docker-image://docker.io/library/alpine:3",
+            "--add-host=other=::1",
+            "--allow=security.insecure",
+        ]
+        .map(ToOwned::to_owned);
+        pretty_assertions::assert_eq!(
+            buildctl_build_args(&args),
+            [
+                "--secret=id=aws,src=/root/.aws/credentials",
+                "--local=vendored=/some/dir",
+                "--opt=context:vendored=local:vendored",
+                "--opt=context:alpine=docker-image://docker.io/library/alpine:3",
+                "--allow=security.insecure",
+                "--opt=add-hosts=my.host=10.0.0.2,other=::1",
+                "--opt=ulimit=nofile=1024:1024",
+            ]
+        );
+    }
+
+    #[test]
+    fn cache_args() {
+        assert_eq!(
+            buildctl_cache_arg("--cache-from=type=local,src=/a"),
+            "--import-cache=type=local,src=/a"
+        );
+        assert_eq!(
+            buildctl_cache_arg("--cache-to=type=local,dest=/a"),
+            "--export-cache=type=local,dest=/a"
+        );
+    }
+}
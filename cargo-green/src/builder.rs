@@ -1,9 +1,9 @@
-use std::{fs, str::FromStr, sync::LazyLock, time::Duration};
+use std::{collections::BTreeMap, fmt, fs, str::FromStr, sync::LazyLock, time::Duration};
 
 use anyhow::{Result, anyhow, bail};
 use camino::Utf8PathBuf;
-use indexmap::IndexSet;
-use log::info;
+use indexmap::{IndexMap, IndexSet};
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use version_compare::Version;
 
@@ -11,7 +11,8 @@ use crate::{
     buildkitd,
     ext::CommandExt,
     green::Green,
-    image_uri::{ImageUri, fetch_digest},
+    image_uri::{BAD_CHARS, ImageUri},
+    remotes::Remote,
     retrier::Retrier,
     tmp,
 };
@@ -28,22 +29,81 @@ macro_rules! ENV_BUILDER_IMAGE {
     };
 }
 
+macro_rules! ENV_BUILDER_DRIVER {
+    () => {
+        "CARGOGREEN_BUILDER_DRIVER"
+    };
+}
+
+macro_rules! ENV_REMOTES {
+    () => {
+        "CARGOGREEN_REMOTES"
+    };
+}
+
+macro_rules! ENV_BUILDER_MAX_PARALLELISM {
+    () => {
+        "CARGOGREEN_BUILDER_MAX_PARALLELISM"
+    };
+}
+
+macro_rules! ENV_BUILDER_GC_KEEP_STORAGE {
+    () => {
+        "CARGOGREEN_BUILDER_GC_KEEP_STORAGE"
+    };
+}
+
+macro_rules! ENV_BUILDER_GC_POLICIES {
+    () => {
+        "CARGOGREEN_BUILDER_GC_POLICIES"
+    };
+}
+
+macro_rules! ENV_BUILDER_ENTITLEMENTS {
+    () => {
+        "CARGOGREEN_BUILDER_ENTITLEMENTS"
+    };
+}
+
+macro_rules! ENV_REGISTRIES {
+    () => {
+        "CARGOGREEN_REGISTRIES"
+    };
+}
+
 const BUILDX_BUILDER: &str = BUILDX_BUILDER!();
 const ENV_BUILDER_IMAGE: &str = ENV_BUILDER_IMAGE!();
+const ENV_BUILDER_DRIVER: &str = ENV_BUILDER_DRIVER!();
+const ENV_REMOTES: &str = ENV_REMOTES!();
+const ENV_BUILDER_MAX_PARALLELISM: &str = ENV_BUILDER_MAX_PARALLELISM!();
+const ENV_BUILDER_GC_KEEP_STORAGE: &str = ENV_BUILDER_GC_KEEP_STORAGE!();
+const ENV_BUILDER_GC_POLICIES: &str = ENV_BUILDER_GC_POLICIES!();
+const ENV_BUILDER_ENTITLEMENTS: &str = ENV_BUILDER_ENTITLEMENTS!();
+const ENV_REGISTRIES: &str = ENV_REGISTRIES!();
 
 /// TODO: move to `:rootless`
-static BUILDKIT_IMAGE: LazyLock<ImageUri> =
+pub(crate) static BUILDKIT_IMAGE: LazyLock<ImageUri> =
     LazyLock::new(|| ImageUri::try_new("docker-image://docker.io/moby/buildkit:latest").unwrap());
 
+/// For `kubernetes` builders, which run without privileges
+static BUILDKIT_ROOTLESS_IMAGE: LazyLock<ImageUri> =
+    LazyLock::new(|| ImageUri::try_new("docker-image://docker.io/moby/buildkit:rootless").unwrap());
+
 /// <https://docs.docker.com/build/builders/drivers/docker-container/#qemu>
 ///
 /// <https://docs.docker.com/build/cache/backends/>
 const BUILDER_DRIVER: &str = "docker-container";
 
+/// Worker label holding a digest of the `buildkitd.toml` settings a builder got created with
+const CONFIG_LABEL: &str = "org.supergreen.config";
+
+/// <https://docs.docker.com/reference/cli/docker/buildx/build/#allow>
+const ENTITLEMENTS: &[&str] = &["network.host", "security.insecure", "device"];
+
 /// Not a Release Candidate
 ///
 /// <https://github.com/moby/buildkit/tags>
-static LATEST_BUILDKIT: LazyLock<Version> =
+pub(crate) static LATEST_BUILDKIT: LazyLock<Version> =
     LazyLock::new(|| Version::from(include_str!("../latest_buildkit.txt").trim()).unwrap());
 
 #[test]
@@ -65,18 +125,28 @@ pub(crate) struct Builder {
     #[serde(rename = "builder-image")]
     pub(crate) image: Option<ImageUri>,
 
-    /// Shows which driver the configured builder uses.
-    ///
-    /// Defaults to [BUILDER_DRIVER].
+    #[doc = include_str!(concat!("../docs/",ENV_REMOTES!(),".md"))]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(rename = "remotes")]
+    pub(crate) remotes: Vec<Remote>,
+
+    #[doc = include_str!(concat!("../docs/",ENV_BUILDER_DRIVER!(),".md"))]
     ///
-    /// See <https://docs.docker.com/build/drivers/>
-    /// * <https://docs.docker.com/build/drivers/docker-container/>
-    /// * <https://docs.docker.com/build/drivers/remote/>
-    /// * <https://docs.docker.com/build/drivers/kubernetes/>
+    /// When unset, shows which driver the configured builder uses.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "builder-driver")]
     pub(crate) driver: Option<Driver>,
 
+    /// BuildKit worker tuning
+    #[serde(skip_serializing_if = "Tuning::is_empty")]
+    #[serde(rename = "builder")]
+    pub(crate) tuning: Tuning,
+
+    #[doc = include_str!(concat!("../docs/",ENV_REGISTRIES!(),".md"))]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    #[serde(rename = "registries")]
+    pub(crate) registries: IndexMap<String, buildkitd::Registry>,
+
     /// Builder container ID
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "id")]
@@ -88,22 +158,406 @@ pub(crate) struct Builder {
     pub(crate) data: Option<Utf8PathBuf>,
 }
 
+/// The `[package.metadata.green.builder]` table, mirroring `$CARGOGREEN_BUILDER_*`
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Tuning {
+    #[doc = include_str!(concat!("../docs/",ENV_BUILDER_MAX_PARALLELISM!(),".md"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_parallelism: Option<u8>,
+
+    #[doc = include_str!(concat!("../docs/",ENV_BUILDER_GC_KEEP_STORAGE!(),".md"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) gc_keep_storage: Option<String>,
+
+    #[doc = include_str!(concat!("../docs/",ENV_BUILDER_GC_POLICIES!(),".md"))]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) gc_policies: Vec<GcPolicy>,
+
+    #[doc = include_str!(concat!("../docs/",ENV_BUILDER_ENTITLEMENTS!(),".md"))]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) entitlements: Vec<String>,
+}
+
+impl Tuning {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 impl Builder {
     pub(crate) fn is_default(&self) -> bool {
         self.driver.as_ref().is_none_or(|d| *d == Driver::Docker)
     }
+
+    /// Whether there is a local `buildx_buildkit_<name>0` container to inspect
+    pub(crate) fn has_container(&self) -> bool {
+        self.remotes.is_empty() && matches!(self.driver, Some(Driver::DockerContainer))
+    }
+
+    /// Settings that are set and end up in the builder's `buildkitd.toml` (or flags)
+    fn configured_by(&self) -> Vec<&'static str> {
+        [
+            (self.tuning.max_parallelism.is_some(), ENV_BUILDER_MAX_PARALLELISM),
+            (self.tuning.gc_keep_storage.is_some(), ENV_BUILDER_GC_KEEP_STORAGE),
+            (!self.tuning.gc_policies.is_empty(), ENV_BUILDER_GC_POLICIES),
+            (!self.tuning.entitlements.is_empty(), ENV_BUILDER_ENTITLEMENTS),
+            (!self.registries.is_empty(), ENV_REGISTRIES),
+        ]
+        .into_iter()
+        .filter_map(|(set, var)| set.then_some(var))
+        .collect()
+    }
+
+    /// Sums up these settings, if any: a builder created with other settings gets this digest wrong
+    fn config_digest(&self) -> Option<String> {
+        if self.configured_by().is_empty() {
+            return None;
+        }
+        let config = (
+            self.tuning.max_parallelism,
+            &self.tuning.gc_keep_storage,
+            &self.tuning.gc_policies,
+            &self.tuning.entitlements,
+            &self.registries,
+        );
+        let config = serde_json::to_string(&config).expect("serializable");
+        Some(format!("{:08x}", crc32fast::hash(config.as_bytes())))
+    }
+
+    /// Applies worker tuning and registries settings to a `buildkitd.toml`
+    fn configure(&self, config: &mut buildkitd::Config) {
+        let Some(digest) = self.config_digest() else { return };
+        for (host, registry) in &self.registries {
+            let buildkitd::Registry { mirrors, ca, http, insecure } = registry.clone();
+            let entry = config.registry.entry(host.to_owned()).or_default();
+            entry.mirrors.extend(mirrors);
+            entry.ca = ca;
+            entry.http = http;
+            entry.insecure = insecure;
+        }
+        let worker = config.worker.entry("oci".to_owned()).or_default();
+        worker.max_parallelism = self.tuning.max_parallelism;
+        if self.tuning.gc_keep_storage.is_some() || !self.tuning.gc_policies.is_empty() {
+            worker.gc = Some(true);
+        }
+        worker.gckeepstorage = self.tuning.gc_keep_storage.clone();
+        worker.gcpolicy = self.tuning.gc_policies.iter().map(Into::into).collect();
+        worker.labels.insert(CONFIG_LABEL.to_owned(), digest);
+    }
+
+    /// Checks values of settings that end up in `buildkitd.toml`, whichever layer they come from.
+    pub(crate) fn check_config(&self, origin: impl Fn(&'static str) -> String) -> Result<()> {
+        if self.tuning.max_parallelism == Some(0) {
+            bail!("{} must be positive", origin(ENV_BUILDER_MAX_PARALLELISM))
+        }
+
+        if let Some(ref space) = self.tuning.gc_keep_storage {
+            check_disk_space(space)
+                .map_err(|e| anyhow!("{} {e}", origin(ENV_BUILDER_GC_KEEP_STORAGE)))?;
+        }
+
+        for policy in &self.tuning.gc_policies {
+            policy.check().map_err(|e| anyhow!("{} {e}", origin(ENV_BUILDER_GC_POLICIES)))?;
+        }
+
+        let at = origin(ENV_BUILDER_ENTITLEMENTS);
+        if let Some(bad) =
+            self.tuning.entitlements.iter().find(|e| !ENTITLEMENTS.contains(&e.as_str()))
+        {
+            bail!("{at} only accepts {} (got {bad:?})", ENTITLEMENTS.join(", "))
+        }
+        if self.tuning.entitlements.len()
+            != self.tuning.entitlements.iter().collect::<IndexSet<_>>().len()
+        {
+            bail!("{at} contains duplicates")
+        }
+
+        for (host, registry) in &self.registries {
+            check_registry(host, registry)
+                .map_err(|e| anyhow!("{} {e}", origin(ENV_REGISTRIES)))?;
+        }
+        Ok(())
+    }
+}
+
+/// A BuildKit garbage collection policy, see <https://docs.docker.com/build/cache/garbage-collection/>
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct GcPolicy {
+    /// Also prunes cache that is still referenced
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) all: bool,
+
+    /// Only prunes matching records, e.g. `"type==source.local"`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) filters: Vec<String>,
+
+    /// Only prunes records unused for this long, e.g. `"48h"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) keep_duration: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reserved_space: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_used_space: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) min_free_space: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+impl From<&GcPolicy> for buildkitd::GcPolicy {
+    fn from(policy: &GcPolicy) -> Self {
+        let GcPolicy {
+            all,
+            filters,
+            keep_duration,
+            reserved_space,
+            max_used_space,
+            min_free_space,
+        } = policy.clone();
+        Self { all, filters, keep_duration, reserved_space, max_used_space, min_free_space }
+    }
+}
+
+impl GcPolicy {
+    fn spaces(&self) -> [(&'static str, &Option<String>); 3] {
+        [
+            ("reserved-space", &self.reserved_space),
+            ("max-used-space", &self.max_used_space),
+            ("min-free-space", &self.min_free_space),
+        ]
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.keep_duration.is_none() && self.spaces().iter().all(|(_, v)| v.is_none()) {
+            bail!("has a policy that sets no limit: {self}")
+        }
+        if self.filters.iter().any(|filter| filter.trim().is_empty()) {
+            bail!("has a policy with an empty filter: {self}")
+        }
+        if let Some(ref duration) = self.keep_duration {
+            check_duration(duration)?;
+        }
+        for (_, space) in self.spaces() {
+            if let Some(space) = space {
+                check_disk_space(space)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for GcPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut kvs = vec![];
+        if self.all {
+            kvs.push("all=true".to_owned());
+        }
+        kvs.extend(self.filters.iter().map(|filter| format!("filter={filter}")));
+        if let Some(ref duration) = self.keep_duration {
+            kvs.push(format!("keep-duration={duration}"));
+        }
+        for (k, v) in self.spaces() {
+            if let Some(v) = v {
+                kvs.push(format!("{k}={v}"));
+            }
+        }
+        write!(f, "{}", kvs.join(","))
+    }
+}
+
+impl FromStr for GcPolicy {
+    type Err = anyhow::Error;
+
+    /// Comma-separated `key=value`s
+    fn from_str(s: &str) -> Result<Self> {
+        let mut policy = Self::default();
+        for kv in s.split(',').map(str::trim) {
+            let Some((k, v)) = kv.split_once('=') else { bail!("Expected key=value, got {kv:?}") };
+            let (k, v) = (k.trim(), v.trim());
+            if v.is_empty() {
+                bail!("Empty value for {k:?}")
+            }
+            match k {
+                "all" => {
+                    policy.all = v.parse().map_err(|_| anyhow!("all must be true or false"))?
+                }
+                "filter" => policy.filters.push(v.to_owned()),
+                "keep-duration" => policy.keep_duration = Some(v.to_owned()),
+                "reserved-space" => policy.reserved_space = Some(v.to_owned()),
+                "max-used-space" => policy.max_used_space = Some(v.to_owned()),
+                "min-free-space" => policy.min_free_space = Some(v.to_owned()),
+                _ => bail!("Unexpected key {k:?}"),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// Reads `;`-separated GC policies
+pub(crate) fn parse_gc_policies(s: &str) -> Result<Vec<GcPolicy>> {
+    let policies = s
+        .split(';')
+        .map(str::trim)
+        .filter(|policy| !policy.is_empty())
+        .map(|policy| policy.parse().map_err(|e| anyhow!("Bad policy {policy:?}: {e}")))
+        .collect::<Result<Vec<GcPolicy>>>()?;
+    if policies.is_empty() {
+        bail!("No policies given")
+    }
+    Ok(policies)
+}
+
+/// Reads `;`-separated registries, each comma-separated `key=value`s
+pub(crate) fn parse_registries(s: &str) -> Result<IndexMap<String, buildkitd::Registry>> {
+    let mut registries = IndexMap::new();
+    for settings in s.split(';').map(str::trim).filter(|settings| !settings.is_empty()) {
+        let mut host = None;
+        let mut registry = buildkitd::Registry::default();
+        for kv in settings.split(',').map(str::trim) {
+            let Some((k, v)) = kv.split_once('=') else {
+                bail!("Bad registry {settings:?}: expected key=value, got {kv:?}")
+            };
+            let (k, v) = (k.trim(), v.trim());
+            if v.is_empty() {
+                bail!("Bad registry {settings:?}: empty value for {k:?}")
+            }
+            let flag = |v: &str| {
+                v.parse()
+                    .map_err(|_| anyhow!("Bad registry {settings:?}: {k} must be true or false"))
+            };
+            match k {
+                "host" => host = Some(v.to_owned()),
+                "mirror" => registry.mirrors.push(v.to_owned()),
+                "ca" => registry.ca.push(v.into()),
+                "http" => registry.http = flag(v)?,
+                "insecure" => registry.insecure = flag(v)?,
+                _ => bail!("Bad registry {settings:?}: unexpected key {k:?}"),
+            }
+        }
+        let Some(host) = host else { bail!("Bad registry {settings:?}: missing host") };
+        if registries.insert(host.clone(), registry).is_some() {
+            bail!("Registry {host:?} is given more than once")
+        }
+    }
+    if registries.is_empty() {
+        bail!("No registries given")
+    }
+    Ok(registries)
+}
+
+/// The `;`-separated form of `registries`, see [`parse_registries`]
+pub(crate) fn show_registries(registries: &IndexMap<String, buildkitd::Registry>) -> String {
+    let show = |(host, registry): (&String, &buildkitd::Registry)| {
+        let buildkitd::Registry { mirrors, ca, http, insecure } = registry;
+        let mut kvs = vec![format!("host={host}")];
+        kvs.extend(mirrors.iter().map(|mirror| format!("mirror={mirror}")));
+        kvs.extend(ca.iter().map(|ca| format!("ca={ca}")));
+        if *http {
+            kvs.push("http=true".to_owned());
+        }
+        if *insecure {
+            kvs.push("insecure=true".to_owned());
+        }
+        kvs.join(",")
+    };
+    registries.iter().map(show).collect::<Vec<_>>().join(";")
+}
+
+fn check_registry(host: &str, registry: &buildkitd::Registry) -> Result<()> {
+    if host.is_empty() || host.contains("://") || host.contains(BAD_CHARS) {
+        bail!("has a bad registry host {host:?}: expected a host, maybe with a port")
+    }
+    if *registry == buildkitd::Registry::default() {
+        bail!("sets nothing for registry {host:?}")
+    }
+    if let Some(mirror) = registry
+        .mirrors
+        .iter()
+        .find(|mirror| mirror.is_empty() || mirror.contains("://") || mirror.contains(BAD_CHARS))
+    {
+        bail!("has a bad mirror for registry {host:?}: {mirror:?}")
+    }
+    if let Some(ca) = registry.ca.iter().find(|ca| !ca.is_absolute()) {
+        bail!("has a relative CA certificate path for registry {host:?}: {ca}")
+    }
+    Ok(())
+}
+
+/// A size in bytes, as BuildKit reads them (`"512MB"`, `"20GB"`, `"1.5t"`), or a percentage of disk
+fn check_disk_space(space: &str) -> Result<()> {
+    let bad = || {
+        anyhow!(r#"must be a size such as "20GB", or a percentage such as "10%" (got {space:?})"#)
+    };
+    if let Some(pct) = space.strip_suffix('%') {
+        return pct
+            .parse::<u8>()
+            .ok()
+            .filter(|pct| (1..=100).contains(pct))
+            .map(|_| ())
+            .ok_or_else(bad);
+    }
+    let unit = space.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let number = &space[..space.len() - unit.len()];
+    if number.parse::<f64>().is_err() {
+        return Err(bad());
+    }
+    let unit = unit.trim_start().to_lowercase();
+    let unit = unit.strip_prefix(['k', 'm', 'g', 't', 'p']).unwrap_or(&unit);
+    let unit = unit.strip_prefix('i').unwrap_or(unit);
+    if !["", "b"].contains(&unit) {
+        return Err(bad());
+    }
+    Ok(())
+}
+
+/// A duration, as Go reads them (`"48h"`, `"1h30m"`)
+fn check_duration(duration: &str) -> Result<()> {
+    let bad = || anyhow!(r#"must be a duration such as "48h" or "1h30m" (got {duration:?})"#);
+    if duration.is_empty() {
+        return Err(bad());
+    }
+    let mut rest = duration;
+    while !rest.is_empty() {
+        let unit = rest.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+        if unit.len() == rest.len() || rest[..rest.len() - unit.len()].parse::<f64>().is_err() {
+            return Err(bad());
+        }
+        let end = unit.find(|c: char| c.is_ascii_digit()).unwrap_or(unit.len());
+        if !["ns", "us", "µs", "ms", "s", "m", "h"].contains(&&unit[..end]) {
+            return Err(bad());
+        }
+        rest = &unit[end..];
+    }
+    Ok(())
+}
+
+/// <https://docs.docker.com/build/builders/drivers/>
+///
+/// (De)serialized as its setting, see [`Driver::from_setting`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(into = "String", try_from = "String")]
 pub(crate) enum Driver {
     Docker,
     DockerContainer,
+    /// A BuildKit daemon started elsewhere, see <https://docs.docker.com/build/builders/drivers/remote/>
+    Remote {
+        endpoint: String,
+        opts: BTreeMap<String, String>,
+    },
+    /// Rootless BuildKit pods, see <https://docs.docker.com/build/builders/drivers/kubernetes/>
+    Kubernetes {
+        opts: BTreeMap<String, String>,
+    },
     Other(String),
 }
 
 impl FromStr for Driver {
     type Err = anyhow::Error;
 
+    /// Reads a driver name, as `buildx ls` shows it
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
             "docker" => Self::Docker,
@@ -113,17 +567,129 @@ impl FromStr for Driver {
     }
 }
 
+impl fmt::Display for Driver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Docker => write!(f, "docker"),
+            Self::DockerContainer => write!(f, "{BUILDER_DRIVER}"),
+            Self::Remote { endpoint, opts } => {
+                write!(f, "remote,endpoint={endpoint}")?;
+                opts.iter().try_for_each(|(k, v)| write!(f, ",{k}={v}"))
+            }
+            Self::Kubernetes { opts } => {
+                write!(f, "kubernetes")?;
+                opts.iter().try_for_each(|(k, v)| write!(f, ",{k}={v}"))
+            }
+            Self::Other(driver) => write!(f, "{driver}"),
+        }
+    }
+}
+
+impl From<Driver> for String {
+    fn from(driver: Driver) -> Self {
+        driver.to_string()
+    }
+}
+
+impl TryFrom<String> for Driver {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        Self::from_setting(&s)
+    }
+}
+
+impl Driver {
+    /// Reads `$CARGOGREEN_BUILDER_DRIVER`: a driver name then its comma-separated `key=value` options
+    pub(crate) fn from_setting(s: &str) -> Result<Self> {
+        let mut parts = s.split(',').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let mut opts = parts
+            .map(|kv| match kv.split_once('=') {
+                Some((k, v)) if !k.is_empty() && !v.is_empty() => Ok((k.to_owned(), v.to_owned())),
+                _ => Err(anyhow!("Expected key=value, got {kv:?}")),
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        if opts.contains_key("image") {
+            bail!("Set the image through ${ENV_BUILDER_IMAGE}")
+        }
+        match name {
+            BUILDER_DRIVER if opts.is_empty() => Ok(Self::DockerContainer),
+            BUILDER_DRIVER => bail!("Driver {BUILDER_DRIVER} takes no options"),
+            "remote" => {
+                let Some(endpoint) = opts.remove("endpoint") else {
+                    bail!("Driver remote needs an endpoint=tcp://.. option")
+                };
+                if !["tcp://", "unix://"].iter().any(|scheme| endpoint.starts_with(scheme)) {
+                    bail!(
+                        "Driver remote endpoint must start with tcp:// or unix:// (got {endpoint:?})"
+                    )
+                }
+                Ok(Self::Remote { endpoint, opts })
+            }
+            "kubernetes" => {
+                if opts.get("rootless").is_some_and(|v| v != "true") {
+                    bail!("Driver kubernetes only runs rootless")
+                }
+                opts.remove("rootless");
+                Ok(Self::Kubernetes { opts })
+            }
+            _ => {
+                bail!("Driver must be one of {BUILDER_DRIVER}, remote or kubernetes (got {name:?})")
+            }
+        }
+    }
+
+    /// Options passed as `--driver-opt`s, and that a builder must show
+    fn driver_opts(&self) -> Vec<(String, String)> {
+        match self {
+            Self::Remote { opts, .. } => opts.clone().into_iter().collect(),
+            Self::Kubernetes { opts } => {
+                let rootless = ("rootless".to_owned(), "true".to_owned());
+                opts.clone().into_iter().chain([rootless]).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// The name `buildx` knows this driver by
+    fn name(&self) -> String {
+        self.to_string().split(',').next().unwrap_or_default().to_owned()
+    }
+}
+
 impl Green {
     pub(crate) async fn maybe_inspect_builder(&mut self) -> Result<()> {
+        self.backend().inspect_builder(self).await
+    }
+
+    pub(crate) async fn maybe_setup_builder(&mut self, env: Option<String>) -> Result<()> {
+        self.backend().setup_builder(self, env).await
+    }
+
+    pub(crate) async fn inspect_buildx_builder(&mut self) -> Result<()> {
         let Some(ref name) = self.builder.name else { return Ok(()) };
+        if !self.builder.has_container() {
+            // NOTE: builder.id then holds a BuildKit worker ID, see setup_buildx_builder
+            info!("Skipping inspecting builder container: there is none locally");
+            return Ok(());
+        }
+        // TODO: find out how to come up with "buildx_buildkit_supergreen0"
+        let container = format!("buildx_buildkit_{name}0");
 
         let mut cmd = self.cmd()?;
-        // TODO: find out how to come up with "buildx_buildkit_supergreen0"
-        cmd.arg("inspect").arg(format!("buildx_buildkit_{name}0"));
+        cmd.arg("inspect").arg(&container);
 
         let (succeeded, stdout, stderr) = cmd.exec().await?;
         if !succeeded {
             let stderr = String::from_utf8_lossy(&stderr);
+            if stderr.to_lowercase().contains("no such object") {
+                warn!(
+                    "No builder container {container}: builds won't be retried on its recreation"
+                );
+                return Ok(());
+            }
             bail!("BUG: failed to inspect builder container: {stderr}")
         }
 
@@ -142,12 +708,7 @@ impl Green {
         Ok(())
     }
 
-    pub(crate) async fn maybe_setup_builder(&mut self, env: Option<String>) -> Result<()> {
-        if self.runner.is_none() {
-            info!("Skipping builder setup (runner:{})", self.runner);
-            return Ok(());
-        }
-
+    pub(crate) async fn setup_buildx_builder(&mut self, env: Option<String>) -> Result<()> {
         let (managed, name) = match env.as_deref() {
             None | Some("supergreen") => (true, "supergreen"),
             Some("") => {
@@ -159,13 +720,57 @@ impl Green {
             Some(name) => (false, name),
         };
 
-        let builders = self.list_builders().await?;
+        if !self.builder.remotes.is_empty() {
+            self.ensure_remote_contexts().await?;
+        }
+
+        let is_remote = matches!(self.builder.driver, Some(Driver::Remote { .. }));
+        if let Some(var) = self.builder.configured_by().first().filter(|_| is_remote) {
+            bail!(
+                "Driver remote runs its own configuration, yet {} is set",
+                self.origins.setting(var)
+            )
+        }
+
+        let mut builders = self.list_builders().await?;
         let builder = find_builder(name, &builders);
         info!("found builder {builder:?}");
+        let mut created = false;
         if let Some(existing) = builder {
+            let drifts = existing.drifts_from(&self.builder.remotes);
+            if !drifts.is_empty() {
+                bail!(
+                    "Existing ${BUILDX_BUILDER}={name:?} does not match ${ENV_REMOTES}:\n{}\nMaybe try `docker buildx rm {name}` then run your cargo command again.",
+                    drifts.join("\n")
+                )
+            }
+
             let mut recreate = false;
 
+            let drifts = existing.drifts_from_driver(self.builder.driver.as_ref());
+            if !drifts.is_empty() {
+                if !managed {
+                    bail!(
+                        "Existing ${BUILDX_BUILDER}={name:?} does not match ${ENV_BUILDER_DRIVER}:\n{}",
+                        drifts.join("\n")
+                    )
+                }
+                recreate = true;
+            }
+
+            let drifts = existing.drifts_from_config(self.builder.config_digest().as_deref());
+            if !drifts.is_empty() {
+                if !managed {
+                    bail!(
+                        "Existing ${BUILDX_BUILDER}={name:?} does not match worker tuning settings:\n{}",
+                        drifts.join("\n")
+                    )
+                }
+                recreate = true;
+            }
+
             if let Some(ref img) = self.builder.image
+                && !is_remote
                 && !existing.uses_image(img)
             {
                 if !managed {
@@ -193,26 +798,39 @@ then run your cargo command again.
             }
 
             if recreate {
-                self.remove_builder(name, true).await?;
-                self.create_builder(name).await?;
+                self.remove_buildx_builder(name, true).await?;
+                self.create_buildx_builder(name).await?;
+                created = true;
             }
         } else if !managed {
             bail!("${BUILDX_BUILDER}={name} does not exist")
         } else {
-            self.create_builder(name).await?;
+            self.create_buildx_builder(name).await?;
+            created = true;
         }
 
+        if created {
+            builders = self.list_builders().await?;
+        }
+        let builder = find_builder(name, &builders);
+
         if self.builder.image.is_none() {
             // Only informational: only used through showing envs values
             self.builder.image = builder.and_then(BuildxBuilder::first_image);
         }
 
-        self.builder.driver = builder.map(|b| b.driver.parse().expect("infaillible"));
+        if self.builder.driver.is_none() {
+            self.builder.driver = builder.map(|b| b.driver.parse().expect("infaillible"));
+        }
+        if !self.builder.has_container() {
+            // Changes when the builder's state is lost, like a container ID would
+            self.builder.id = builder.and_then(BuildxBuilder::first_worker_id);
+        }
         self.builder.name = Some(name.to_owned());
         Ok(())
     }
 
-    pub(crate) async fn remove_builder(&mut self, name: &str, keep_state: bool) -> Result<()> {
+    pub(crate) async fn remove_buildx_builder(&self, name: &str, keep_state: bool) -> Result<()> {
         if !keep_state {
             return self.try_removing_builder(name, false).await;
         }
@@ -224,11 +842,41 @@ then run your cargo command again.
         Ok(())
     }
 
-    pub(crate) async fn create_builder(&mut self, name: &str) -> Result<()> {
+    pub(crate) async fn create_buildx_builder(&mut self, name: &str) -> Result<()> {
+        let driver = self.builder.driver.clone().unwrap_or(Driver::DockerContainer);
+        let driver_opts: Vec<_> = driver
+            .driver_opts()
+            .into_iter()
+            .map(|(k, v)| format!("--driver-opt={k}={v}"))
+            .collect();
+        let driver_name = driver.name();
+
+        match driver {
+            Driver::DockerContainer | Driver::Kubernetes { .. } => {}
+            Driver::Remote { ref endpoint, .. } => {
+                // The daemon runs elsewhere, along with its configuration
+                let mut args = vec!["buildx", "create", "--bootstrap"];
+                args.extend_from_slice(&["--name", name]);
+                args.extend_from_slice(&["--driver", &driver_name]);
+                args.extend(driver_opts.iter().map(String::as_str));
+                args.push(endpoint);
+                return self.try_creating_builder(&args).await;
+            }
+            Driver::Docker | Driver::Other(_) => {
+                bail!("Cannot create a builder with driver {driver}")
+            }
+        }
+
         let mut config = buildkitd::Config::default();
         if !self.registry_mirrors.is_empty() {
             config.set_registry_mirrors("docker.io", self.registry_mirrors.clone());
         }
+        self.builder.configure(&mut config);
+        for (host, buildkitd::Registry { ca, .. }) in &config.registry {
+            if let Some(ca) = ca.iter().find(|ca| !ca.exists()) {
+                bail!("CA certificate of registry {host:?} does not exist: {ca}")
+            }
+        }
 
         let mut use_host_network = false;
         let hosts = self
@@ -257,13 +905,17 @@ then run your cargo command again.
                 use_host_network = true;
             }
 
+            if self.builder.registries.get(domain).is_some_and(|registry| {
+                registry.http || registry.insecure || !registry.ca.is_empty()
+            }) {
+                // Explicitly configured
+                continue;
+            }
+
             if tst(&mut clt, "https", domain).await.is_err()
                 && tst(&mut clt, "http", domain).await.is_ok()
             {
-                config.registry.insert(
-                    domain.to_owned(),
-                    buildkitd::Registry { http: true, ..Default::default() },
-                );
+                config.registry.entry(domain.to_owned()).or_default().http = true;
             }
         }
 
@@ -279,28 +931,72 @@ then run your cargo command again.
 
         let mut args = vec!["buildx", "create", "--bootstrap"];
         args.extend_from_slice(&["--name", name]);
-        args.extend_from_slice(&["--driver", BUILDER_DRIVER]);
+        args.extend_from_slice(&["--driver", &driver_name]);
+        args.extend(driver_opts.iter().map(String::as_str));
         if let Some(ref cfg) = cfg {
             args.extend_from_slice(&["--buildkitd-config", cfg.as_str()]);
         }
 
-        if use_host_network {
+        // NOTE: passed as flags, as these replace any `insecure-entitlements` from the config.
+        let mut entitlements = self.builder.tuning.entitlements.clone();
+        if use_host_network && driver == Driver::DockerContainer {
             // From [Insecure Entitlement "network.host" not working](https://github.com/docker/buildx/issues/835)
-            args.extend_from_slice(&[
-                "--driver-opt=network=host",
-                "--buildkitd-flags",
-                "--allow-insecure-entitlement network.host",
-            ]);
+            args.push("--driver-opt=network=host");
+            entitlements.push("network.host".to_owned());
+        }
+        if !entitlements.is_empty() && driver == Driver::DockerContainer {
+            // Keep what buildx allows by default
+            entitlements.insert(0, "network.host".to_owned());
+        }
+        let flags = entitlements
+            .iter()
+            .collect::<IndexSet<_>>()
+            .into_iter()
+            .map(|entitlement| format!("--allow-insecure-entitlement={entitlement}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !flags.is_empty() {
+            args.extend_from_slice(&["--buildkitd-flags", &flags]);
         }
 
         let img = if let Some(ref img) = self.builder.image {
             img.clone()
+        } else if driver == Driver::DockerContainer {
+            self.fetch_digest(&BUILDKIT_IMAGE).await?
         } else {
-            fetch_digest(&self.runner, &BUILDKIT_IMAGE).await?
+            self.fetch_digest(&BUILDKIT_ROOTLESS_IMAGE).await?
         };
         let image = format!("--driver-opt=image={}", img.noscheme());
         args.push(&image);
 
+        if self.builder.remotes.is_empty() {
+            self.try_creating_builder(&args).await?;
+        }
+        // One node per remote, each on the Docker host its context points to
+        for (i, remote) in self.builder.remotes.iter().enumerate() {
+            let mut args = args.clone();
+            if i != 0 {
+                args.push("--append");
+            }
+            args.extend_from_slice(&["--node", &remote.name]);
+            let platforms = remote.platforms.join(",");
+            if !platforms.is_empty() {
+                args.extend_from_slice(&["--platform", &platforms]);
+            }
+            args.push(&remote.name);
+            self.try_creating_builder(&args).await?;
+        }
+
+        if let Some(cfg) = cfg {
+            fs::remove_file(cfg)
+                .map_err(|e| anyhow!("Failed cleaning up buildkitd config: {e}"))?;
+        }
+
+        self.builder.image = Some(img);
+        Ok(())
+    }
+
+    async fn try_creating_builder(&self, args: &[&str]) -> Result<()> {
         fn builder_transient_error(stderr: &str) -> Result<anyhow::Error> {
             if stderr.contains("existing instance") && stderr.contains("but no append mode") {
                 return Ok(anyhow!("A builder with the same name already exists..."));
@@ -315,10 +1011,10 @@ then run your cargo command again.
         loop {
             assert!(!self.runner.is_none(), "create_builder() called with Runner::None");
             let mut cmd = self.cmd()?;
-            cmd.args(&args);
+            cmd.args(args);
             let (succeeded, _, stderr) = cmd.exec().await?;
             if succeeded {
-                break;
+                return Ok(());
             }
             let stderr = String::from_utf8_lossy(&stderr);
             if retrier.continues() {
@@ -331,14 +1027,18 @@ then run your cargo command again.
                 self.runner
             )
         }
+    }
 
-        if let Some(cfg) = cfg {
-            fs::remove_file(cfg)
-                .map_err(|e| anyhow!("Failed cleaning up buildkitd config: {e}"))?;
-        }
+    pub(crate) async fn show_buildx_builder(&self) -> Result<String> {
+        let mut cmd = self.cmd()?;
+        cmd.args(["buildx", "inspect"]);
 
-        self.builder.image = Some(img);
-        Ok(())
+        let (succeeded, stdout, stderr) = cmd.exec().await?;
+        if !succeeded {
+            let stderr = String::from_utf8_lossy(&stderr);
+            bail!("BUG: failed to inspect builder: {stderr}")
+        }
+        Ok(String::from_utf8_lossy(&stdout).into_owned())
     }
 
     async fn try_removing_builder(&self, name: &str, keep_state: bool) -> Result<()> {
@@ -359,6 +1059,31 @@ then run your cargo command again.
         Ok(())
     }
 
+    /// Describes the buildx builder `name`, for `supergreen doctor`.
+    ///
+    /// Errors when the builder is missing or when one of its nodes is not running.
+    pub(crate) async fn buildx_builder_health(&self, name: &str) -> Result<BuilderHealth> {
+        let builders = self.list_builders().await?;
+        let Some(builder) = find_builder(name, &builders) else { bail!("No builder {name:?}") };
+        let down: Vec<_> = builder
+            .nodes
+            .iter()
+            .filter(|node| node.status.as_deref() != Some("running"))
+            .map(|node| format!("{} ({})", node.name, node.status.as_deref().unwrap_or("unknown")))
+            .collect();
+        if !down.is_empty() {
+            bail!("Builder {name:?} has nodes that are not running: {}", down.join(", "))
+        }
+        let mut versions: Vec<_> =
+            builder.nodes.iter().filter_map(|node| node.version.clone()).collect();
+        versions.dedup();
+        Ok(BuilderHealth {
+            driver: builder.driver.clone(),
+            versions,
+            up_to_date: builder.uses_version_newer_or_equal_to(&LATEST_BUILDKIT),
+        })
+    }
+
     async fn list_builders(&self) -> Result<Vec<BuildxBuilder>> {
         assert!(!self.runner.is_none(), "list_builders() called with Runner::None");
         let mut cmd = self.cmd()?;
@@ -401,10 +1126,25 @@ fn find_builders() {
             name: "bla".to_owned(),
             driver: BUILDER_DRIVER.to_owned(),
             nodes: vec![BuilderNode {
+                name: "bla0".to_owned(),
+                endpoint: "unix:///var/run/docker.sock".to_owned(),
+                platforms: [
+                    "linux/amd64",
+                    "linux/amd64/v2",
+                    "linux/amd64/v3",
+                    "linux/amd64/v4",
+                    "linux/386"
+                ]
+                .map(ToOwned::to_owned)
+                .to_vec(),
                 version: Some("v0.22.0".to_owned()),
+                status: Some("running".to_owned()),
+                ids: vec!["zh05kd8qdrkor9k2h15br199l".to_owned()],
                 driver_opts: Some(DriverOpts {
                     image: Some("docker.io/moby/buildkit:buildx-stable-1".to_owned()),
+                    others: [].into(),
                 }),
+                labels: WorkerLabels::default(),
             }],
         }
     );
@@ -418,17 +1158,320 @@ fn find_builders() {
         &BuildxBuilder {
             name: "default".to_owned(),
             driver: "docker".to_owned(),
-            nodes: vec![BuilderNode { version: Some("v0.23.2".to_owned()), driver_opts: None }],
+            nodes: vec![BuilderNode {
+                name: "default".to_owned(),
+                endpoint: "default".to_owned(),
+                platforms: [
+                    "linux/amd64",
+                    "linux/amd64/v2",
+                    "linux/amd64/v3",
+                    "linux/amd64/v4",
+                    "linux/386"
+                ]
+                .map(ToOwned::to_owned)
+                .to_vec(),
+                ids: vec!["4ff1ee7f-a3ff-4df0-ad6e-9d0162ddbda5".to_owned()],
+                version: Some("v0.23.2".to_owned()),
+                status: Some("running".to_owned()),
+                driver_opts: None,
+                labels: WorkerLabels::default(),
+            }],
         }
     );
 }
 
+#[test]
+fn remote_builder_drifts() {
+    let json = r#"{"Driver":"docker-container","Name":"multi","Nodes":[{"Name":"amd","Endpoint":"amd","Platforms":["linux/amd64","linux/386"],"Version":"v0.23.2"},{"Name":"old","Endpoint":"ssh://old-box","Platforms":["linux/arm64"]}]}"#;
+    let builders = parse_builders(json).unwrap();
+    let builder = find_builder("multi", &builders).unwrap();
+
+    let remotes = crate::remotes::parse_remotes(
+        "name=amd,host=ssh://amd64-box,platform=linux/amd64;name=arm,host=ssh://arm64-box,platform=linux/arm64",
+    )
+    .unwrap();
+    assert_eq!(
+        builder.drifts_from(&remotes),
+        [r#"* missing node "arm""#, r#"* unexpected node "old" at "ssh://old-box""#]
+    );
+
+    let remotes = crate::remotes::parse_remotes(
+        "name=amd,host=ssh://amd64-box,platform=linux/arm64;name=old,host=ssh://old-box",
+    )
+    .unwrap();
+    assert_eq!(
+        builder.drifts_from(&remotes),
+        [
+            r#"* node "amd" does not build for linux/arm64"#,
+            r#"* node "old" uses endpoint "ssh://old-box", not context "old""#,
+        ]
+    );
+
+    assert!(builder.drifts_from(&[]).is_empty());
+}
+
+#[cfg(test)]
+#[test_case::test_case("docker-container", Ok(Driver::DockerContainer); "docker-container")]
+#[test_case::test_case("remote,endpoint=tcp://bk:1234,cacert=/c/ca.pem", Ok(Driver::Remote { endpoint: "tcp://bk:1234".to_owned(), opts: [("cacert".to_owned(), "/c/ca.pem".to_owned())].into() }); "remote")]
+#[test_case::test_case("kubernetes,namespace=ci,rootless=true", Ok(Driver::Kubernetes { opts: [("namespace".to_owned(), "ci".to_owned())].into() }); "kubernetes")]
+#[test_case::test_case("docker-container,network=host", Err("Driver docker-container takes no options"); "container options")]
+#[test_case::test_case("remote", Err("Driver remote needs an endpoint=tcp://.. option"); "remote without endpoint")]
+#[test_case::test_case("remote,endpoint=ssh://bk", Err(r#"Driver remote endpoint must start with tcp:// or unix:// (got "ssh://bk")"#); "remote over ssh")]
+#[test_case::test_case("kubernetes,rootless=false", Err("Driver kubernetes only runs rootless"); "rootful kubernetes")]
+#[test_case::test_case("kubernetes,image=my/buildkit", Err("Set the image through $CARGOGREEN_BUILDER_IMAGE"); "image")]
+#[test_case::test_case("kubernetes,replicas", Err(r#"Expected key=value, got "replicas""#); "bare option")]
+#[test_case::test_case("docker", Err(r#"Driver must be one of docker-container, remote or kubernetes (got "docker")"#); "docker")]
+fn driver_settings(setting: &str, expected: Result<Driver, &str>) {
+    let driver = Driver::from_setting(setting).map_err(|e| e.to_string());
+    assert_eq!(driver, expected.map_err(ToOwned::to_owned));
+    if let Ok(driver) = driver {
+        assert_eq!(Driver::from_setting(&driver.to_string()).unwrap(), driver);
+    }
+}
+
+#[test]
+fn driver_drifts() {
+    let json = r#"{"Driver":"remote","Name":"shared","Nodes":[{"Name":"shared0","Endpoint":"tcp://bk:1234","DriverOpts":{"cacert":"/c/ca.pem"},"IDs":["w0rk3r"]}]}"#;
+    let builders = parse_builders(json).unwrap();
+    let builder = find_builder("shared", &builders).unwrap();
+    assert_eq!(builder.first_worker_id().as_deref(), Some("w0rk3r"));
+
+    let remote = Driver::from_setting("remote,endpoint=tcp://bk:1234,cacert=/c/ca.pem").unwrap();
+    assert!(builder.drifts_from_driver(Some(&remote)).is_empty());
+    assert!(builder.drifts_from_driver(None).is_empty());
+
+    let remote = Driver::from_setting("remote,endpoint=tcp://other:1234,cert=/c/cert.pem").unwrap();
+    assert_eq!(
+        builder.drifts_from_driver(Some(&remote)),
+        [
+            r#"* node "shared0" is at "tcp://bk:1234", not "tcp://other:1234""#,
+            r#"* node "shared0" does not have cert=/c/cert.pem"#,
+        ]
+    );
+
+    let k8s = Driver::from_setting("kubernetes").unwrap();
+    assert_eq!(
+        builder.drifts_from_driver(Some(&k8s)),
+        [
+            r#"* uses driver "remote", not "kubernetes""#,
+            r#"* node "shared0" does not have rootless=true"#
+        ]
+    );
+}
+
+#[test]
+fn gc_policies() {
+    let policies = parse_gc_policies(
+        "filter=type==source.local,filter=type==exec.cachemount,keep-duration=48h ; all=true,reserved-space=10GB,min-free-space=10%;",
+    )
+    .unwrap();
+    assert_eq!(
+        policies,
+        [
+            GcPolicy {
+                filters: vec!["type==source.local".to_owned(), "type==exec.cachemount".to_owned()],
+                keep_duration: Some("48h".to_owned()),
+                ..Default::default()
+            },
+            GcPolicy {
+                all: true,
+                reserved_space: Some("10GB".to_owned()),
+                min_free_space: Some("10%".to_owned()),
+                ..Default::default()
+            },
+        ]
+    );
+    assert_eq!(
+        policies[0].to_string(),
+        "filter=type==source.local,filter=type==exec.cachemount,keep-duration=48h"
+    );
+    assert_eq!(parse_gc_policies(&policies[1].to_string()).unwrap(), [policies[1].clone()]);
+    assert!(policies.iter().all(|policy| policy.check().is_ok()));
+}
+
+#[cfg(test)]
+#[test_case::test_case("", "No policies given"; "empty")]
+#[test_case::test_case("all", r#"Bad policy "all": Expected key=value, got "all""#; "bare key")]
+#[test_case::test_case("all=yes", r#"Bad policy "all=yes": all must be true or false"#; "bad bool")]
+#[test_case::test_case("keep-bytes=1GB", r#"Bad policy "keep-bytes=1GB": Unexpected key "keep-bytes""#; "unknown key")]
+#[test_case::test_case("all=true", "has a policy that sets no limit: all=true"; "no limit")]
+#[test_case::test_case("keep-duration=2d", r#"must be a duration such as "48h" or "1h30m" (got "2d")"#; "days")]
+#[test_case::test_case("max-used-space=10 apples", r#"must be a size such as "20GB", or a percentage such as "10%" (got "10 apples")"#; "bad size")]
+#[test_case::test_case("min-free-space=200%", r#"must be a size such as "20GB", or a percentage such as "10%" (got "200%")"#; "bad percentage")]
+fn bad_gc_policies(policies: &str, expected: &str) {
+    let err = match parse_gc_policies(policies) {
+        Ok(policies) => policies.iter().find_map(|policy| policy.check().err()).unwrap(),
+        Err(e) => e,
+    };
+    assert_eq!(err.to_string(), expected);
+}
+
+#[cfg(test)]
+#[test_case::test_case("20GB", true)]
+#[test_case::test_case("512MB", true)]
+#[test_case::test_case("1.5t", true)]
+#[test_case::test_case("10 GiB", true)]
+#[test_case::test_case("9000", true)]
+#[test_case::test_case("10%", true)]
+#[test_case::test_case("0%", false)]
+#[test_case::test_case("GB", false)]
+#[test_case::test_case("10GBs", false)]
+#[test_case::test_case("-1", false)]
+fn disk_spaces(space: &str, ok: bool) {
+    assert_eq!(check_disk_space(space).is_ok(), ok);
+}
+
+#[cfg(test)]
+#[test_case::test_case("48h", true)]
+#[test_case::test_case("1h30m", true)]
+#[test_case::test_case("1.5h", true)]
+#[test_case::test_case("300ms", true)]
+#[test_case::test_case("", false)]
+#[test_case::test_case("h", false)]
+#[test_case::test_case("2d", false)]
+#[test_case::test_case("48", false)]
+fn durations(duration: &str, ok: bool) {
+    assert_eq!(check_duration(duration).is_ok(), ok);
+}
+
+#[test]
+fn registries() {
+    let registries = parse_registries(
+        "host=ghcr.io,mirror=mirror.internal/ghcr ; host=registry.internal:5000,ca=/c/ca.pem,ca=/c/other.pem,insecure=true;",
+    )
+    .unwrap();
+    assert_eq!(
+        registries,
+        [
+            (
+                "ghcr.io".to_owned(),
+                buildkitd::Registry {
+                    mirrors: vec!["mirror.internal/ghcr".to_owned()],
+                    ..Default::default()
+                }
+            ),
+            (
+                "registry.internal:5000".to_owned(),
+                buildkitd::Registry {
+                    ca: vec!["/c/ca.pem".into(), "/c/other.pem".into()],
+                    insecure: true,
+                    ..Default::default()
+                }
+            ),
+        ]
+        .into()
+    );
+    assert_eq!(parse_registries(&show_registries(&registries)).unwrap(), registries);
+
+    let builder = Builder { registries, ..Default::default() };
+    let mut config = buildkitd::Config::default();
+    config.set_registry_mirrors("docker.io", vec!["mirror.gcr.io".to_owned()]);
+    builder.configure(&mut config);
+    assert_eq!(config.registry.len(), 3);
+    assert_eq!(config.registry["ghcr.io"].mirrors, ["mirror.internal/ghcr"]);
+    assert!(config.registry["registry.internal:5000"].insecure);
+    assert!(config.worker["oci"].labels.contains_key(CONFIG_LABEL));
+}
+
+#[cfg(test)]
+#[test_case::test_case("", "No registries given"; "empty")]
+#[test_case::test_case("mirror=m.org", r#"Bad registry "mirror=m.org": missing host"#; "no host")]
+#[test_case::test_case("host=a.org,b", r#"Bad registry "host=a.org,b": expected key=value, got "b""#; "bare value")]
+#[test_case::test_case("host=a.org,http=yes", r#"Bad registry "host=a.org,http=yes": http must be true or false"#; "bad bool")]
+#[test_case::test_case("host=a.org,keypair=k", r#"Bad registry "host=a.org,keypair=k": unexpected key "keypair""#; "unknown key")]
+#[test_case::test_case("host=a.org,http=true;host=a.org,insecure=true", r#"Registry "a.org" is given more than once"#; "same host")]
+fn bad_registries(registries: &str, err: &str) {
+    assert_eq!(parse_registries(registries).unwrap_err().to_string(), err);
+}
+
+#[cfg(test)]
+#[test_case::test_case("https://a.org", "mirror=m.org", r#"has a bad registry host "https://a.org": expected a host, maybe with a port"#; "scheme")]
+#[test_case::test_case("a.org", "http=false", r#"sets nothing for registry "a.org""#; "nothing")]
+#[test_case::test_case("a.org", "mirror=https://m.org", r#"has a bad mirror for registry "a.org": "https://m.org""#; "mirror scheme")]
+#[test_case::test_case("a.org", "ca=certs/ca.pem", r#"has a relative CA certificate path for registry "a.org": certs/ca.pem"#; "relative ca")]
+fn bad_registry(host: &str, settings: &str, err: &str) {
+    let registries = parse_registries(&format!("host={host},{settings}")).unwrap();
+    assert_eq!(check_registry(host, &registries[host]).unwrap_err().to_string(), err);
+}
+
+#[test]
+fn worker_tuning() {
+    let builder = Builder::default();
+    assert!(builder.config_digest().is_none());
+    let mut config = buildkitd::Config::default();
+    builder.configure(&mut config);
+    assert_eq!(config, buildkitd::Config::default());
+
+    let tuning = Tuning {
+        max_parallelism: Some(4),
+        gc_keep_storage: Some("20GB".to_owned()),
+        entitlements: vec!["security.insecure".to_owned()],
+        ..Default::default()
+    };
+    let builder = Builder { tuning: tuning.clone(), ..Default::default() };
+    let digest = builder.config_digest().unwrap();
+    builder.configure(&mut config);
+    let worker = &config.worker["oci"];
+    assert_eq!(worker.max_parallelism, Some(4));
+    assert_eq!(worker.gc, Some(true));
+    assert_eq!(worker.gckeepstorage.as_deref(), Some("20GB"));
+    assert_eq!(worker.labels[CONFIG_LABEL], digest);
+    assert!(config.insecure_entitlements.is_empty());
+
+    let other = Tuning { max_parallelism: Some(8), ..tuning };
+    let other = Builder { tuning: other, ..builder };
+    assert_ne!(other.config_digest().unwrap(), digest);
+
+    let json = format!(
+        r#"{{"Driver":"docker-container","Name":"supergreen","Nodes":[{{"Name":"supergreen0","Labels":{{"org.mobyproject.buildkit.worker.executor":"oci","{CONFIG_LABEL}":"{digest}"}}}}]}}"#
+    );
+    let builders = parse_builders(&json).unwrap();
+    let builder = find_builder("supergreen", &builders).unwrap();
+    assert_eq!(
+        builder.drifts_from_config(None),
+        [r#"* node "supergreen0" was created with worker settings"#]
+    );
+    assert!(builder.drifts_from_config(Some(&digest)).is_empty());
+    assert_eq!(
+        builder.drifts_from_config(other.config_digest().as_deref()),
+        [r#"* node "supergreen0" was created with other worker settings"#]
+    );
+
+    let json =
+        r#"{"Driver":"docker-container","Name":"supergreen","Nodes":[{"Name":"supergreen0"}]}"#;
+    let builders = parse_builders(json).unwrap();
+    let builder = find_builder("supergreen", &builders).unwrap();
+    assert!(builder.drifts_from_config(None).is_empty());
+}
+
 #[derive(Debug, Deserialize)]
 #[cfg_attr(test, derive(PartialEq))]
 #[serde(rename_all = "PascalCase")]
 struct BuilderNode {
+    #[serde(default)]
+    name: String,
+    /// A Docker context name, or a daemon address
+    #[serde(default)]
+    endpoint: String,
+    #[serde(default)]
+    platforms: Vec<String>,
+    /// BuildKit worker IDs
+    #[serde(default, rename = "IDs")]
+    ids: Vec<String>,
     driver_opts: Option<DriverOpts>,
     version: Option<String>,
+    /// E.g. `"running"`, `"inactive"`
+    status: Option<String>,
+    #[serde(default)]
+    labels: WorkerLabels,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[cfg_attr(test, derive(PartialEq))]
+struct WorkerLabels {
+    /// See [`CONFIG_LABEL`]
+    #[serde(rename = "org.supergreen.config")]
+    config: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -436,9 +1479,21 @@ struct BuilderNode {
 struct DriverOpts {
     /// An ImageUri without `^docker-image://`
     image: Option<String>,
+    #[serde(flatten)]
+    others: BTreeMap<String, String>,
 }
 
 /// <https://docs.docker.com/build/builders/drivers/>
+/// See [`Green::buildx_builder_health`]
+#[derive(Debug)]
+pub(crate) struct BuilderHealth {
+    pub(crate) driver: String,
+    /// BuildKit versions its nodes run
+    pub(crate) versions: Vec<String>,
+    /// Whether a node runs at least [`LATEST_BUILDKIT`]
+    pub(crate) up_to_date: bool,
+}
+
 #[derive(Debug, Deserialize)]
 #[cfg_attr(test, derive(PartialEq))]
 #[serde(rename_all = "PascalCase")]
@@ -452,7 +1507,7 @@ struct BuildxBuilder {
 impl BuildxBuilder {
     fn uses_image(&self, img: &ImageUri) -> bool {
         self.nodes.iter().any(|BuilderNode { driver_opts, .. }| {
-            driver_opts.iter().any(|DriverOpts { image }| {
+            driver_opts.iter().any(|DriverOpts { image, .. }| {
                 if image.as_ref().is_some_and(|i| i.contains('@')) {
                     image.as_deref() == Some(img.noscheme())
                 } else {
@@ -462,6 +1517,79 @@ impl BuildxBuilder {
         })
     }
 
+    /// Describes how nodes differ from `remotes`, unless none are expected
+    fn drifts_from(&self, remotes: &[Remote]) -> Vec<String> {
+        if remotes.is_empty() {
+            return vec![];
+        }
+        let mut drifts = vec![];
+        for Remote { name, platforms, .. } in remotes {
+            let Some(node) = self.nodes.iter().find(|node| node.name == *name) else {
+                drifts.push(format!("* missing node {name:?}"));
+                continue;
+            };
+            if node.endpoint != *name {
+                drifts.push(format!(
+                    "* node {name:?} uses endpoint {:?}, not context {name:?}",
+                    node.endpoint
+                ));
+            }
+            for platform in platforms.iter().filter(|p| !node.platforms.contains(p)) {
+                drifts.push(format!("* node {name:?} does not build for {platform}"));
+            }
+        }
+        for node in &self.nodes {
+            if !remotes.iter().any(|remote| remote.name == node.name) {
+                drifts.push(format!("* unexpected node {:?} at {:?}", node.name, node.endpoint));
+            }
+        }
+        drifts
+    }
+
+    /// Describes which nodes were not created with the worker settings summed up by `digest`,
+    /// or with any when `digest` is unset
+    fn drifts_from_config(&self, digest: Option<&str>) -> Vec<String> {
+        self.nodes
+            .iter()
+            .filter(|node| node.labels.config.as_deref() != digest)
+            .map(|node| match digest {
+                Some(_) => format!("* node {:?} was created with other worker settings", node.name),
+                None => format!("* node {:?} was created with worker settings", node.name),
+            })
+            .collect()
+    }
+
+    /// Describes how the builder differs from `driver`, if set
+    fn drifts_from_driver(&self, driver: Option<&Driver>) -> Vec<String> {
+        let Some(driver) = driver else { return vec![] };
+        let mut drifts = vec![];
+        let name = driver.name();
+        if self.driver != name {
+            drifts.push(format!("* uses driver {:?}, not {name:?}", self.driver));
+        }
+        for node in &self.nodes {
+            if let Driver::Remote { endpoint, .. } = driver
+                && node.endpoint != *endpoint
+            {
+                drifts.push(format!(
+                    "* node {:?} is at {:?}, not {endpoint:?}",
+                    node.name, node.endpoint
+                ));
+            }
+            let opts = node.driver_opts.as_ref().map(|o| &o.others);
+            for (k, v) in driver.driver_opts() {
+                if opts.and_then(|opts| opts.get(&k)) != Some(&v) {
+                    drifts.push(format!("* node {:?} does not have {k}={v}", node.name));
+                }
+            }
+        }
+        drifts
+    }
+
+    fn first_worker_id(&self) -> Option<String> {
+        self.nodes.iter().flat_map(|node| node.ids.iter()).next().cloned()
+    }
+
     fn uses_version_newer_or_equal_to(&self, latest: &Version) -> bool {
         self.nodes.iter().any(|BuilderNode { version, .. }| {
             version.as_deref().is_some_and(|v| {
@@ -1,3 +1,4 @@
+use camino::Utf8PathBuf;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
@@ -26,12 +27,17 @@ impl Config {
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
-#[serde(default, rename_all = "kebab-case")]
+/// Also the type of the `registries` setting, hence `deny_unknown_fields`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
 pub(crate) struct Registry {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub(crate) mirrors: Vec<String>,
 
+    /// PEM files of CAs to trust, which buildx copies into the builder
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) ca: Vec<Utf8PathBuf>,
+
     #[serde(skip_serializing_if = "<&bool as std::ops::Not>::not")]
     pub(crate) http: bool,
 
@@ -50,6 +56,43 @@ pub(crate) struct Worker {
 
     #[serde(skip_serializing_if = "String::is_empty")]
     pub(crate) namespace: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) gc: Option<bool>,
+
+    /// A size (e.g. `"20GB"`) or a percentage of disk (e.g. `"10%"`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) gckeepstorage: Option<String>,
+
+    /// Shown by `buildx ls`, as the worker's
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub(crate) labels: IndexMap<String, String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) gcpolicy: Vec<GcPolicy>,
+}
+
+/// <https://docs.docker.com/build/cache/garbage-collection/>
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct GcPolicy {
+    #[serde(skip_serializing_if = "<&bool as std::ops::Not>::not")]
+    pub(crate) all: bool,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) filters: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) keep_duration: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reserved_space: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_used_space: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) min_free_space: Option<String>,
 }
 
 #[test]
@@ -126,6 +169,43 @@ insecure = true
     assert_eq!(ser, cfg);
 }
 
+#[test]
+fn private_ca_registry() {
+    let cfg = &r#"
+[registry."ghcr.io"]
+mirrors = ["mirror.internal/ghcr"]
+
+[registry."registry.internal:5000"]
+ca = ["/etc/ssl/internal-ca.pem"]
+"#[1..];
+
+    let de: Config = toml::de::from_str(cfg).unwrap();
+    assert_eq!(
+        de,
+        Config {
+            registry: [
+                (
+                    "ghcr.io".to_owned(),
+                    Registry {
+                        mirrors: vec!["mirror.internal/ghcr".to_owned()],
+                        ..Default::default()
+                    }
+                ),
+                (
+                    "registry.internal:5000".to_owned(),
+                    Registry { ca: vec!["/etc/ssl/internal-ca.pem".into()], ..Default::default() }
+                ),
+            ]
+            .into(),
+            ..Default::default()
+        }
+    );
+
+    let ser = toml::to_string_pretty(&de).unwrap();
+    println!("{ser}");
+    assert_eq!(toml::de::from_str::<Config>(&ser).unwrap(), de);
+}
+
 #[test]
 fn insecure() {
     let cfg = &r#"
@@ -201,6 +281,64 @@ max-parallelism = 0
     );
 }
 
+#[test]
+fn garbage_collection() {
+    let cfg = &r#"
+[worker.oci]
+max-parallelism = 4
+gc = true
+gckeepstorage = "20GB"
+
+[worker.oci.labels]
+"org.supergreen.config" = "0a1b2c3d"
+
+[[worker.oci.gcpolicy]]
+filters = ["type==source.local"]
+keepDuration = "48h"
+
+[[worker.oci.gcpolicy]]
+all = true
+reservedSpace = "10GB"
+minFreeSpace = "10%"
+"#[1..];
+
+    let de: Config = toml::de::from_str(cfg).unwrap();
+    assert_eq!(
+        de,
+        Config {
+            worker: [(
+                "oci".to_owned(),
+                Worker {
+                    max_parallelism: Some(4),
+                    gc: Some(true),
+                    gckeepstorage: Some("20GB".to_owned()),
+                    labels: [("org.supergreen.config".to_owned(), "0a1b2c3d".to_owned())].into(),
+                    gcpolicy: vec![
+                        GcPolicy {
+                            filters: vec!["type==source.local".to_owned()],
+                            keep_duration: Some("48h".to_owned()),
+                            ..Default::default()
+                        },
+                        GcPolicy {
+                            all: true,
+                            reserved_space: Some("10GB".to_owned()),
+                            min_free_space: Some("10%".to_owned()),
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                }
+            )]
+            .into(),
+            ..Default::default()
+        }
+    );
+
+    let ser = toml::to_string_pretty(&de).unwrap();
+    println!("{ser}");
+    assert_eq!(toml::de::from_str::<Config>(&ser).unwrap(), de);
+}
+
 // https://github.com/moby/buildkit/issues/5340#issuecomment-2828164139
 #[test]
 fn use_containerd() {
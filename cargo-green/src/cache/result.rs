@@ -107,7 +107,7 @@ pub(crate) async fn extract_just(src: &Utf8Path, fname: &str) -> Result<Vec<u8>>
     Ok(inner)
 }
 
-fn header_for(fname: &str, len: usize) -> Result<Header> {
+pub(crate) fn header_for(fname: &str, len: usize) -> Result<Header> {
     let mut header = Header::new_gnu();
     header.set_path(fname).map_err(|e| anyhow!("Failed setting {fname} path: {e}"))?;
     match len.try_into() {
@@ -9,15 +9,20 @@ use log::{debug, info, warn};
 use crate::{
     PKG, VSN,
     base_image::{BASE_IMAGE, BASE_IMAGE_LOCKED},
+    buildctl::check_buildkit_host,
+    builder::Driver,
     cratesio::{self},
+    cross,
     dirs::{cargo_home, pwd},
     experiments::EXPERIMENTS,
-    green::{Green, validate_csv},
-    image_uri::{SYNTAX_IMAGE, SYNTAX_IMAGE_LOCKED, fetch_digest},
+    green::{Green, Layer, validate_csv},
+    image_uri::{SYNTAX_IMAGE, SYNTAX_IMAGE_LOCKED},
     lockfile::{find_lockfile, locked_crates},
     logging::{self, maybe_log},
     network::Network,
+    remotes::parse_remotes,
     runner::{BUILDKIT_HOST, DOCKER_BUILDKIT, DOCKER_CONTEXT, DOCKER_HOST, Runner},
+    rustup::{self, ToolchainFile, maybe_get_local_host_triple},
     stage::{RST, Stage},
 };
 
@@ -31,6 +36,7 @@ pub(crate) async fn main(is_install: bool) -> Result<Green> {
     }
     if let Ok(val) = env::var(var) {
         green.runner = val.parse().map_err(|e| anyhow!("${var}={val:?} {e}"))?;
+        green.origins.set_env(ENV_RUNNER!());
     }
 
     // Get $CARGO_HOME only once and disallow conf overrides
@@ -69,6 +75,13 @@ pub(crate) async fn main(is_install: bool) -> Result<Green> {
         info!("${BUILDKIT_HOST} is set to {val:?}");
         eprintln!("${BUILDKIT_HOST} is set to {val:?}");
     }
+    if green.runner == Runner::Buildkitd {
+        check_buildkit_host(&green)?;
+    }
+    green.check_runner_build_arguments()?;
+    if green.cachebuildkit() && !green.runner.is_buildkit() && !green.runner.is_none() {
+        bail!("Experiment cachebuildkit needs a BuildKit runner, not {}", green.runner)
+    }
 
     var = BUILDX_BUILDER!();
     if green.builder.name.is_some() {
@@ -76,7 +89,7 @@ pub(crate) async fn main(is_install: bool) -> Result<Green> {
     }
     let builder = green.runner_envs.get(var);
     if let Some(name) = builder
-        && !green.runner.is_none()
+        && green.runner.is_buildx()
     {
         info!("${var} is set to {name:?}");
         eprintln!("${var} is set to {name:?}");
@@ -90,44 +103,73 @@ pub(crate) async fn main(is_install: bool) -> Result<Green> {
         }
     }
 
-    //CARGOGREEN_REMOTES ~= CCSV: host=URL;URL
-    // URL;0:host=URL,ca,cert,..;1:URL,ca=,..;2:..  https://docs.docker.com/build/ci/github-actions/configure-builder/#append-additional-nodes-to-the-builder
-    //=> colon CSV
-    //=> keys= host,ca,cert,key,skip-tls-verify + name,description,from (enforce!)
-    //=> when only URL given: craft name
-    //error if creating fails || creating existing name but different values
-    //error if given builder does not have exactly these remotes (ESC name,description,from)
-    //error if any of these is also set: DOCKER_HOST, DOCKER_CONTEXT, BUILDKIT_HOST
-    //docker context create --help
-    //
-    // docker context create amd64 --docker host=ssh://root@x.x.x.220
-    // docker context create arm64 --docker host=ssh://root@x.x.x.72
-    // docker buildx create --name multiarch-builder amd64 [--platform linux/amd64]
-    // docker buildx create --name multiarch-builder --append arm64 [--platform linux/arm64]
-    // docker buildx build --builder multiarch-builder -t dustinrue/buildx-example --platform linux/amd64,linux/arm64,linux/arm/v6 .
-    // https://dustinrue.com/2021/12/using-a-remote-docker-engine-with-buildx/
-    //
-    // https://github.com/moby/buildkit/issues/4268#issuecomment-2128464135
-    // docker buildx create --name amd64-builder --driver docker-container --platform linux/amd64 ssh://user@remote-machine
-    // docker buildx build --builder amd64-builder --load .
-    //
-    //docker use builder on other host
-    //https://dev.to/aboozar/build-docker-multi-platform-image-using-buildx-remote-builder-node-5631
+    var = ENV_BUILDER_DRIVER!();
+    if green.builder.driver.is_some() {
+        bail!("builder-driver can only be set through the environment variable")
+    }
+    if let Ok(driver) = env::var(var) {
+        if !green.runner.is_buildx() {
+            bail!("${var} is only supported with runner docker, not {}", green.runner)
+        }
+        if builder.is_some_and(|name| name.is_empty()) {
+            bail!("Setting ${var} requires a builder, yet ${}=\"\"", BUILDX_BUILDER!())
+        }
+        let driver = Driver::from_setting(&driver).map_err(|e| anyhow!("${var}={driver:?} {e}"))?;
+        if matches!(driver, Driver::Remote { .. }) && env::var(ENV_BUILDER_IMAGE!()).is_ok() {
+            bail!("Driver remote runs its own image, yet ${} is set", ENV_BUILDER_IMAGE!())
+        }
+        green.builder.driver = Some(driver);
+        green.origins.set_env(ENV_BUILDER_DRIVER!());
+    }
+
+    var = ENV_REMOTES!();
+    if !green.builder.remotes.is_empty() {
+        bail!("remotes can only be set through the environment variable")
+    }
+    if let Ok(remotes) = env::var(var) {
+        if !green.runner.is_buildx() {
+            bail!("${var} is only supported with runner docker, not {}", green.runner)
+        }
+        for other in [DOCKER_HOST, DOCKER_CONTEXT, BUILDKIT_HOST] {
+            if let Some(val) = green.runner_envs.get(other).filter(|val| !val.is_empty()) {
+                bail!("Setting ${var} along with ${other}={val:?} is unsupported")
+            }
+        }
+        if builder.is_some_and(|name| name.is_empty()) {
+            bail!("Setting ${var} requires a builder, yet ${}=\"\"", BUILDX_BUILDER!())
+        }
+        if green.builder.driver.as_ref().is_some_and(|driver| *driver != Driver::DockerContainer) {
+            bail!("Setting ${var} requires driver docker-container")
+        }
+        green.builder.remotes = parse_remotes(&remotes).map_err(|e| anyhow!("${var}: {e}"))?;
+        green.origins.set_env(ENV_REMOTES!());
+        info!("${var} is set to {remotes:?}");
+        eprintln!("${var} is set to {remotes:?}");
+    }
 
     // Then the builder: needed by cmd calls
     var = ENV_BUILDER_IMAGE!();
-    if green.builder.image.is_some() {
-        bail!("${var} can only be set through the environment variable")
+    if green.builder.image.is_some()
+        && !green.origins.last("builder-image").is_some_and(|layer| layer.is_file())
+    {
+        bail!("${var} can only be set through the environment variable or a config file")
     }
     if let Ok(builder_image) = env::var(var) {
         let img = builder_image
             .as_str()
             .try_into()
             .map_err(|e| anyhow!("${var}={builder_image:?} {e}"))?;
+        green.builder.image = Some(img);
+        green.origins.set_env(ENV_BUILDER_IMAGE!());
+    }
+    if let Some(img) = green.builder.image.take() {
         // Don't use 'maybe_lock_image', only 'fetch_digest': cmd uses builder.
-        green.builder.image = Some(fetch_digest(&green.runner, &img).await?);
+        green.builder.image = Some(green.fetch_digest(&img).await?);
     }
 
+    if builder.is_some() {
+        green.origins.set_env(BUILDX_BUILDER!());
+    }
     green.maybe_setup_builder(builder.cloned()).await?;
     green.maybe_inspect_builder().await?;
 
@@ -137,6 +179,7 @@ pub(crate) async fn main(is_install: bool) -> Result<Green> {
     }
     if let Ok(syntax) = env::var(var) {
         green.syntax = syntax.as_str().try_into().map_err(|e| anyhow!("${var}={syntax:?} {e}"))?;
+        green.origins.set_env(ENV_SYNTAX_IMAGE!());
     }
     if green.syntax.is_empty() {
         // TODO: dynamically lock, if network is up.
@@ -145,7 +188,7 @@ pub(crate) async fn main(is_install: bool) -> Result<Green> {
     // Use local hashed image if one matching exists locally
     green.syntax = green.maybe_lock_image(&green.syntax).await?;
     // otherwise default to a hash found through some Web API
-    green.syntax = fetch_digest(&green.runner, &green.syntax).await?;
+    green.syntax = green.fetch_digest(&green.syntax).await?;
     if !green.syntax.stable_syntax_frontend() {
         // Enforce a known stable syntax + allow pinning to digest
         bail!("${var} must be a digest of {}", SYNTAX_IMAGE.as_str())
@@ -169,6 +212,7 @@ pub(crate) async fn main(is_install: bool) -> Result<Green> {
             fs::create_dir_all(dir).map_err(|e| anyhow!("Failed `mkdir -p {dir}`: {e}"))?;
         }
         green.r#final.path = Some(path);
+        green.origins.set_env(ENV_FINAL_PATH!());
     }
 
     if !green.base.image.locked() {
@@ -177,19 +221,52 @@ pub(crate) async fn main(is_install: bool) -> Result<Green> {
             green.base.image = BASE_IMAGE_LOCKED.clone();
         }
         let base = green.maybe_lock_image(&green.base.image).await?;
-        green.base.image = fetch_digest(&green.runner, &base).await?;
+        green.base.image = green.fetch_digest(&base).await?;
+    }
+    if !green.targets.is_empty() {
+        bail!("'targets' setting cannot be set")
     }
+    green.targets = cross::targets(&env::args().skip(2).collect::<Vec<_>>())?;
+
     let toolchain = env::var("RUSTUP_TOOLCHAIN").expect("$RUSTUP_TOOLCHAIN");
-    green.base = green.base.make_block(&toolchain, &green.components, &green.add)?;
+    let mut toolchain_file = ToolchainFile::find(&pwd(), &toolchain)?;
+    for target in &green.targets {
+        if !toolchain_file.targets.contains(target) {
+            toolchain_file.targets.push(target.clone());
+        }
+    }
+    green.setup_dirs()?;
+    let Some(ref dirs) = green.dirs else { bail!("BUG: dirs were just set up") };
+    let offline = env::var("CARGO_NET_OFFLINE").is_ok_and(|val| val == "1");
+    let host = maybe_get_local_host_triple(&toolchain)?;
+    let dists = rustup::pinned_dists(
+        &dirs.toolchains,
+        &toolchain,
+        &host,
+        &toolchain_file,
+        &green.components,
+        offline,
+    )
+    .await?;
+    green.base = green.base.make_block(
+        &toolchain,
+        &toolchain_file,
+        &green.components,
+        &dists,
+        &green.add,
+        !green.targets.is_empty(),
+    )?;
 
     var = ENV_WITH_NETWORK!();
     if let Ok(val) = env::var(var) {
         green.base.with_network = val.parse().map_err(|e| anyhow!("${var}={val:?} {e}"))?;
+        green.origins.set_env(ENV_WITH_NETWORK!());
     }
     if let Ok(val) = env::var("CARGO_NET_OFFLINE")
         && val == "1"
     {
         green.base.with_network = Network::None;
+        green.origins.set("with-network", Layer::Env("CARGO_NET_OFFLINE"));
     }
 
     // TODO? docker dial-stdio proxy
@@ -215,14 +292,15 @@ pub(crate) async fn main(is_install: bool) -> Result<Green> {
         bail!("${var} can only be set through the environment variable")
     }
     validate_csv(&mut green.experiment, ENV_EXPERIMENT!())?;
+    if env::var_os(var).is_some() {
+        green.origins.set_env(ENV_EXPERIMENT!());
+    }
     let nopes: Vec<_> =
         green.experiment.iter().filter(|ex| !EXPERIMENTS.contains(&ex.as_str())).collect();
     if !nopes.is_empty() {
         bail!("${var} contains unknown experiment names: {nopes:?}")
     }
 
-    green.setup_dirs()?;
-
     Ok(green)
 }
 
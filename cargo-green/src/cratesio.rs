@@ -1,19 +1,24 @@
-use std::fs;
+use std::{fs, time::Duration};
 
 use anyhow::{Result, anyhow, bail};
 use camino::{Utf8Path, Utf8PathBuf};
+use cargo_toml::{SemVer, VersionReq};
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::{
+    PKG, REPO, VSN,
     base_image::rewrite_cargo_home,
+    cache::result::extract_just,
+    dirs::tmp,
     green::Green,
     stage::{AsBlock, AsStage, NamedStage, Stage},
 };
 
 pub(crate) const HOME: &str = "registry/src";
 
-const INDEX: &str = "index.crates.io";
+pub(crate) const INDEX: &str = "index.crates.io";
 
 impl Green {
     pub(crate) fn maybe_arrange_cratesio_index(&self) -> Result<()> {
@@ -145,3 +150,282 @@ ADD --chmod=0664 --unpack --checksum=sha256:{hash} \
 "#
     )
 }
+
+/// Finds which crates.io crate (and maybe version requirement) `cargo install <args>` targets.
+///
+/// Returns `None` when installing from elsewhere (`--git`, `--path`, ...) or more than one crate.
+#[must_use]
+pub(crate) fn crate_to_install(args: &[String]) -> Option<(String, Option<String>)> {
+    const ELSEWHERE: &[&str] = &["--git", "--path", "--index", "--registry"];
+    const WITH_VALUE: &[&str] = &[
+        "--version",
+        "--vers",
+        "--branch",
+        "--tag",
+        "--rev",
+        "--root",
+        "--features",
+        "-F",
+        "--profile",
+        "--target",
+        "--target-dir",
+        "--jobs",
+        "-j",
+        "--bin",
+        "--example",
+        "--config",
+        "-Z",
+        "--color",
+        "--message-format",
+        "--manifest-path",
+    ];
+
+    let mut krates = vec![];
+    let mut version = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with('-') => (flag, Some(value.to_owned())),
+            _ => (arg.as_str(), None),
+        };
+        if ELSEWHERE.contains(&flag) {
+            return None;
+        }
+        if WITH_VALUE.contains(&flag) {
+            let value = value.or_else(|| args.next().cloned());
+            if ["--version", "--vers"].contains(&flag) {
+                version = value;
+            }
+            continue;
+        }
+        if flag.starts_with('-') {
+            continue;
+        }
+        krates.push(arg.clone());
+    }
+
+    let [krate] = &krates[..] else { return None };
+    match krate.split_once('@') {
+        Some((name, vsn)) => Some((name.to_owned(), Some(vsn.to_owned()))),
+        None => Some((krate.clone(), version)),
+    }
+}
+
+/// Reads the `Cargo.toml` of a published crate, either from Cargo's cache or from crates.io.
+///
+/// Returns the version picked as well.
+pub(crate) async fn packaged_manifest(
+    cargo_home: &Utf8Path,
+    name: &str,
+    version: Option<&str>,
+) -> Result<(String, String, Option<Utf8PathBuf>)> {
+    let exact = version.and_then(|vsn| SemVer::parse(vsn.trim_start_matches('=')).ok());
+    let version = match exact {
+        Some(vsn) => vsn.to_string(),
+        None => latest_version(name, version.unwrap_or("*")).await?,
+    };
+    let name_dash_version = format!("{name}-{version}");
+    let fname = format!("{name_dash_version}/Cargo.toml");
+    let checksum = index_checksum(cargo_home, name, &version).await?;
+
+    if let Some(cached) = cached_crate(cargo_home, &name_dash_version)? {
+        info!("reading manifest from {cached}");
+        let tarball = fs::read(&cached).map_err(|e| anyhow!("Failed reading {cached}: {e}"))?;
+        check_checksum(&tarball, &checksum, &cached)?;
+        let manifest = extract_just(&cached, &fname).await?;
+        let manifest = String::from_utf8(manifest)
+            .map_err(|e| anyhow!("Corrupted {fname} in {cached}: {e}"))?;
+        return Ok((version, manifest, Some(cached)));
+    }
+
+    let url = format!("https://static.crates.io/crates/{name}/{name_dash_version}.crate");
+    info!("GETing {url}");
+    let tarball = http_client()?
+        .get(&url)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| anyhow!("Failed fetching {url}: {e}"))?
+        .bytes()
+        .await
+        .map_err(|e| anyhow!("Failed reading {url}: {e}"))?;
+    check_checksum(&tarball, &checksum, &url)?;
+
+    let tmp = tmp().join(format!("{}.crate", Uuid::new_v4()));
+    fs::write(&tmp, &tarball).map_err(|e| anyhow!("Failed writing {tmp}: {e}"))?;
+    let manifest = extract_just(&tmp, &fname).await;
+    let _ = fs::remove_file(&tmp);
+    let manifest =
+        String::from_utf8(manifest?).map_err(|e| anyhow!("Corrupted {fname} from {url}: {e}"))?;
+    Ok((version, manifest, None))
+}
+
+fn check_checksum(tarball: &[u8], expected: &str, from: impl std::fmt::Display) -> Result<()> {
+    let actual = sha256::digest(tarball);
+    if actual != expected {
+        bail!("Checksum of {from} is {actual}, but the index says {expected}")
+    }
+    Ok(())
+}
+
+/// Path of a crate's file within the index,
+/// see <https://doc.rust-lang.org/cargo/reference/registry-index.html#index-files>
+fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{name}"),
+        2 => format!("2/{name}"),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    }
+}
+
+/// Reads the checksum of a crate's `.crate` from Cargo's copy of the crates.io index,
+/// or else from the index itself.
+async fn index_checksum(cargo_home: &Utf8Path, name: &str, version: &str) -> Result<String> {
+    let path = index_path(name);
+
+    let index = cargo_home.join("registry/index");
+    if index.exists() {
+        for entry in index.read_dir_utf8().map_err(|e| anyhow!("Failed `ls {index}`: {e}"))? {
+            let Ok(entry) = entry else { continue };
+            if !entry.file_name().starts_with(INDEX) {
+                continue;
+            }
+            // Cargo's cache files hold the index lines, NUL-separated along with their versions
+            let cached = entry.path().join(".cache").join(&path);
+            let Ok(txt) = fs::read(&cached) else { continue };
+            if let Some(checksum) = find_checksum(txt.split(|&b| b == 0 || b == b'\n'), version) {
+                debug!("read checksum of {name}@{version} from {cached}");
+                return Ok(checksum);
+            }
+        }
+    }
+
+    let url = format!("https://index.crates.io/{path}");
+    info!("GETing {url}");
+    let txt = http_client()?
+        .get(&url)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| anyhow!("Failed fetching {url}: {e}"))?
+        .text()
+        .await
+        .map_err(|e| anyhow!("Failed reading {url}: {e}"))?;
+    find_checksum(txt.lines().map(str::as_bytes), version)
+        .ok_or_else(|| anyhow!("No {name}@{version} in {url}"))
+}
+
+fn find_checksum<'a>(lines: impl Iterator<Item = &'a [u8]>, version: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Entry {
+        vers: String,
+        cksum: String,
+    }
+    lines
+        .filter_map(|line| serde_json::from_slice::<Entry>(line).ok())
+        .find(|Entry { vers, .. }| vers == version)
+        .map(|Entry { cksum, .. }| cksum)
+}
+
+/// Looks for `$CARGO_HOME/registry/cache/index.crates.io-*/<name>-<version>.crate`
+fn cached_crate(cargo_home: &Utf8Path, name_dash_version: &str) -> Result<Option<Utf8PathBuf>> {
+    let cache = cargo_home.join("registry/cache");
+    if !cache.exists() {
+        return Ok(None);
+    }
+    for entry in cache.read_dir_utf8().map_err(|e| anyhow!("Failed `ls {cache}`: {e}"))? {
+        let Ok(entry) = entry else { continue };
+        if !entry.file_name().starts_with(INDEX) {
+            continue;
+        }
+        let cached = entry.path().join(format!("{name_dash_version}.crate"));
+        if cached.is_file() {
+            return Ok(Some(cached));
+        }
+    }
+    Ok(None)
+}
+
+/// Asks crates.io for the greatest non-yanked version matching `req`
+async fn latest_version(name: &str, req: &str) -> Result<String> {
+    let req = VersionReq::parse(req).map_err(|e| anyhow!("Bad version of {name}: {e}"))?;
+
+    let url = format!("https://crates.io/api/v1/crates/{name}/versions");
+    info!("GETing {url}");
+    let txt = http_client()?
+        .get(&url)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| anyhow!("Failed fetching {url}: {e}"))?
+        .text()
+        .await
+        .map_err(|e| anyhow!("Failed reading {url}: {e}"))?;
+
+    #[derive(Deserialize)]
+    struct Versions {
+        versions: Vec<Version>,
+    }
+    #[derive(Deserialize)]
+    struct Version {
+        num: String,
+        yanked: bool,
+    }
+    let Versions { versions } =
+        serde_json::from_str(&txt).map_err(|e| anyhow!("Failed decoding {url}: {e}"))?;
+
+    versions
+        .into_iter()
+        .filter(|Version { yanked, .. }| !yanked)
+        .filter_map(|Version { num, .. }| SemVer::parse(&num).ok())
+        .filter(|vsn| req.matches(vsn))
+        .max()
+        .map(|vsn| vsn.to_string())
+        .ok_or_else(|| anyhow!("No version of {name} matches {req}"))
+}
+
+fn http_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(4))
+        .user_agent(format!("{PKG}/{VSN} ({REPO})"))
+        .build()
+        .map_err(|e| anyhow!("HTTP client's config/TLS failed: {e}"))
+}
+
+#[cfg(test)]
+#[test_case::test_case("ripgrep", Some(("ripgrep", None)); "name")]
+#[test_case::test_case("ripgrep@14.1.1", Some(("ripgrep", Some("14.1.1"))); "at version")]
+#[test_case::test_case("--locked ripgrep --version ^14", Some(("ripgrep", Some("^14"))); "version flag")]
+#[test_case::test_case("--vers=14.1.1 -F pcre2 ripgrep --force", Some(("ripgrep", Some("14.1.1"))); "with values")]
+#[test_case::test_case("--git https://github.com/BurntSushi/ripgrep ripgrep", None; "git")]
+#[test_case::test_case("--path .", None; "path")]
+#[test_case::test_case("ripgrep fd-find", None; "many")]
+#[test_case::test_case("--list", None; "none")]
+fn installing(args: &str, expected: Option<(&str, Option<&str>)>) {
+    let args: Vec<_> = args.split_whitespace().map(ToOwned::to_owned).collect();
+    let expected = expected.map(|(name, vsn)| (name.to_owned(), vsn.map(ToOwned::to_owned)));
+    assert_eq!(crate_to_install(&args), expected);
+}
+
+#[cfg(test)]
+#[test_case::test_case("a", "1/a"; "one")]
+#[test_case::test_case("cc", "2/cc"; "two")]
+#[test_case::test_case("syn", "3/s/syn"; "three")]
+#[test_case::test_case("Serde_JSON", "se/rd/serde_json"; "more")]
+fn index_paths(name: &str, path: &str) {
+    assert_eq!(index_path(name), path);
+}
+
+#[test]
+fn finds_checksums() {
+    // As in $CARGO_HOME/registry/index/index.crates.io-*/.cache/3/s/syn
+    let cached = b"\x03\x00\x00\x00etag: W/\"1\"\x002.0.1\x00{\"name\":\"syn\",\"vers\":\"2.0.1\",\"cksum\":\"c1\"}\x002.0.2\x00{\"name\":\"syn\",\"vers\":\"2.0.2\",\"cksum\":\"c2\"}\x00";
+    let lines = || cached.split(|&b| b == 0 || b == b'\n');
+    assert_eq!(find_checksum(lines(), "2.0.2").as_deref(), Some("c2"));
+    assert_eq!(find_checksum(lines(), "2.0.3"), None);
+
+    assert!(check_checksum(b"", &sha256::digest(b""), "empty").is_ok());
+    assert!(check_checksum(b"tampered", &sha256::digest(b""), "tampered").is_err());
+}
@@ -0,0 +1,109 @@
+use std::{collections::HashSet, env};
+
+use anyhow::{Result, bail};
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::REPO;
+
+/// Finds the `--target`s cargo was given, or else `$CARGO_BUILD_TARGET`
+pub(crate) fn targets(args: &[String]) -> Result<Vec<String>> {
+    let mut targets = vec![];
+    let mut args = args.iter().take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        if arg == "--target" {
+            targets.extend(args.next().cloned());
+        } else if let Some(target) = arg.strip_prefix("--target=") {
+            targets.push(target.to_owned());
+        }
+    }
+    if targets.is_empty()
+        && let Ok(target) = env::var("CARGO_BUILD_TARGET")
+    {
+        targets.push(target);
+    }
+
+    let mut platforms = HashSet::new();
+    let mut deduped = vec![];
+    for target in targets {
+        let Some(platform) = platform(&target) else {
+            bail!("Unhandled --target {target:?}, please report to {REPO}")
+        };
+        if deduped.contains(&target) {
+            continue;
+        }
+        if !platforms.insert(platform) {
+            bail!("More than one --target maps to BuildKit platform {platform}")
+        }
+        deduped.push(target);
+    }
+    Ok(deduped)
+}
+
+/// Maps a Rust target triple to a BuildKit platform
+#[must_use]
+pub(crate) fn platform(triple: &str) -> Option<&'static str> {
+    let (arch, os) = triple.split_once('-')?;
+    if !os.starts_with("unknown-linux-") {
+        return None;
+    }
+    Some(match arch {
+        "x86_64" => "linux/amd64",
+        "aarch64" => "linux/arm64",
+        "armv7" => "linux/arm/v7",
+        "arm" => "linux/arm/v6",
+        "i586" | "i686" => "linux/386",
+        "riscv64gc" => "linux/riscv64",
+        "powerpc64le" => "linux/ppc64le",
+        "s390x" => "linux/s390x",
+        _ => return None,
+    })
+}
+
+/// Name of the linker the base image provides for this target
+#[must_use]
+pub(crate) fn linker(triple: &str) -> String {
+    format!("{triple}-clang")
+}
+
+/// Where cargo puts host artifacts (build scripts, proc-macros) when cross-compiling
+///
+/// E.g. `$CARGO_TARGET_DIR/aarch64-unknown-linux-gnu/debug` => `$CARGO_TARGET_DIR/debug`
+#[must_use]
+pub(crate) fn host_target_path(target_path: &Utf8Path, triple: &str) -> Option<Utf8PathBuf> {
+    let profile = target_path.file_name()?;
+    let dir = target_path.parent()?;
+    (dir.file_name()? == triple).then(|| dir.parent()).flatten().map(|dir| dir.join(profile))
+}
+
+#[cfg(test)]
+#[test_case::test_case("build --target aarch64-unknown-linux-gnu", &["aarch64-unknown-linux-gnu"]; "flag")]
+#[test_case::test_case("build --release --target=armv7-unknown-linux-gnueabihf", &["armv7-unknown-linux-gnueabihf"]; "flag equals")]
+#[test_case::test_case("build --target aarch64-unknown-linux-gnu --target x86_64-unknown-linux-gnu", &["aarch64-unknown-linux-gnu", "x86_64-unknown-linux-gnu"]; "several")]
+#[test_case::test_case("build --target aarch64-unknown-linux-gnu --target aarch64-unknown-linux-gnu", &["aarch64-unknown-linux-gnu"]; "repeated")]
+#[test_case::test_case("build", &[]; "none")]
+#[test_case::test_case("run -- --target x86_64-unknown-linux-gnu", &[]; "after dashdash")]
+fn cli_targets(args: &str, expected: &[&str]) {
+    let args: Vec<_> = args.split_whitespace().map(ToOwned::to_owned).collect();
+    temp_env::with_var_unset("CARGO_BUILD_TARGET", || {
+        assert_eq!(targets(&args).unwrap(), expected);
+    });
+}
+
+#[cfg(test)]
+#[test_case::test_case("build --target x86_64-apple-darwin", "Unhandled"; "not linux")]
+#[test_case::test_case("build --target aarch64-unknown-linux-gnu --target aarch64-unknown-linux-musl", "linux/arm64"; "same platform")]
+fn bad_targets(args: &str, reason: &str) {
+    let args: Vec<_> = args.split_whitespace().map(ToOwned::to_owned).collect();
+    let err = targets(&args).unwrap_err().to_string();
+    assert!(err.contains(reason), "In: {err}");
+}
+
+#[test]
+fn host_target_paths() {
+    let triple = "aarch64-unknown-linux-gnu";
+    assert_eq!(
+        host_target_path("/target/aarch64-unknown-linux-gnu/debug".into(), triple),
+        Some("/target/debug".into())
+    );
+    assert_eq!(host_target_path("/target/debug".into(), triple), None);
+}
@@ -11,6 +11,13 @@ pub(crate) fn tmp() -> Utf8PathBuf {
     env::temp_dir().try_into().expect("$TMPDIR is not utf-8")
 }
 
+/// Stands in for `docker build -`'s absence of a filesystem context
+pub(crate) fn empty_context() -> Result<Utf8PathBuf> {
+    let dir = tmp().join("cargo-green-empty-context");
+    fs::create_dir_all(&dir).map_err(|e| anyhow!("Failed creating {dir}: {e}"))?;
+    Ok(dir)
+}
+
 pub(crate) fn pwd() -> Utf8PathBuf {
     env::current_dir()
         .expect("$PWD does not exist or is otherwise unreadable")
@@ -80,6 +87,10 @@ pub(crate) struct Dirs {
     /// <https://docs.docker.com/build/cache/backends/local/>
     #[doc(hidden)]
     pub(crate) buildkit: Utf8PathBuf,
+
+    /// A place for toolchain tarballs pinned from channel manifests
+    #[doc(hidden)]
+    pub(crate) toolchains: Utf8PathBuf,
 }
 
 impl Green {
@@ -105,7 +116,11 @@ impl Green {
         fs::create_dir_all(&buildkit)
             .map_err(|e| anyhow!("Failed to `mkdir -p {buildkit}`: {e}"))?;
 
-        self.dirs = Some(Dirs { tmp, results, buildkit });
+        let toolchains = app_cache_dir.join("toolchains");
+        fs::create_dir_all(&toolchains)
+            .map_err(|e| anyhow!("Failed to `mkdir -p {toolchains}`: {e}"))?;
+
+        self.dirs = Some(Dirs { tmp, results, buildkit, toolchains });
         Ok(())
     }
 }
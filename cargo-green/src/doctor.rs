@@ -0,0 +1,459 @@
+//! `cargo green supergreen doctor`: checks, one by one, what builds rely on.
+//!
+//! Exits non-zero when a check fails, so CI can gate on it. With `--bundle`, also prints
+//! versions, builders and settings (with home directory and credentials redacted) for bug reports.
+
+use std::{env, fmt};
+
+use anyhow::{Result, bail};
+use camino::Utf8Path;
+use indexmap::IndexSet;
+
+use crate::{
+    EEXIT, PKG, VSN,
+    base_image::{BASE_IMAGE, CARGO_HOME},
+    builder::{BuilderHealth, LATEST_BUILDKIT},
+    cratesio::{HOME, INDEX},
+    dirs::cargo_home,
+    ext::CommandExt,
+    green::Green,
+    image_uri::ImageUri,
+    registry::ping,
+    runner::Runner,
+    supergreen::all_envs,
+    wrap::safeify,
+};
+
+/// Less free space than this fails builds sooner or later
+const DISK_FAIL_KIB: u64 = 1 << 20;
+/// Less free space than this makes for a short-lived cache
+const DISK_WARN_KIB: u64 = 10 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Check {
+    status: Status,
+    what: String,
+    detail: String,
+}
+
+impl Check {
+    fn pass(what: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { status: Status::Pass, what: what.into(), detail: detail.into() }
+    }
+
+    fn warn(what: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { status: Status::Warn, what: what.into(), detail: detail.into() }
+    }
+
+    fn fail(what: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { status: Status::Fail, what: what.into(), detail: detail.into() }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.status {
+            Status::Pass => "[ ok ]",
+            Status::Warn => "[warn]",
+            Status::Fail => "[FAIL]",
+        };
+        let detail = self.detail.lines().filter(|line| !line.trim().is_empty());
+        let detail = detail.collect::<Vec<_>>().join("\n         ");
+        write!(f, "{status} {}: {detail}", self.what)
+    }
+}
+
+/// Runs even when `setup` failed, falling back to what can be read from the environment.
+pub(crate) async fn main(setup: Result<Green>, bundle: bool) -> Result<()> {
+    let configured = Green::new_from_env_then_manifest(false).await.ok();
+    let images = configured.as_ref().map(configured_images).unwrap_or_default();
+
+    let mut checks = vec![];
+    let green = match setup {
+        Ok(green) => {
+            checks.push(Check::pass("configuration", "read and applied"));
+            green
+        }
+        Err(e) => {
+            checks.push(Check::fail("configuration", format!("{e:#}")));
+            fallback(configured)
+        }
+    };
+
+    checks.push(check_cargo_home(&green.cargo_home));
+    checks.push(check_cratesio_index(&green.cargo_home));
+    if green.runner.is_none() {
+        checks.push(Check::pass("runner", "none: nothing to check"));
+    } else {
+        checks.push(green.check_runner().await);
+        if green.runner.is_buildx() {
+            checks.push(green.check_buildx().await);
+        }
+        checks.push(green.check_builder().await);
+    }
+    checks.push(green.check_disk_space().await);
+    checks.extend(green.check_registries().await);
+    checks.extend(images.iter().map(|(key, img)| check_locked(key, img)));
+
+    for check in &checks {
+        println!("{check}");
+    }
+    let count = |status| checks.iter().filter(|check| check.status == status).count();
+    let (warned, failed) = (count(Status::Warn), count(Status::Fail));
+    println!();
+    println!("{} passed, {warned} warned, {failed} failed", checks.len() - warned - failed);
+
+    if bundle {
+        let home = home::home_dir().and_then(|home| home.to_str().map(ToOwned::to_owned));
+        println!();
+        println!("{}", redact(&green.bundle().await?, home.as_deref()));
+    }
+
+    if failed != 0 {
+        bail!(EEXIT)
+    }
+    Ok(())
+}
+
+/// Settings and runner as `cargo_green::main` would read them, minus the failing parts
+fn fallback(configured: Option<Green>) -> Green {
+    let mut green = configured.unwrap_or_default();
+    green.runner =
+        env::var(ENV_RUNNER!()).ok().and_then(|runner| runner.parse().ok()).unwrap_or_default();
+    green.runner_envs = green.runner.envs();
+    green.cargo_home = cargo_home().unwrap_or_default();
+    if green.runner.is_buildx() {
+        let name = green.runner_envs.get(BUILDX_BUILDER!()).map_or("supergreen", String::as_str);
+        green.builder.name = (!name.is_empty()).then(|| name.to_owned());
+    }
+    let _ = green.setup_dirs();
+    green
+}
+
+/// See [`Green::setup`]
+fn check_cargo_home(host: &Utf8Path) -> Check {
+    let what = format!("{CARGO_HOME} symlink");
+    let guest = Utf8Path::new(CARGO_HOME);
+    let setup = "run `cargo green supergreen setup`";
+    if guest.canonicalize_utf8().is_ok_and(|guest| host.canonicalize_utf8().ok() == Some(guest)) {
+        return Check::pass(what, format!("resolves to {host}"));
+    }
+    match guest.read_link_utf8() {
+        Ok(target) => Check::fail(what, format!("points to {target}, not {host}: {setup}")),
+        Err(_) if guest.exists() => Check::warn(what, format!("exists but is not {host}")),
+        Err(_) => Check::fail(what, format!("missing: {setup}")),
+    }
+}
+
+/// See [`Green::maybe_arrange_cratesio_index`]
+fn check_cratesio_index(cargo_home: &Utf8Path) -> Check {
+    let what = "crates.io index symlink";
+    let crates_home = cargo_home.join(HOME);
+    let link = crates_home.join(INDEX);
+    let indices = crates_home
+        .read_dir_utf8()
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .any(|entry| entry.file_name().starts_with(INDEX) && entry.file_name() != INDEX);
+    match link.read_link_utf8() {
+        Ok(target) if link.exists() => Check::pass(what, format!("{link} -> {target}")),
+        Ok(target) => Check::fail(
+            what,
+            format!("{link} points to missing {target}: run `cargo green supergreen setup`"),
+        ),
+        Err(_) if !indices => Check::pass(what, "no crates downloaded yet"),
+        Err(_) => Check::fail(what, format!("missing {link}: run `cargo green supergreen setup`")),
+    }
+}
+
+impl Green {
+    async fn check_runner(&self) -> Check {
+        let what = format!("runner {}", self.runner);
+        match self.exec_for_doctor(&["--version"]).await {
+            Ok(version) => Check::pass(what, version),
+            Err(e) => Check::fail(what, e.to_string()),
+        }
+    }
+
+    async fn check_buildx(&self) -> Check {
+        match self.exec_for_doctor(&["buildx", "version"]).await {
+            Ok(version) => Check::pass("buildx", version),
+            Err(e) => Check::fail("buildx", e.to_string()),
+        }
+    }
+
+    /// Reachability of the builder (or daemon) and, through buildx, its driver and BuildKit version
+    async fn check_builder(&self) -> Check {
+        let args: &[&str] = match self.runner {
+            Runner::Docker => {
+                let Some(ref name) = self.builder.name else {
+                    return Check::pass("builder", "none: builds use the daemon's own BuildKit");
+                };
+                let what = format!("builder {name}");
+                return match self.buildx_builder_health(name).await {
+                    Ok(health) => check_health(what, &health),
+                    Err(e) => Check::fail(what, e.to_string()),
+                };
+            }
+            Runner::Podman | Runner::Nerdctl => &["info"],
+            Runner::Buildkitd => &["debug", "workers"],
+            Runner::None => return Check::pass("builder", "none"),
+        };
+        match self.exec_for_doctor(args).await {
+            Ok(_) => {
+                Check::pass("builder", format!("{} answers `{}`", self.runner, args.join(" ")))
+            }
+            Err(e) => Check::fail("builder", e.to_string()),
+        }
+    }
+
+    async fn check_disk_space(&self) -> Check {
+        let what = "disk space";
+        let Some(ref dirs) = self.dirs else {
+            return Check::fail(what, "cache directories could not be set up");
+        };
+        let mut cmd = tokio::process::Command::new("df");
+        cmd.arg("-Pk").args([&dirs.tmp, &dirs.results, &dirs.buildkit]);
+        let stdout = match cmd.exec().await {
+            Ok((true, stdout, _)) => stdout,
+            Ok((false, _, stderr)) => {
+                return Check::fail(what, String::from_utf8_lossy(&stderr).into_owned());
+            }
+            Err(e) => return Check::fail(what, e.to_string()),
+        };
+        check_free_space(&String::from_utf8_lossy(&stdout))
+    }
+
+    /// Hosts of cache images, along with those of `registries`
+    async fn check_registries(&self) -> Vec<Check> {
+        let cache = &self.cache;
+        let hosts: IndexSet<_> = (cache.images.iter())
+            .chain(cache.from_images.iter())
+            .chain(cache.to_images.iter())
+            .map(ImageUri::host)
+            .chain(self.builder.registries.iter().flat_map(|(host, registry)| {
+                [host.as_str()].into_iter().chain(registry.mirrors.iter().map(String::as_str))
+            }))
+            .collect();
+
+        let mut checks = vec![];
+        for host in hosts {
+            let what = format!("registry {host}");
+            checks.push(match ping(host, self.builder.registries.get(host)).await {
+                Ok(status) => Check::pass(what, format!("answers {status}")),
+                Err(e) => Check::fail(what, e.to_string()),
+            });
+        }
+        checks
+    }
+
+    /// Trimmed STDOUT of a successful runner call
+    async fn exec_for_doctor(&self, args: &[&str]) -> Result<String> {
+        let mut cmd = self.cmd()?;
+        cmd.args(args);
+        let (succeeded, stdout, stderr) = cmd.exec().await?;
+        if !succeeded {
+            let stderr = String::from_utf8_lossy(&stderr);
+            bail!("`{} {}` failed: {}", self.runner, args.join(" "), stderr.trim())
+        }
+        Ok(String::from_utf8_lossy(&stdout).trim().to_owned())
+    }
+
+    /// Versions, builders and settings, unredacted
+    async fn bundle(&self) -> Result<String> {
+        let mut bundle = format!("{PKG} v{VSN} on {}/{}\n", env::consts::OS, env::consts::ARCH);
+
+        let calls: &[&[&str]] = match self.runner {
+            Runner::Docker => {
+                &[&["--version"], &["buildx", "version"], &["buildx", "ls"], &["info"]]
+            }
+            Runner::Podman | Runner::Nerdctl => &[&["--version"], &["info"]],
+            Runner::Buildkitd => &[&["--version"], &["debug", "workers"]],
+            Runner::None => &[],
+        };
+        for args in calls {
+            bundle.push_str(&format!("\n$ {} {}\n", self.runner, args.join(" ")));
+            match self.exec_for_doctor(args).await {
+                Ok(stdout) => bundle.push_str(&stdout),
+                Err(e) => bundle.push_str(&e.to_string()),
+            }
+            bundle.push('\n');
+        }
+
+        bundle.push_str("\n$ cargo green supergreen env\n");
+        for (var, _, val) in all_envs(self) {
+            bundle.push_str(&format!("{var}={}\n", safeify(val.as_deref().unwrap_or_default())?));
+        }
+        Ok(bundle)
+    }
+}
+
+fn check_health(
+    what: String,
+    BuilderHealth { driver, versions, up_to_date }: &BuilderHealth,
+) -> Check {
+    let detail = format!("driver {driver}, BuildKit {}", versions.join(", "));
+    if *up_to_date {
+        return Check::pass(what, detail);
+    }
+    let latest = LATEST_BUILDKIT.as_str();
+    Check::warn(
+        what,
+        format!("{detail}: older than v{latest}, try `cargo green supergreen builder recreate`"),
+    )
+}
+
+/// Reads the output of `df -P`: the least free space among distinct mount points
+fn check_free_space(df: &str) -> Check {
+    let what = "disk space";
+    let mut mounts: Vec<(&str, u64)> = df
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let cols: Vec<_> = line.split_whitespace().collect();
+            Some((*cols.get(5)?, cols.get(3)?.parse().ok()?))
+        })
+        .collect();
+    mounts.sort();
+    mounts.dedup();
+    let Some(&(mount, kib)) = mounts.iter().min_by_key(|(_, kib)| *kib) else {
+        return Check::fail(what, format!("unexpected `df` output: {df:?}"));
+    };
+    let detail = format!("{:.1}GiB free on {mount}", kib as f64 / (1 << 20) as f64);
+    match kib {
+        _ if kib < DISK_FAIL_KIB => Check::fail(what, detail),
+        _ if kib < DISK_WARN_KIB => Check::warn(what, detail),
+        _ => Check::pass(what, detail),
+    }
+}
+
+/// Images set by users, as read before being locked in memory
+fn configured_images(configured: &Green) -> Vec<(&'static str, ImageUri)> {
+    let mut images = vec![];
+    if configured.base.image.as_str() != BASE_IMAGE.as_str() {
+        images.push(("base-image", configured.base.image.clone()));
+    }
+    let envs = [("builder-image", ENV_BUILDER_IMAGE!()), ("syntax", ENV_SYNTAX_IMAGE!())];
+    for (key, var) in envs {
+        if let Ok(img) = env::var(var)
+            && let Ok(img) = ImageUri::try_new(img)
+        {
+            images.push((key, img));
+        }
+    }
+    if let Some(ref img) = configured.builder.image
+        && !images.iter().any(|(key, _)| *key == "builder-image")
+    {
+        images.push(("builder-image", img.clone()));
+    }
+    images
+}
+
+fn check_locked(key: &str, img: &ImageUri) -> Check {
+    let what = format!("{key} lock");
+    if img.locked() {
+        return Check::pass(what, img.as_str());
+    }
+    Check::warn(
+        what,
+        format!("{img} is not pinned to a digest, try `cargo green supergreen update-images`"),
+    )
+}
+
+/// Hides the home directory and credentials that URLs or `docker info` may show
+fn redact(txt: &str, home: Option<&str>) -> String {
+    let mut txt = txt.to_owned();
+    if let Some(home) = home.filter(|home| home.len() > 1) {
+        txt = txt.replace(home, "~");
+    }
+
+    let mut redacted = String::with_capacity(txt.len());
+    let mut rest = txt.as_str();
+    while let Some(at) = rest.find("://") {
+        let (head, tail) = rest.split_at(at + "://".len());
+        redacted.push_str(head);
+        let end = tail
+            .find(|c: char| c.is_whitespace() || ['/', '"', '\'', ',', ';'].contains(&c))
+            .unwrap_or(tail.len());
+        match tail[..end].rfind('@') {
+            Some(userinfo) => {
+                redacted.push_str("REDACTED");
+                rest = &tail[userinfo..];
+            }
+            None => rest = tail,
+        }
+    }
+    redacted.push_str(rest);
+
+    redacted
+        .lines()
+        .map(|line| match line.split_once("Username:") {
+            Some((indent, _)) if indent.trim().is_empty() => format!("{indent}Username: REDACTED"),
+            _ => line.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports() {
+        assert_eq!(Check::pass("buildx", "v0.23.0\n").to_string(), "[ ok ] buildx: v0.23.0");
+        assert_eq!(
+            Check::fail("builder", "one\ntwo").to_string(),
+            "[FAIL] builder: one\n         two"
+        );
+    }
+
+    #[test]
+    fn free_space() {
+        let df = "Filesystem     1024-blocks      Used Available Capacity Mounted on
+/dev/nvme0n1p2   490617784 401287892  64329676      87% /
+/dev/nvme0n1p2   490617784 401287892  64329676      87% /
+tmpfs             16318744    112344    204800       1% /tmp
+";
+        assert_eq!(check_free_space(df), Check::fail("disk space", "0.2GiB free on /tmp"));
+        let df = df.replace("    204800", "   5242880");
+        assert_eq!(check_free_space(&df), Check::warn("disk space", "5.0GiB free on /tmp"));
+        let df = df.replace("   5242880", "  52428800");
+        assert_eq!(check_free_space(&df), Check::pass("disk space", "50.0GiB free on /tmp"));
+        assert_eq!(check_free_space("").status, Status::Fail);
+    }
+
+    #[test]
+    fn locked_images() {
+        let img = ImageUri::try_new("docker-image://docker.io/library/rust:1-slim").unwrap();
+        assert_eq!(check_locked("base-image", &img).status, Status::Warn);
+        let img = img.lock(&format!("sha256:{}", "0".repeat(64)));
+        assert_eq!(check_locked("base-image", &img).status, Status::Pass);
+    }
+
+    #[test]
+    fn redacts() {
+        let txt = "CARGOGREEN_LOG_PATH=/home/me/.cache/x.log
+CARGOGREEN_REMOTES='ssh://me@box;name=b,host=tcp://u:p@b:2376'
+ Username: me
+ Registry: https://index.docker.io/v1/
+CARGOGREEN_CACHE_IMAGES=docker-image://ghcr.io/me/cache
+";
+        assert_eq!(
+            redact(txt, Some("/home/me")),
+            "CARGOGREEN_LOG_PATH=~/.cache/x.log
+CARGOGREEN_REMOTES='ssh://REDACTED@box;name=b,host=tcp://REDACTED@b:2376'
+ Username: REDACTED
+ Registry: https://index.docker.io/v1/
+CARGOGREEN_CACHE_IMAGES=docker-image://ghcr.io/me/cache"
+        );
+    }
+}
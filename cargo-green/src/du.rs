@@ -2,9 +2,9 @@ use std::sync::OnceLock;
 
 use anyhow::{Result, bail};
 use chrono::{DateTime, FixedOffset};
-use log::{info, warn};
+use log::warn;
 
-use crate::{ext::CommandExt, green::Green};
+use crate::{ext::CommandExt, green::Green, runner::Runner};
 
 #[derive(Debug, Default)]
 pub(crate) struct Du {
@@ -73,14 +73,18 @@ impl Green {
             return Ok(got);
         }
 
-        if self.runner.is_none() {
-            info!("Skipping inspecting builder cache (runner:{})", self.runner);
-            let _ = ARRAY.set(vec![]);
-            return Ok(ARRAY.get().unwrap());
-        }
+        let got = self.backend().disk_usage(self).await?;
+        let _ = ARRAY.set(got);
+        Ok(ARRAY.get().unwrap())
+    }
 
+    /// Lists images pulled into the runner's build cache
+    pub(crate) async fn runner_du(&self) -> Result<Vec<Du>> {
         let mut cmd = self.cmd()?;
-        cmd.args(["buildx", "du", "--verbose"]);
+        if self.runner.is_buildx() {
+            cmd.arg("buildx");
+        }
+        cmd.args(["du", "--verbose"]);
         cmd.arg("--filter=type=regular");
         cmd.arg("--filter=description~=pulled.from");
         let (succeeded, stdout, stderr) = cmd.exec().await?;
@@ -88,9 +92,27 @@ impl Green {
             let stderr = String::from_utf8_lossy(&stderr);
             bail!("Failed to query builder cache: {stderr}")
         }
-        let got = parse_images(&stdout);
-        let _ = ARRAY.set(got);
-        Ok(ARRAY.get().unwrap())
+        Ok(parse_images(&stdout))
+    }
+
+    /// Frees the runner's build cache, internal and frontend records too with `all`
+    pub(crate) async fn runner_prune(&self, all: bool) -> Result<()> {
+        let mut cmd = self.cmd()?;
+        match self.runner {
+            Runner::Docker => cmd.args(["buildx", "prune", "--force"]),
+            Runner::Nerdctl => cmd.args(["builder", "prune", "--force"]),
+            Runner::Buildkitd => cmd.arg("prune"),
+            Runner::Podman | Runner::None => bail!("BUG: no builder cache to prune"),
+        };
+        if all {
+            cmd.arg("--all");
+        }
+        let (succeeded, _, stderr) = cmd.exec().await?;
+        if !succeeded {
+            let stderr = String::from_utf8_lossy(&stderr);
+            bail!("Failed to prune builder cache: {stderr}")
+        }
+        Ok(())
     }
 }
 
@@ -3,6 +3,8 @@ use std::{ffi::OsStr, process::Output};
 use anyhow::{Result, anyhow};
 use log::info;
 
+use crate::progress;
+
 pub(crate) trait Popped: Clone {
     #[must_use]
     fn pop(&mut self) -> bool;
@@ -47,7 +49,9 @@ impl CommandExt for tokio::process::Command {
         let envs = self.envs_string(except_envs);
 
         info!("Calling {envs} {call}");
-        eprintln!("Calling {envs} {call}");
+        if !progress::is_shown() {
+            eprintln!("Calling {envs} {call}");
+        }
 
         let Output { status, stdout, stderr } =
             self.output().await.map_err(|e| anyhow!("Failed to spawn {envs} {call}: {e}"))?;
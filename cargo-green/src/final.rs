@@ -1,16 +1,17 @@
 use std::{
     env,
     fs::{self, OpenOptions},
-    io::Write,
+    io::{ErrorKind, Write},
 };
 
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
 use camino::{Utf8Path, Utf8PathBuf};
 use indexmap::IndexSet;
 use log::info;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    cross::platform,
     green::Green,
     md::{BuildContext, DIESES, Md},
 };
@@ -38,13 +39,16 @@ pub(crate) fn is_primary() -> bool {
 
 impl Green {
     // NOTE: using $CARGO_PRIMARY_PACKAGE still makes >1 hits in rustc calls history: lib + bin, at least.
-    fn should_write_final_path(&self) -> Option<&Utf8Path> {
+    fn should_write_final_path(&self, target_path: &Utf8Path) -> Result<Option<Utf8PathBuf>> {
         if let Some(path) = self.r#final.path.as_deref()
             && (self.finalpathnonprimary() || is_primary())
         {
-            return Some(path);
+            if let Some(ref target) = self.target {
+                return platform_part(path, target_path, target, target).map(Some);
+            }
+            return Ok(Some(path.to_owned()));
         }
-        None
+        Ok(None)
     }
 
     pub(crate) fn maybe_write_final_path(
@@ -54,13 +58,14 @@ impl Green {
         call: &str,
         envs: &str,
     ) -> Result<()> {
-        if let Some(path) = self.should_write_final_path() {
+        let target_path = containerfile.parent().unwrap_or(containerfile);
+        if let Some(path) = self.should_write_final_path(target_path)? {
             let mut fbuf = String::new();
 
             info!("reading (RO) containerfile {containerfile}");
             let mut opts = OpenOptions::new();
             if self.finalpathcomments() {
-                let _ = fs::copy(containerfile, path)?;
+                let _ = fs::copy(containerfile, &path)?;
 
                 info!("writing (AW) final path {path}");
                 opts.append(true);
@@ -79,13 +84,18 @@ impl Green {
             fbuf.push('\n');
             fbuf.push_str("# Pipe this file to");
             if !contexts.is_empty() {
-                //TODO: or additional-build-arguments
                 fbuf.push_str(" (not portable due to usage of local build contexts)");
             }
             fbuf.push_str(&format!(":\n# {envs} \\\n"));
             fbuf.push_str(&format!("#   {call} <THIS_FILE\n"));
+            if !self.additional_build_arguments.is_empty() {
+                fbuf.push_str("# which depends on these additional build arguments:\n");
+                for arg in &self.additional_build_arguments {
+                    fbuf.push_str(&format!("#   {arg}\n"));
+                }
+            }
 
-            let mut file = opts.open(path)?;
+            let mut file = opts.open(&path)?;
             write!(file, "{fbuf}")?;
         }
         Ok(())
@@ -96,7 +106,8 @@ impl Green {
         md_path: &Utf8Path,
         final_stage: String,
     ) -> Result<()> {
-        if let Some(path) = self.should_write_final_path() {
+        let target_path = md_path.parent().unwrap_or(md_path);
+        if let Some(path) = self.should_write_final_path(target_path)? {
             info!("appending (AW) to final path {path}");
 
             let mut fbuf = String::new();
@@ -111,9 +122,208 @@ impl Green {
             fbuf.push('\n');
             fbuf.push_str(&final_stage);
 
-            let mut file = OpenOptions::new().append(true).open(path)?;
+            let mut file = OpenOptions::new().append(true).open(&path)?;
             write!(file, "{fbuf}")?;
+
+            if let Some(path) = self.r#final.path.as_deref()
+                && let Some(ref built_for) = self.target
+            {
+                self.merge_platform_parts(path, target_path, built_for)?;
+            }
         }
         Ok(())
     }
+
+    /// Assembles the containerfiles written per `--target` into one building for all their platforms.
+    fn merge_platform_parts(
+        &self,
+        path: &Utf8Path,
+        target_path: &Utf8Path,
+        built_for: &str,
+    ) -> Result<()> {
+        let mut parts = vec![];
+        for target in &self.targets {
+            let part = platform_part(path, target_path, built_for, target)?;
+            let Some(platform) = platform(target) else { continue };
+            match fs::read_to_string(&part) {
+                Ok(txt) => parts.push((platform, txt)),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => bail!("Failed reading {part}: {e}"),
+            }
+        }
+
+        info!("writing (TW) final path {path} for {} platforms", parts.len());
+        fs::write(path, merge_platforms(&parts)).map_err(|e| anyhow!("Failed writing {path}: {e}"))
+    }
+}
+
+/// Where the final containerfile for one `--target` gets written,
+/// from the `target_path` of a crate built for `built_for`.
+///
+/// E.g. `$CARGO_TARGET_DIR/aarch64-unknown-linux-gnu/final.Dockerfile`
+fn platform_part(
+    path: &Utf8Path,
+    target_path: &Utf8Path,
+    built_for: &str,
+    target: &str,
+) -> Result<Utf8PathBuf> {
+    let name = path.file_name().unwrap_or("final.Dockerfile");
+    // target_path is $CARGO_TARGET_DIR/<triple>/<profile>
+    let Some(dir) = target_path
+        .parent()
+        .filter(|dir| dir.file_name() == Some(built_for))
+        .and_then(Utf8Path::parent)
+    else {
+        bail!("BUG: {target_path} is not of the form $CARGO_TARGET_DIR/{built_for}/<profile>")
+    };
+    Ok(dir.join(target).join(name))
+}
+
+/// Merges single-platform final containerfiles, deduplicating shared stages.
+///
+/// Each part's unnamed last stage gets named after its platform, and a last stage
+/// picks the one matching `$TARGETPLATFORM`.
+fn merge_platforms(parts: &[(&str, String)]) -> String {
+    let mut header = String::new();
+    let mut trailer = vec![];
+    let mut names = IndexSet::new();
+    let mut blocks = vec![];
+    for (i, (platform, txt)) in parts.iter().enumerate() {
+        let mut lines = txt.lines().peekable();
+        while let Some(line) = lines.next_if(|line| line.starts_with("# ") || line.is_empty()) {
+            if i == 0 {
+                header.push_str(line);
+                header.push('\n');
+            }
+        }
+
+        let mut block: Option<(String, String)> = None;
+        for line in lines {
+            if line.starts_with("# ") {
+                if i == 0 {
+                    trailer.push(line.to_owned());
+                }
+                continue;
+            }
+            if line.starts_with("FROM ") {
+                blocks.extend(block.take());
+                let (line, name) = match line.split_once(" AS ") {
+                    Some((_, name)) => (line.to_owned(), name.trim().to_owned()),
+                    None => {
+                        let name = format!("final-{}", platform.replace('/', "-"));
+                        (format!("{line} AS {name}"), name)
+                    }
+                };
+                block = Some((name, format!("{line}\n")));
+                continue;
+            }
+            if let Some((_, ref mut txt)) = block {
+                txt.push_str(line);
+                txt.push('\n');
+            }
+        }
+        blocks.extend(block);
+    }
+
+    let mut merged = header;
+    for (name, block) in blocks {
+        if names.insert(name) {
+            merged.push_str(block.trim());
+            merged.push_str("\n\n");
+        }
+    }
+    merged.push_str("FROM final-${TARGETOS}-${TARGETARCH}${TARGETVARIANT:+-$TARGETVARIANT}\n");
+
+    let platforms = parts.iter().map(|(platform, _)| *platform).collect::<Vec<_>>().join(",");
+    if !trailer.is_empty() {
+        merged.push('\n');
+    }
+    for line in trailer {
+        let line = line
+            .split(' ')
+            .map(|word| {
+                if word.starts_with("--platform=") {
+                    format!("--platform={platforms}")
+                } else {
+                    word.to_owned()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        merged.push_str(&line);
+        merged.push('\n');
+    }
+    merged
+}
+
+#[cfg(test)]
+#[test_case::test_case("/target/aarch64-unknown-linux-gnu/debug", "aarch64-unknown-linux-gnu", Some("/target/aarch64-unknown-linux-gnu/final.Dockerfile"); "same target")]
+#[test_case::test_case("/target/aarch64-unknown-linux-gnu/release", "x86_64-unknown-linux-gnu", Some("/target/x86_64-unknown-linux-gnu/final.Dockerfile"); "other target")]
+#[test_case::test_case("/target/aarch64-unknown-linux-gnu/debug/deps", "aarch64-unknown-linux-gnu", None; "deps dir")]
+#[test_case::test_case("/target/debug", "aarch64-unknown-linux-gnu", None; "not cross")]
+#[test_case::test_case("/target/x86_64-unknown-linux-gnu/debug", "aarch64-unknown-linux-gnu", None; "built for another")]
+fn platform_parts(target_path: &str, target: &str, expected: Option<&str>) {
+    let built_for = "aarch64-unknown-linux-gnu";
+    let got = platform_part("/final.Dockerfile".into(), target_path.into(), built_for, target);
+    match expected {
+        Some(expected) => assert_eq!(got.unwrap(), expected),
+        None => {
+            assert!(got.unwrap_err().to_string().contains("is not of the form"), "{target_path}")
+        }
+    }
+}
+
+#[test]
+fn merging_platforms() {
+    let part = |platform: &str, triple: &str| {
+        format!(
+            r#"# syntax=docker.io/docker/dockerfile:1@sha256:abc
+# Generated by https://github.com/fenollp/supergreen v0.1.0
+
+FROM --platform=$BUILDPLATFORM rust AS rust-base
+RUN true
+
+FROM rust-base AS out-{triple}
+RUN cargo
+
+# Pipe this file to:
+# CARGOGREEN=1 \
+#   docker buildx build --platform={platform} --output=. - <THIS_FILE
+
+FROM scratch
+COPY --from=out-{triple} /bin/app /
+"#
+        )
+    };
+    let parts = [
+        ("linux/amd64", part("linux/amd64", "x86_64-unknown-linux-gnu")),
+        ("linux/arm/v7", part("linux/arm/v7", "armv7-unknown-linux-gnueabihf")),
+    ];
+    pretty_assertions::assert_eq!(
+        merge_platforms(&parts),
+        r#"# syntax=docker.io/docker/dockerfile:1@sha256:abc
+# Generated by https://github.com/fenollp/supergreen v0.1.0
+
+FROM --platform=$BUILDPLATFORM rust AS rust-base
+RUN true
+
+FROM rust-base AS out-x86_64-unknown-linux-gnu
+RUN cargo
+
+FROM scratch AS final-linux-amd64
+COPY --from=out-x86_64-unknown-linux-gnu /bin/app /
+
+FROM rust-base AS out-armv7-unknown-linux-gnueabihf
+RUN cargo
+
+FROM scratch AS final-linux-arm-v7
+COPY --from=out-armv7-unknown-linux-gnueabihf /bin/app /
+
+FROM final-${TARGETOS}-${TARGETARCH}${TARGETVARIANT:+-$TARGETVARIANT}
+
+# Pipe this file to:
+# CARGOGREEN=1 \
+#   docker buildx build --platform=linux/amd64,linux/arm/v7 --output=. - <THIS_FILE
+"#
+    );
 }
@@ -1,26 +1,34 @@
 use std::{
     collections::{HashMap, HashSet},
-    env,
+    env, fmt, fs,
+    sync::LazyLock,
 };
 
 use anyhow::{Result, anyhow, bail};
-use camino::Utf8PathBuf;
-use cargo_toml::{Manifest, Package, Value as MetadataValue};
-use log::warn;
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_toml::{Manifest, Value as MetadataValue};
+use directories::ProjectDirs;
+use indexmap::IndexMap;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
+use toml::Table;
 
 use crate::{
     ENV_RUNNER, PKG,
     add::Add,
-    base_image::BaseImage,
-    builder::Builder,
+    backend::BackendOverride,
+    base_image::{BaseImage, check_also_run, parse_also_run},
+    builder::{Builder, parse_gc_policies, parse_registries},
     buildkitd::MIRRORS,
     cache::Cache,
     containerfile::Containerfile,
-    dirs::Dirs,
+    cratesio::{crate_to_install, packaged_manifest},
+    cross,
+    dirs::{Dirs, cargo_home},
     r#final::Final,
     image_uri::{BAD_CHARS, ImageUri},
-    lockfile::find_manifest_path,
+    lockfile::{find_manifest_path, find_workspace_manifest_path},
+    packages::{Packages, check_packages},
     runner::Runner,
 };
 
@@ -30,12 +38,24 @@ macro_rules! ENV_REGISTRY_MIRRORS {
     };
 }
 
+macro_rules! ENV_PROFILE {
+    () => {
+        "CARGOGREEN_PROFILE"
+    };
+}
+
 macro_rules! ENV_SET_ENVS {
     () => {
         "CARGOGREEN_SET_ENVS"
     };
 }
 
+macro_rules! ENV_ADDITIONAL_BUILD_ARGUMENTS {
+    () => {
+        "CARGOGREEN_ADDITIONAL_BUILD_ARGUMENTS"
+    };
+}
+
 #[macro_export]
 macro_rules! ENV_SYNTAX_IMAGE {
     () => {
@@ -75,11 +95,35 @@ pub(crate) struct Green {
     #[doc(hidden)]
     pub(crate) dirs: Option<Dirs>,
 
+    /// Cross-compilation target triples, from `--target`. Not user-settable.
+    #[doc(hidden)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) targets: Vec<String>,
+
+    /// Which of `targets` the current crate is built for.
+    #[doc(hidden)]
+    #[serde(skip)]
+    pub(crate) target: Option<String>,
+
     /// Snapshot of runner's envs. Not user-settable.
     #[doc(hidden)]
     #[serde(skip_serializing_if = "HashMap::is_empty")]
     pub(crate) runner_envs: HashMap<String, String>,
 
+    #[doc = include_str!(concat!("../docs/",ENV_PROFILE!(),".md"))]
+    #[serde(skip)]
+    pub(crate) profile: Option<String>,
+
+    /// Where each setting was read from. Not user-settable.
+    #[doc(hidden)]
+    #[serde(skip)]
+    pub(crate) origins: Origins,
+
+    /// Answers instead of the runner's backend, see [`Green::with_backend`]. Not user-settable.
+    #[doc(hidden)]
+    #[serde(skip)]
+    pub(crate) backend_override: BackendOverride,
+
     #[serde(flatten)]
     pub(crate) builder: Builder,
 
@@ -103,6 +147,10 @@ pub(crate) struct Green {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub(crate) set_envs: Vec<String>,
 
+    #[doc = include_str!(concat!("../docs/",ENV_ADDITIONAL_BUILD_ARGUMENTS!(),".md"))]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) additional_build_arguments: Vec<String>,
+
     #[serde(skip_serializing_if = "Add::is_empty")]
     pub(crate) add: Add,
 
@@ -113,6 +161,10 @@ pub(crate) struct Green {
     #[doc = include_str!(concat!("../docs/",ENV_COMPONENTS!(),".md"))]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub(crate) components: Vec<String>,
+
+    /// Per-package settings, see [`PackageOverride`](crate::packages::PackageOverride).
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub(crate) packages: Packages,
 }
 
 impl Green {
@@ -120,41 +172,123 @@ impl Green {
         Containerfile::with_syntax(&self.syntax)
     }
 
-    // TODO: handle worskpace cfg + merging fields
-    // TODO: find a way to read cfg on `cargo install <non-local code>` cc https://github.com/rust-lang/cargo/issues/9700#issuecomment-2748617896
+    /// BuildKit platform to build for
+    #[must_use]
+    pub(crate) fn platform(&self) -> &'static str {
+        self.target.as_deref().and_then(cross::platform).unwrap_or("local")
+    }
+
     pub(crate) async fn new_from_env_then_manifest(is_install: bool) -> Result<Self> {
+        let mut files = vec![];
+        let system = Some(Utf8Path::new(SYSTEM_CONFIG));
+        for (layer, path) in [(Layer::System, system), (Layer::User, USER_CONFIG.as_deref())] {
+            let Some(path) = path else { continue };
+            if !path.exists() {
+                continue;
+            }
+            let txt = fs::read_to_string(path)
+                .map_err(|e| anyhow!("Can't read {PKG} configuration {path}: {e}"))?;
+            let table = toml::from_str(&txt)
+                .map_err(|e| anyhow!("Can't parse {PKG} configuration {path}: {e}"))?;
+            files.push((layer, table));
+        }
+
         let manifest = if is_install {
-            let empty_manifest: Manifest<MetadataValue> = Manifest::from_str("").unwrap();
-            empty_manifest
+            installed_manifest().await
         } else {
             let manifest_path = find_manifest_path()
                 .await
                 .map_err(|e| anyhow!("Can't find package manifest: {e}"))?;
-            Manifest::from_path(&manifest_path)
-                .map_err(|e| anyhow!("Can't read package manifest {manifest_path}: {e}"))?
+            let mut manifest = Manifest::from_path(&manifest_path)
+                .map_err(|e| anyhow!("Can't read package manifest {manifest_path}: {e}"))?;
+
+            if manifest.workspace.is_none() {
+                let workspace_path = find_workspace_manifest_path(&manifest_path)
+                    .await
+                    .map_err(|e| anyhow!("Can't find workspace manifest: {e}"))?;
+                if workspace_path != manifest_path {
+                    let workspace = Manifest::from_path(&workspace_path).map_err(|e| {
+                        anyhow!("Can't read workspace manifest {workspace_path}: {e}")
+                    })?;
+                    manifest.workspace = workspace.workspace;
+                }
+            }
+            manifest
         };
 
-        Self::try_new(manifest).map_err(|e| anyhow!("Failed reading {PKG} configuration: {e}"))
+        Self::try_new(files, manifest)
+            .map_err(|e| anyhow!("Failed reading {PKG} configuration: {e}"))
     }
 
-    fn try_new(manifest: Manifest) -> Result<Self> {
+    /// Reads settings from config files, the workspace's then the package's TOML metadata,
+    /// then from the environment.
+    fn try_new(files: Vec<(Layer, Table)>, manifest: Manifest) -> Result<Self> {
         let mut green = Self::default();
 
-        if let Manifest { package: Some(Package { metadata: Some(metadata), .. }), .. } = manifest {
-            #[derive(Deserialize, Default)]
-            struct GreenMetadata {
-                green: Option<Green>,
+        let mut layers = files;
+        for (layer, metadata) in [
+            (Layer::Workspace, manifest.workspace.and_then(|ws| ws.metadata)),
+            (Layer::Package, manifest.package.and_then(|pkg| pkg.metadata)),
+        ] {
+            let Some(table) = metadata.as_ref().and_then(|metadata| metadata.get("green")) else {
+                continue;
+            };
+            let Some(table) = table.as_table() else { bail!("{layer} must be a table") };
+            layers.push((layer, table.clone()));
+        }
+
+        let mut merged = Table::new();
+        let mut profiles = vec![];
+        let mut origins = Origins::default();
+        for (layer, mut table) in layers {
+            let layer_profiles = match table.remove("profile") {
+                None => Table::new(),
+                Some(MetadataValue::Table(profiles)) => profiles,
+                Some(_) => bail!("{} must be a table", layer.origin_of("profile")),
+            };
+            for (name, profile) in &layer_profiles {
+                let origin = layer.origin_of(&format!("profile.{name}"));
+                let Some(profile) = profile.as_table() else { bail!("{origin} must be a table") };
+                if profile.contains_key("profile") {
+                    bail!("{origin} cannot itself contain profiles")
+                }
+                Self::check_layer(profile, &layer, Some(name))?;
+            }
+            Self::check_layer(&table, &layer, None)?;
+
+            merge_tables(&mut merged, table, &layer, "", &mut origins);
+            profiles.push((layer, layer_profiles));
+        }
+
+        let var = ENV_PROFILE!();
+        let mut active_profile = None;
+        if let Ok(name) = env::var(var) {
+            // Each table's definition of the profile goes on top, in the order of the tables
+            let mut defined = false;
+            for (table, mut layer_profiles) in profiles {
+                let Some(MetadataValue::Table(profile)) = layer_profiles.remove(&name) else {
+                    continue;
+                };
+                let layer = Layer::Profile { name: name.clone(), table: Box::new(table) };
+                merge_tables(&mut merged, profile, &layer, "", &mut origins);
+                defined = true;
             }
-            if let GreenMetadata { green: Some(from_manifest) } = metadata.try_into()? {
-                green = from_manifest;
+            if !defined {
+                bail!("${var} names an undefined profile: {name:?}")
             }
+            active_profile = Some(name);
         }
 
+        if !merged.is_empty() {
+            green = merged.try_into()?;
+        }
+        green.origins = origins;
+        green.profile = active_profile;
+
         let var = ENV_REGISTRY_MIRRORS!();
-        let mut origin = setting(var);
         let mut was_reset = false;
         if let Ok(val) = env::var(var) {
-            origin = format!("${var}");
+            green.origins.set_env(var);
             if val.is_empty() {
                 was_reset = true;
                 green.registry_mirrors = vec![];
@@ -162,28 +296,136 @@ impl Green {
                 green.registry_mirrors = parse_csv(&val);
             }
         }
-        if green.registry_mirrors.len()
-            != green.registry_mirrors.iter().collect::<HashSet<_>>().len()
-        {
-            bail!("{origin} contains duplicates")
-        }
-        if green.registry_mirrors.is_empty() && !was_reset {
-            green.registry_mirrors = MIRRORS.iter().map(ToString::to_string).collect();
-        }
 
         for (field, var) in [
             (&mut green.cache.from_images, ENV_CACHE_FROM_IMAGES!()),
             (&mut green.cache.to_images, ENV_CACHE_TO_IMAGES!()),
             (&mut green.cache.images, ENV_CACHE_IMAGES!()),
         ] {
-            let mut origin = setting(var);
             if let Ok(val) = env::var(var) {
-                origin = format!("${var}");
+                green.origins.set_env(var);
                 *field = val
                     .split(',')
-                    .map(|x| ImageUri::try_new(x).map_err(|e| anyhow!("{origin} {e}")))
+                    .map(|x| ImageUri::try_new(x).map_err(|e| anyhow!("${var} {e}")))
                     .collect::<Result<_>>()?;
             }
+        }
+
+        for (field, var) in [
+            (&mut green.add.apk, ENV_ADD_APK!()),
+            (&mut green.add.apt, ENV_ADD_APT!()),
+            (&mut green.components, ENV_COMPONENTS!()),
+            (&mut green.set_envs, ENV_SET_ENVS!()),
+            (&mut green.builder.tuning.entitlements, ENV_BUILDER_ENTITLEMENTS!()),
+        ] {
+            if let Ok(val) = env::var(var) {
+                green.origins.set_env(var);
+                if val.is_empty() {
+                    bail!("${var} is empty")
+                }
+                *field = parse_csv(&val);
+            }
+        }
+
+        let var = ENV_BUILDER_MAX_PARALLELISM!();
+        if let Ok(val) = env::var(var) {
+            green.origins.set_env(var);
+            green.builder.tuning.max_parallelism =
+                Some(val.parse().map_err(|e| anyhow!("${var}={val:?} {e}"))?);
+        }
+
+        let var = ENV_BUILDER_GC_KEEP_STORAGE!();
+        if let Ok(val) = env::var(var) {
+            green.origins.set_env(var);
+            green.builder.tuning.gc_keep_storage = Some(val);
+        }
+
+        let var = ENV_REGISTRIES!();
+        if let Ok(val) = env::var(var) {
+            green.origins.set_env(var);
+            green.builder.registries =
+                parse_registries(&val).map_err(|e| anyhow!("${var}: {e}"))?;
+        }
+
+        let var = ENV_BUILDER_GC_POLICIES!();
+        if let Ok(val) = env::var(var) {
+            green.origins.set_env(var);
+            green.builder.tuning.gc_policies =
+                parse_gc_policies(&val).map_err(|e| anyhow!("${var}: {e}"))?;
+        }
+
+        let var = ENV_ADDITIONAL_BUILD_ARGUMENTS!();
+        if let Ok(val) = env::var(var) {
+            green.origins.set_env(var);
+            if val.trim().is_empty() {
+                bail!("${var} is empty")
+            }
+            green.additional_build_arguments =
+                val.split_whitespace().map(ToOwned::to_owned).collect();
+        }
+
+        let var = ENV_BASE_IMAGE!();
+        if let Ok(val) = env::var(var) {
+            green.origins.set_env(var);
+            green.base.image = val.try_into().map_err(|e| anyhow!("${var} {e}"))?;
+        }
+
+        let var = ENV_ALSO_RUN!();
+        if let Ok(val) = env::var(var) {
+            green.origins.set_env(var);
+            if val.is_empty() {
+                bail!("${var} is empty")
+            }
+            green.base.also_run = parse_also_run(&val).map_err(|e| anyhow!("${var} {e}"))?;
+        }
+
+        green.check(|var| green.origins.setting(var))?;
+
+        if green.registry_mirrors.is_empty() && !was_reset {
+            green.registry_mirrors = MIRRORS.iter().map(ToString::to_string).collect();
+        }
+
+        for (field, var) in [(&green.add.apk, ENV_ADD_APK!()), (&green.add.apt, ENV_ADD_APT!())] {
+            let origin = green.origins.setting(var);
+            for f in field.iter().filter(|f| !f.contains('=')) {
+                warn!("warning: config {origin} is missing version constraints on {f:?}");
+                eprintln!("warning: config {origin} is missing version constraints on {f:?}");
+            }
+        }
+
+        Ok(green)
+    }
+
+    /// Checks a layer (or one of its profiles) on its own, so errors point to where the bad value was set.
+    fn check_layer(table: &Table, layer: &Layer, profile: Option<&str>) -> Result<()> {
+        let key = |key: &str| match profile {
+            Some(name) => format!("profile.{name}.{key}"),
+            None => key.to_owned(),
+        };
+        let at = match profile {
+            Some(name) => layer.origin_of(&format!("profile.{name}")),
+            None => layer.to_string(),
+        };
+
+        let from_layer: Self = table.clone().try_into().map_err(|e| anyhow!("{at} {e}"))?;
+        from_layer.check(|var| layer.origin_of(&key(&env_as_toml(var))))?;
+        check_packages(&from_layer.packages, &layer.origin_of(&key("packages")))
+    }
+
+    /// Checks values, whichever layer they come from.
+    fn check(&self, origin: impl Fn(&'static str) -> String) -> Result<()> {
+        let var = ENV_REGISTRY_MIRRORS!();
+        if self.registry_mirrors.len() != self.registry_mirrors.iter().collect::<HashSet<_>>().len()
+        {
+            bail!("{} contains duplicates", origin(var))
+        }
+
+        for (field, var) in [
+            (&self.cache.from_images, ENV_CACHE_FROM_IMAGES!()),
+            (&self.cache.to_images, ENV_CACHE_TO_IMAGES!()),
+            (&self.cache.images, ENV_CACHE_IMAGES!()),
+        ] {
+            let origin = origin(var);
             if field.len() != field.iter().collect::<HashSet<_>>().len() {
                 bail!("{origin} contains duplicates")
             }
@@ -197,37 +439,377 @@ impl Green {
             }
         }
 
-        for (field, var) in
-            [(&mut green.add.apk, ENV_ADD_APK!()), (&mut green.add.apt, ENV_ADD_APT!())]
-        {
-            let origin = validate_csv(field, var)?;
-            for f in field.iter().filter(|f| !f.contains('=')) {
-                warn!("warning: config {origin} is missing version constraints on {f:?}");
-                eprintln!("warning: config {origin} is missing version constraints on {f:?}");
-            }
+        for (field, var) in [
+            (&self.add.apk, ENV_ADD_APK!()),
+            (&self.add.apt, ENV_ADD_APT!()),
+            (&self.components, ENV_COMPONENTS!()),
+            (&self.set_envs, ENV_SET_ENVS!()),
+            (&self.builder.tuning.entitlements, ENV_BUILDER_ENTITLEMENTS!()),
+        ] {
+            check_csv(field, &origin(var))?;
         }
 
-        validate_csv(&mut green.components, ENV_COMPONENTS!())?;
+        self.builder.check_config(&origin)?;
+        if self.builder.registries.get("docker.io").is_some_and(|r| !r.mirrors.is_empty()) {
+            bail!(
+                "{} sets mirrors for docker.io: use {} instead",
+                origin(ENV_REGISTRIES!()),
+                origin(ENV_REGISTRY_MIRRORS!())
+            )
+        }
 
-        if !green.base.image_inline.is_empty() {
+        check_also_run(&self.base.also_run, &origin(ENV_ALSO_RUN!()))?;
+
+        check_build_arguments(
+            &self.additional_build_arguments,
+            &origin(ENV_ADDITIONAL_BUILD_ARGUMENTS!()),
+        )?;
+
+        if !self.base.image_inline.is_empty() {
             bail!("'base-image-inline' setting cannot be set")
         }
-        let var = ENV_BASE_IMAGE!();
-        if let Ok(val) = env::var(var) {
-            green.base.image = val.try_into().map_err(|e| anyhow!("${var} {e}"))?;
+
+        if self.set_envs.iter().any(|var| var.starts_with("CARGOGREEN_")) {
+            bail!("{} contains CARGOGREEN_* names", origin(ENV_SET_ENVS!()))
         }
 
-        validate_csv(&mut green.set_envs, ENV_SET_ENVS!())?;
-        if green.set_envs.iter().any(|var| var.starts_with("CARGOGREEN_")) {
-            bail!("{origin} contains CARGOGREEN_* names")
+        Ok(())
+    }
+
+    /// Refuses `additional-build-arguments` flags the runner has no equivalent for
+    pub(crate) fn check_runner_build_arguments(&self) -> Result<()> {
+        let unsupported: &[&str] = match self.runner {
+            Runner::Podman => &["--allow"],
+            _ => &[],
+        };
+        for arg in &self.additional_build_arguments {
+            let flag = arg.split_once('=').map_or(arg.as_str(), |(flag, _)| flag);
+            if unsupported.contains(&flag) {
+                bail!(
+                    "{} contains {flag:?} which runner {} does not support",
+                    self.origins.setting(ENV_ADDITIONAL_BUILD_ARGUMENTS!()),
+                    self.runner
+                )
+            }
         }
+        Ok(())
+    }
 
-        Ok(green)
+    /// Mounts exposing secrets and SSH agents of `additional-build-arguments` to a `RUN` step
+    pub(crate) fn run_mounts(&self) -> String {
+        let mut mounts = String::new();
+        for arg in &self.additional_build_arguments {
+            if let Some(opts) = arg.strip_prefix("--secret=")
+                && let Some(id) = opts.split(',').find_map(|opt| opt.strip_prefix("id="))
+            {
+                mounts.push_str(&format!("  --mount=type=secret,id={id} \\\n"));
+            }
+            if let Some(opts) = arg.strip_prefix("--ssh=") {
+                let id = opts.split_once('=').map_or(opts, |(id, _)| id);
+                mounts.push_str(&format!("  --mount=type=ssh,id={id} \\\n"));
+            }
+        }
+        mounts
     }
 }
 
-fn env_as_toml(var: &str) -> String {
-    var.replace("CARGOGREEN_", "").replace('_', "-").to_lowercase()
+/// Flags that `additional-build-arguments` may pass to build calls
+const BUILD_ARGUMENTS: &[&str] =
+    &["--secret", "--ssh", "--build-context", "--ulimit", "--add-host", "--allow"];
+
+/// Flags that build calls already set (see `with_docker_args`)
+const OWN_BUILD_ARGUMENTS: &[&str] = &[
+    "--cache-from",
+    "--cache-to",
+    "--load",
+    "--network",
+    "--no-cache",
+    "--output",
+    "-o",
+    "--platform",
+    "--pull",
+    "--tag",
+    "-t",
+    "--target",
+];
+
+fn check_build_arguments(field: &[String], origin: &str) -> Result<()> {
+    if field.len() != field.iter().collect::<HashSet<_>>().len() {
+        bail!("{origin} contains duplicates")
+    }
+
+    for arg in field {
+        let flag = arg.split_once('=').map_or(arg.as_str(), |(flag, _)| flag);
+        if flag == "--build-arg" && arg.contains("BUILDKIT_SYNTAX") {
+            bail!("{origin} must not set BUILDKIT_SYNTAX, see {}", setting(ENV_SYNTAX_IMAGE!()))
+        }
+        if OWN_BUILD_ARGUMENTS.contains(&flag) {
+            bail!("{origin} must not contain {flag:?} as {PKG} already sets it")
+        }
+        if !BUILD_ARGUMENTS.contains(&flag) {
+            bail!("{origin} only accepts {}: {arg:?}", BUILD_ARGUMENTS.join(", "))
+        }
+
+        let value = &arg[flag.len()..];
+        let Some(value) = value.strip_prefix('=').filter(|value| !value.is_empty()) else {
+            bail!("{origin} must be written as {flag}=VALUE: {arg:?}")
+        };
+        if value.contains(|c: char| c.is_whitespace() || ['\'', '"', ';', '\\'].contains(&c)) {
+            bail!("{origin} contains whitespace, quotes or bad characters: {arg:?}")
+        }
+        if flag == "--secret" && !value.split(',').any(|opt| opt.starts_with("id=")) {
+            bail!("{origin} is missing a secret id: {arg:?}")
+        }
+    }
+    Ok(())
+}
+
+/// The manifest of the crate `cargo install` targets, as published.
+///
+/// See <https://github.com/rust-lang/cargo/issues/9700#issuecomment-2748617896>
+async fn installed_manifest() -> Manifest {
+    let empty_manifest: Manifest<MetadataValue> = Manifest::from_str("").unwrap();
+
+    let args: Vec<_> = env::args().skip_while(|arg| arg != "install").skip(1).collect();
+    let Some((name, version)) = crate_to_install(&args) else { return empty_manifest };
+
+    let manifest = async {
+        let (version, txt, cached) =
+            packaged_manifest(&cargo_home()?, &name, version.as_deref()).await?;
+        let manifest = Manifest::from_str(&txt)
+            .map_err(|e| anyhow!("Can't read manifest of {name}@{version}: {e}"))?;
+        Ok::<_, anyhow::Error>((version, manifest, cached))
+    }
+    .await;
+
+    match manifest {
+        Ok((version, mut manifest, cached)) => {
+            let settings = manifest
+                .package
+                .as_mut()
+                .and_then(|pkg| pkg.metadata.as_mut())
+                .and_then(|metadata| metadata.get_mut("green"));
+            if let Some(settings) = settings {
+                let from = cached.map_or_else(|| "crates.io".to_owned(), |path| path.to_string());
+                info!("using [package.metadata.green] of {name}@{version} from {from}");
+                eprintln!("{PKG}: using [package.metadata.green] of {name}@{version} from {from}");
+
+                let dropped = keep_installable(settings);
+                if !dropped.is_empty() {
+                    let dropped = dropped.join(", ");
+                    warn!(
+                        "ignoring settings of {name}@{version} a published crate may not set: {dropped}"
+                    );
+                    eprintln!(
+                        "warning: {PKG} ignoring settings of {name}@{version} a published crate may not set: {dropped}"
+                    );
+                }
+            }
+            manifest
+        }
+        Err(e) => {
+            warn!("warning: ignoring settings of {name}: {e}");
+            eprintln!("warning: {PKG} ignoring settings of {name}: {e}");
+            empty_manifest
+        }
+    }
+}
+
+/// The only settings a published crate may set for its own installation.
+///
+/// Others (build arguments, network, caches, images, ...) could reach into the host of whoever installs it.
+const INSTALLABLE: &[(&str, &[&str])] = &[("add", &["apt", "apk"]), ("set-envs", &[])];
+
+/// Strips a published crate's settings down to [`INSTALLABLE`] ones, returning the dropped keys.
+fn keep_installable(settings: &mut MetadataValue) -> Vec<String> {
+    let Some(table) = settings.as_table_mut() else {
+        *settings = MetadataValue::Table(Table::new());
+        return vec!["[package.metadata.green]".to_owned()];
+    };
+
+    let mut dropped = vec![];
+    table.retain(|key, value| {
+        let Some((_, subkeys)) = INSTALLABLE.iter().find(|(k, _)| *k == key) else {
+            dropped.push(key.to_owned());
+            return false;
+        };
+        if subkeys.is_empty() {
+            return true;
+        }
+        let Some(subtable) = value.as_table_mut() else {
+            dropped.push(key.to_owned());
+            return false;
+        };
+        subtable.retain(|subkey, _| {
+            let keep = subkeys.contains(&subkey);
+            if !keep {
+                dropped.push(format!("{key}.{subkey}"));
+            }
+            keep
+        });
+        true
+    });
+    dropped
+}
+
+/// Machine-wide configuration file
+const SYSTEM_CONFIG: &str = "/etc/cargo-green/config.toml";
+
+/// Per-user configuration file, e.g. `~/.config/cargo-green/config.toml`
+static USER_CONFIG: LazyLock<Option<Utf8PathBuf>> = LazyLock::new(|| {
+    let xdg = ProjectDirs::from("", "", PKG)?;
+    xdg.config_dir().join("config.toml").try_into().ok()
+});
+
+/// Where a setting was read from, by increasing order of precedence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Layer {
+    System,
+    User,
+    Workspace,
+    Package,
+    /// A profile, as defined in one of the above
+    Profile {
+        name: String,
+        table: Box<Layer>,
+    },
+    Env(&'static str),
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::System => write!(f, "{SYSTEM_CONFIG}"),
+            Self::User => match USER_CONFIG.as_deref() {
+                Some(path) => write!(f, "{path}"),
+                None => write!(f, "user config"),
+            },
+            Self::Workspace => write!(f, "[workspace.metadata.green]"),
+            Self::Package => write!(f, "[package.metadata.green]"),
+            Self::Profile { name, table } => {
+                write!(f, "{}", table.origin_of(&format!("profile.{name}")))
+            }
+            Self::Env(var) => write!(f, "${var}"),
+        }
+    }
+}
+
+impl Layer {
+    /// True for settings that come from a config file of this machine
+    #[must_use]
+    pub(crate) fn is_file(&self) -> bool {
+        matches!(self, Self::System | Self::User)
+    }
+
+    fn origin_of(&self, key: &str) -> String {
+        match self {
+            Self::System | Self::User => format!("{key:?} in {self}"),
+            Self::Workspace => format!("[workspace.metadata.green.{key}]"),
+            Self::Package => format!("[package.metadata.green.{key}]"),
+            Self::Profile { name, table } => table.origin_of(&format!("profile.{name}.{key}")),
+            Self::Env(var) => format!("${var}"),
+        }
+    }
+
+    fn setting(&self, var: &str) -> String {
+        self.origin_of(&env_as_toml(var))
+    }
+}
+
+/// Maps each TOML key (e.g. `add.apt`) to the layers that set it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Origins(IndexMap<String, Vec<Layer>>);
+
+impl Origins {
+    /// Records that a setting was read from the environment.
+    pub(crate) fn set_env(&mut self, var: &'static str) {
+        self.set(&env_as_toml(var), Layer::Env(var));
+    }
+
+    pub(crate) fn set(&mut self, key: &str, layer: Layer) {
+        self.0.insert(key.to_owned(), vec![layer]);
+    }
+
+    pub(crate) fn setting(&self, var: &str) -> String {
+        match self.0.get(&env_as_toml(var)).and_then(|layers| layers.last()) {
+            Some(layer) => layer.setting(var),
+            None => setting(var),
+        }
+    }
+
+    /// The layer with the most precedence that set this TOML key
+    #[must_use]
+    pub(crate) fn last(&self, key: &str) -> Option<Layer> {
+        self.0.get(key).and_then(|layers| layers.last()).cloned()
+    }
+
+    /// Describes where the value of a TOML key (e.g. `add.apt`) comes from.
+    #[must_use]
+    pub(crate) fn provenance(&self, key: &str) -> String {
+        match self.0.get(key) {
+            Some(layers) => layers.iter().map(ToString::to_string).collect::<Vec<_>>().join(" + "),
+            None => "default".to_owned(),
+        }
+    }
+}
+
+/// Layers `upper` onto `lower`: tables get merged, arrays get the union of their items
+/// (in order, without repeating `lower`'s) and any other value gets overridden.
+fn merge_tables(
+    lower: &mut Table,
+    upper: Table,
+    layer: &Layer,
+    prefix: &str,
+    origins: &mut Origins,
+) {
+    for (key, value) in upper {
+        let path = format!("{prefix}{key}");
+        match (lower.get_mut(&key), value) {
+            (Some(MetadataValue::Table(lower)), MetadataValue::Table(upper)) => {
+                merge_tables(lower, upper, layer, &format!("{path}."), origins);
+                continue;
+            }
+            (Some(MetadataValue::Array(lower)), MetadataValue::Array(upper)) => {
+                for item in upper {
+                    if !lower.contains(&item) {
+                        lower.push(item);
+                    }
+                }
+                origins.0.entry(path).or_default().push(layer.clone());
+                continue;
+            }
+            (_, MetadataValue::Table(upper)) => {
+                let mut table = Table::new();
+                merge_tables(&mut table, upper, layer, &format!("{path}."), origins);
+                lower.insert(key, MetadataValue::Table(table));
+                continue;
+            }
+            (_, value) => {
+                lower.insert(key, value);
+            }
+        }
+        origins.0.insert(path, vec![layer.clone()]);
+    }
+}
+
+pub(crate) fn env_as_toml(var: &str) -> String {
+    match var {
+        BUILDX_BUILDER!() => return "builder-name".to_owned(),
+        ENV_SYNTAX_IMAGE!() => return "syntax".to_owned(),
+        _ => {}
+    }
+    let key = var.replace("CARGOGREEN_", "").replace('_', "-").to_lowercase();
+    if let ENV_BUILDER_MAX_PARALLELISM!()
+    | ENV_BUILDER_GC_KEEP_STORAGE!()
+    | ENV_BUILDER_GC_POLICIES!()
+    | ENV_BUILDER_ENTITLEMENTS!() = var
+    {
+        return key.replacen("builder-", "builder.", 1);
+    }
+    match key.strip_prefix("add-") {
+        Some(pm) => format!("add.{pm}"),
+        None => key,
+    }
 }
 
 fn setting(var: &str) -> String {
@@ -248,6 +830,11 @@ pub(crate) fn validate_csv(field: &mut Vec<String>, var: &'static str) -> Result
 
         *field = parse_csv(&val);
     }
+    check_csv(field, &origin)?;
+    Ok(origin)
+}
+
+fn check_csv(field: &[String], origin: &str) -> Result<()> {
     if !field.is_empty() {
         if field.iter().any(|x| x.is_empty() || x.contains(BAD_CHARS) || x.trim() != x) {
             bail!("{origin} contains empty names, whitespace, quotes or bad characters")
@@ -257,7 +844,7 @@ pub(crate) fn validate_csv(field: &mut Vec<String>, var: &'static str) -> Result
             bail!("{origin} contains duplicates")
         }
     }
-    Ok(origin)
+    Ok(())
 }
 
 #[cfg(test)]
@@ -277,7 +864,7 @@ name = "test-package"
 "#
             ))
             .unwrap();
-            let mut green = Green::try_new(manifest).unwrap();
+            let mut green = Green::try_new(vec![], manifest).unwrap();
 
             assert_eq!(green.base, BaseImage::default());
 
@@ -288,6 +875,530 @@ name = "test-package"
         }
     }
 
+    mod installed {
+        use super::super::{Manifest, keep_installable};
+
+        #[test]
+        fn keeps_installable() {
+            let manifest = Manifest::from_str(
+                r#"
+[package]
+name = "test-package"
+
+[package.metadata.green]
+add.apt = [ "libpq-dev" ]
+add.apk = [ "libpq-dev" ]
+add.hack = [ "x" ]
+set-envs = [ "PQ_LIB_DIR" ]
+with-network = "host"
+additional-build-arguments = [ "--secret=id=aws,src=/home/me/.aws/credentials" ]
+base-image = "docker-image://evil.example/rust"
+
+[package.metadata.green.profile.ci]
+with-network = "host"
+"#,
+            )
+            .unwrap();
+            let mut settings = manifest.package.unwrap().metadata.unwrap()["green"].clone();
+            let dropped = keep_installable(&mut settings);
+            assert_eq!(
+                dropped,
+                ["add.hack", "additional-build-arguments", "base-image", "profile", "with-network"]
+            );
+            assert_eq!(
+                settings.to_string(),
+                r#"{ add = { apk = ["libpq-dev"], apt = ["libpq-dev"] }, set-envs = ["PQ_LIB_DIR"] }"#
+            );
+        }
+    }
+
+    mod workspace {
+        use super::super::{Green, Manifest};
+        use crate::{image_uri::ImageUri, network::Network};
+
+        #[test]
+        fn merged() {
+            let manifest = Manifest::from_str(
+                r#"
+[package]
+name = "test-package"
+
+[workspace.metadata.green]
+cache-images = [ "docker-image://some-registry.com/dir/image" ]
+base-image = "docker-image://docker.io/library/debian:bookworm-slim"
+add.apt = [ "libpq-dev=1", "pkg-config=2" ]
+set-envs = [ "A", "B" ]
+
+[package.metadata.green]
+base-image = "docker-image://docker.io/library/ubuntu:latest"
+with-network = "default"
+add.apt = [ "pkg-config=2", "protobuf-compiler=3" ]
+set-envs = [ "C" ]
+"#,
+            )
+            .unwrap();
+            let green = Green::try_new(vec![], manifest).unwrap();
+            assert_eq!(
+                green.cache.images,
+                vec![ImageUri::try_new("docker-image://some-registry.com/dir/image").unwrap()]
+            );
+            assert_eq!(green.base.image, ImageUri::std("ubuntu:latest"));
+            assert_eq!(green.base.with_network, Network::Default);
+            assert_eq!(
+                green.add.apt,
+                vec![
+                    "libpq-dev=1".to_owned(),
+                    "pkg-config=2".to_owned(),
+                    "protobuf-compiler=3".to_owned()
+                ]
+            );
+            assert_eq!(green.set_envs, vec!["A".to_owned(), "B".to_owned(), "C".to_owned()]);
+
+            assert_eq!(green.origins.provenance("cache-images"), "[workspace.metadata.green]");
+            assert_eq!(green.origins.provenance("base-image"), "[package.metadata.green]");
+            assert_eq!(
+                green.origins.provenance("add.apt"),
+                "[workspace.metadata.green] + [package.metadata.green]"
+            );
+            assert_eq!(green.origins.provenance("components"), "default");
+        }
+
+        #[test]
+        fn bad_workspace_value() {
+            let manifest = Manifest::from_str(
+                r#"
+[package]
+name = "test-package"
+
+[workspace.metadata.green]
+components = [ "a b" ]
+
+[package.metadata.green]
+components = [ "rust-src" ]
+"#,
+            )
+            .unwrap();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
+            assert!(err.contains("[workspace.metadata.green.components]"), "In: {err}");
+        }
+
+        #[test]
+        fn bad_package_value() {
+            let manifest = Manifest::from_str(
+                r#"
+[package]
+name = "test-package"
+
+[workspace.metadata.green]
+add.apk = [ "a" ]
+
+[package.metadata.green]
+add.apk = [ "b", "b" ]
+"#,
+            )
+            .unwrap();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
+            assert!(err.contains("[package.metadata.green.add.apk]"), "In: {err}");
+            assert!(err.contains("duplicates"), "In: {err}");
+        }
+
+        #[test]
+        fn bad_workspace_schema() {
+            let manifest = Manifest::from_str(
+                r#"
+[package]
+name = "test-package"
+
+[workspace.metadata.green]
+base-image = "docker.io/library/ubuntu:latest"
+"#,
+            )
+            .unwrap();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
+            assert!(err.contains("[workspace.metadata.green]"), "In: {err}");
+            assert!(err.contains("scheme"), "In: {err}");
+        }
+
+        #[test]
+        fn env_overrides() {
+            let manifest = Manifest::from_str(
+                r#"
+[package]
+name = "test-package"
+
+[workspace.metadata.green]
+components = [ "rust-src" ]
+"#,
+            )
+            .unwrap();
+            temp_env::with_var(ENV_COMPONENTS!(), Some("a b"), || {
+                let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
+                assert!(err.contains(&format!("${}", ENV_COMPONENTS!())), "In: {err}");
+            });
+        }
+
+        #[test]
+        fn env_provenance() {
+            let manifest = Manifest::from_str(
+                r#"
+[package]
+name = "test-package"
+
+[package.metadata.green]
+add.apt = [ "libpq-dev=1" ]
+"#,
+            )
+            .unwrap();
+            temp_env::with_var(ENV_ADD_APT!(), Some("pkg-config=2"), || {
+                let green = Green::try_new(vec![], manifest).unwrap();
+                assert_eq!(green.add.apt, vec!["pkg-config=2".to_owned()]);
+                assert_eq!(green.origins.provenance("add.apt"), "$CARGOGREEN_ADD_APT");
+            });
+        }
+    }
+
+    mod files {
+        use super::super::{Green, Layer, Manifest};
+        use crate::image_uri::ImageUri;
+
+        #[test]
+        fn precedence() {
+            let system = toml::from_str(
+                r#"
+registry-mirrors = [ "mirror.gcr.io" ]
+base-image = "docker-image://docker.io/library/debian:bookworm-slim"
+"#,
+            )
+            .unwrap();
+            let user = toml::from_str(
+                r#"
+registry-mirrors = [ "public.ecr.aws/docker" ]
+cache-from-images = [ "docker-image://some.org/global/cache" ]
+builder-image = "docker-image://docker.io/moby/buildkit:latest"
+base-image = "docker-image://docker.io/library/debian:trixie-slim"
+"#,
+            )
+            .unwrap();
+            let manifest = Manifest::from_str(
+                r#"
+[package]
+name = "test-package"
+
+[package.metadata.green]
+base-image = "docker-image://docker.io/library/ubuntu:latest"
+"#,
+            )
+            .unwrap();
+            let files = vec![(Layer::System, system), (Layer::User, user)];
+            let green = Green::try_new(files, manifest).unwrap();
+            assert_eq!(
+                green.registry_mirrors,
+                vec!["mirror.gcr.io".to_owned(), "public.ecr.aws/docker".to_owned()]
+            );
+            assert_eq!(
+                green.cache.from_images,
+                vec![ImageUri::try_new("docker-image://some.org/global/cache").unwrap()]
+            );
+            assert_eq!(green.base.image, ImageUri::std("ubuntu:latest"));
+            assert_eq!(green.origins.last("builder-image"), Some(Layer::User));
+            assert_eq!(green.origins.last("base-image"), Some(Layer::Package));
+        }
+
+        #[test]
+        fn bad_value() {
+            let user = toml::from_str(r#"registry-mirrors = [ "a", "a" ]"#).unwrap();
+            let manifest = Manifest::from_str("[package]\nname = \"test-package\"").unwrap();
+            let err = Green::try_new(vec![(Layer::User, user)], manifest).err().unwrap();
+            let err = err.to_string();
+            assert!(err.contains(r#""registry-mirrors" in "#), "In: {err}");
+            assert!(err.contains("duplicates"), "In: {err}");
+        }
+    }
+
+    mod profiles {
+        use super::super::{Green, Layer, Manifest};
+        use crate::{image_uri::ImageUri, network::Network};
+
+        const MANIFEST: &str = r#"
+[package]
+name = "test-package"
+
+[package.metadata.green]
+cache-from-images = [ "docker-image://my.org/team/cache" ]
+
+[package.metadata.green.profile.ci]
+cache-to-images = [ "docker-image://my.org/team/cache" ]
+
+[package.metadata.green.profile.bootstrap]
+with-network = "default"
+"#;
+
+        #[test]
+        fn inactive() {
+            let manifest = Manifest::from_str(MANIFEST).unwrap();
+            temp_env::with_var_unset(ENV_PROFILE!(), || {
+                let green = Green::try_new(vec![], manifest).unwrap();
+                assert_eq!(green.profile, None);
+                assert!(green.cache.to_images.is_empty());
+                assert_eq!(green.base.with_network, Network::None);
+            });
+        }
+
+        #[test]
+        fn active() {
+            let manifest = Manifest::from_str(MANIFEST).unwrap();
+            temp_env::with_var(ENV_PROFILE!(), Some("ci"), || {
+                let green = Green::try_new(vec![], manifest).unwrap();
+                assert_eq!(green.profile.as_deref(), Some("ci"));
+                assert_eq!(
+                    green.cache.to_images,
+                    vec![ImageUri::try_new("docker-image://my.org/team/cache").unwrap()]
+                );
+                assert_eq!(green.base.with_network, Network::None);
+                assert_eq!(
+                    green.origins.last("cache-to-images"),
+                    Some(Layer::Profile { name: "ci".to_owned(), table: Box::new(Layer::Package) })
+                );
+                assert_eq!(
+                    green.origins.provenance("cache-to-images"),
+                    "[package.metadata.green.profile.ci]"
+                );
+            });
+        }
+
+        #[test]
+        fn undefined() {
+            let manifest = Manifest::from_str(MANIFEST).unwrap();
+            temp_env::with_var(ENV_PROFILE!(), Some("nope"), || {
+                let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
+                assert!(err.contains(&format!("${}", ENV_PROFILE!())), "In: {err}");
+                assert!(err.contains("nope"), "In: {err}");
+            });
+        }
+
+        #[test]
+        fn bad_value() {
+            let manifest = Manifest::from_str(
+                r#"
+[package]
+name = "test-package"
+
+[workspace.metadata.green.profile.ci]
+set-envs = [ "A", "A" ]
+"#,
+            )
+            .unwrap();
+            temp_env::with_var_unset(ENV_PROFILE!(), || {
+                let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
+                assert!(
+                    err.contains("[workspace.metadata.green.profile.ci.set-envs]"),
+                    "In: {err}"
+                );
+            });
+        }
+    }
+
+    mod additional_build_arguments {
+        use super::super::{Green, Manifest};
+
+        fn try_new(args: &str) -> anyhow::Result<Green> {
+            let manifest = Manifest::from_str(&format!(
+                r#"
+[package]
+name = "test-package"
+
+[package.metadata.green]
+additional-build-arguments = {args}
+"#
+            ))
+            .unwrap();
+            temp_env::with_var_unset(ENV_ADDITIONAL_BUILD_ARGUMENTS!(), || {
+                Green::try_new(vec![], manifest)
+            })
+        }
+
+        #[test]
+        fn ok() {
+            let green = try_new(
+                r#"[ "--secret=id=aws,src=/root/.aws/credentials", "--ssh=default", "--add-host=my.org=10.0.0.1" ]"#,
+            )
+            .unwrap();
+            assert_eq!(green.additional_build_arguments.len(), 3);
+            assert_eq!(
+                green.run_mounts(),
+                "  --mount=type=secret,id=aws \\\n  --mount=type=ssh,id=default \\\n"
+            );
+        }
+
+        #[test_case::test_case(r#"[ "--output=type=local,dest=." ]"#, "already sets it"; "output")]
+        #[test_case::test_case(r#"[ "--target=rust-base" ]"#, "already sets it"; "target")]
+        #[test_case::test_case(r#"[ "--build-arg=BUILDKIT_SYNTAX=docker/dockerfile:1" ]"#, "BUILDKIT_SYNTAX"; "syntax")]
+        #[test_case::test_case(r#"[ "--privileged" ]"#, "only accepts"; "unknown")]
+        #[test_case::test_case(r#"[ "--ssh" ]"#, "--ssh=VALUE"; "no value")]
+        #[test_case::test_case(r#"[ "--secret=src=/root/.aws/credentials" ]"#, "secret id"; "no secret id")]
+        #[test_case::test_case(r#"[ "--ssh=default", "--ssh=default" ]"#, "duplicates"; "duplicates")]
+        #[test_case::test_case(r#"[ "--ulimit=nofile=1024 --ssh=default" ]"#, "whitespace"; "whitespace")]
+        fn bad(args: &str, reason: &str) {
+            let err = try_new(args).err().unwrap().to_string();
+            assert!(
+                err.contains("[package.metadata.green.additional-build-arguments]"),
+                "In: {err}"
+            );
+            assert!(err.contains(reason), "In: {err}");
+        }
+
+        #[test]
+        fn runner_support() {
+            let mut green = try_new(r#"[ "--allow=network.host" ]"#).unwrap();
+            green.check_runner_build_arguments().unwrap();
+            green.runner = crate::runner::Runner::Podman;
+            let err = green.check_runner_build_arguments().err().unwrap().to_string();
+            assert!(err.contains(r#""--allow" which runner podman does not support"#), "In: {err}");
+        }
+
+        #[test]
+        fn env_overrides() {
+            let manifest = Manifest::from_str("[package]\nname = \"test-package\"").unwrap();
+            temp_env::with_var(
+                ENV_ADDITIONAL_BUILD_ARGUMENTS!(),
+                Some("--ssh=default  --ulimit=nofile=1024"),
+                || {
+                    let green = Green::try_new(vec![], manifest).unwrap();
+                    assert_eq!(
+                        green.additional_build_arguments,
+                        vec!["--ssh=default".to_owned(), "--ulimit=nofile=1024".to_owned()]
+                    );
+                },
+            );
+        }
+    }
+
+    mod builder_tuning {
+        use super::super::{Green, Manifest};
+
+        fn try_new(conf: &str) -> anyhow::Result<Green> {
+            let manifest = Manifest::from_str(&format!(
+                r#"
+[package]
+name = "test-package"
+
+[package.metadata.green]
+{conf}
+"#
+            ))
+            .unwrap();
+            Green::try_new(vec![], manifest)
+        }
+
+        #[test]
+        fn ok() {
+            let green = try_new(
+                r#"
+[package.metadata.green.builder]
+max-parallelism = 8
+gc-keep-storage = "20GB"
+gc-policies = [ { filters = [ "type==source.local" ], keep-duration = "48h" } ]
+entitlements = [ "security.insecure" ]
+"#,
+            )
+            .unwrap();
+            assert_eq!(green.builder.tuning.max_parallelism, Some(8));
+            assert_eq!(green.builder.tuning.gc_keep_storage.as_deref(), Some("20GB"));
+            assert_eq!(
+                green.builder.tuning.gc_policies[0].to_string(),
+                "filter=type==source.local,keep-duration=48h"
+            );
+            assert_eq!(green.builder.tuning.entitlements, vec!["security.insecure".to_owned()]);
+            assert_eq!(
+                green.origins.provenance("builder.max-parallelism"),
+                "[package.metadata.green]"
+            );
+        }
+
+        #[test_case::test_case("builder.max-parallelism = 0", ".builder.max-parallelism]", "positive"; "no parallelism")]
+        #[test_case::test_case(r#"builder.gc-keep-storage = "lots""#, ".builder.gc-keep-storage]", "size"; "bad storage")]
+        #[test_case::test_case("builder.gc-policies = [ { all = true } ]", ".builder.gc-policies]", "no limit"; "no limit")]
+        #[test_case::test_case(r#"builder.gc-policies = [ { keep-bytes = "1GB" } ]"#, "]", "keep-bytes"; "unknown key")]
+        #[test_case::test_case(r#"builder.entitlements = [ "network.none" ]"#, ".builder.entitlements]", "only accepts"; "unknown entitlement")]
+        #[test_case::test_case("builder-max-parallelism = 8", "]", "builder-max-parallelism"; "flat key")]
+        fn bad(conf: &str, key: &str, reason: &str) {
+            let err = try_new(conf).err().unwrap().to_string();
+            assert!(err.contains(&format!("[package.metadata.green{key}")), "In: {err}");
+            assert!(err.contains(reason), "In: {err}");
+        }
+
+        #[test]
+        fn registries() {
+            let green = try_new(
+                r#"
+[package.metadata.green.registries."ghcr.io"]
+mirrors = [ "mirror.internal/ghcr" ]
+
+[package.metadata.green.registries."registry.internal:5000"]
+ca = [ "/etc/ssl/certs/internal-ca.pem" ]
+"#,
+            )
+            .unwrap();
+            assert_eq!(green.builder.registries.len(), 2);
+            assert_eq!(green.builder.registries["ghcr.io"].mirrors, ["mirror.internal/ghcr"]);
+            assert_eq!(
+                green.origins.provenance("registries.ghcr.io.mirrors"),
+                "[package.metadata.green]"
+            );
+
+            let err = try_new(
+                r#"
+[package.metadata.green.registries."docker.io"]
+mirrors = [ "mirror.internal/hub" ]
+"#,
+            )
+            .err()
+            .unwrap()
+            .to_string();
+            assert!(
+                err.contains("use [package.metadata.green.registry-mirrors] instead"),
+                "In: {err}"
+            );
+
+            let err = try_new(
+                r#"
+[package.metadata.green.registries."ghcr.io"]
+keypair = [ "/etc/ssl/certs/key.pem" ]
+"#,
+            )
+            .err()
+            .unwrap()
+            .to_string();
+            assert!(err.contains("keypair"), "In: {err}");
+        }
+
+        #[test]
+        fn env_overrides() {
+            temp_env::with_vars(
+                [
+                    (ENV_BUILDER_MAX_PARALLELISM!(), Some("2")),
+                    (ENV_BUILDER_GC_POLICIES!(), Some("all=true,max-used-space=50%")),
+                ],
+                || {
+                    let green = try_new("builder.max-parallelism = 8").unwrap();
+                    assert_eq!(green.builder.tuning.max_parallelism, Some(2));
+                    assert_eq!(
+                        green.builder.tuning.gc_policies[0].to_string(),
+                        "all=true,max-used-space=50%"
+                    );
+                    assert_eq!(
+                        green.origins.provenance("builder.max-parallelism"),
+                        "$CARGOGREEN_BUILDER_MAX_PARALLELISM"
+                    );
+                },
+            );
+            temp_env::with_var(ENV_BUILDER_MAX_PARALLELISM!(), Some("many"), || {
+                let err = try_new("").err().unwrap().to_string();
+                assert!(err.contains("$CARGOGREEN_BUILDER_MAX_PARALLELISM"), "In: {err}");
+            });
+        }
+    }
+
     mod components {
         use super::super::{Green, Manifest};
 
@@ -303,7 +1414,7 @@ components = [ "rust-src", "llvm-tools-preview" ]
 "#,
             )
             .unwrap();
-            let green = Green::try_new(manifest).unwrap();
+            let green = Green::try_new(vec![], manifest).unwrap();
             assert_eq!(
                 green.components,
                 vec!["rust-src".to_owned(), "llvm-tools-preview".to_owned()]
@@ -322,7 +1433,7 @@ components = [ "" ]
 "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("empty"), "In: {err}");
         }
 
@@ -338,7 +1449,7 @@ components = [ "'a'" ]
 "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("quotes"), "In: {err}");
         }
 
@@ -354,7 +1465,7 @@ components = [ "a b" ]
 "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("space"), "In: {err}");
         }
 
@@ -370,7 +1481,7 @@ components = [ "a", "b", "a" ]
             "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("duplicates"), "In: {err}");
         }
     }
@@ -391,7 +1502,7 @@ add.apk = [ "libpq-dev", "pkgconf" ]
 "#,
             )
             .unwrap();
-            let green = Green::try_new(manifest).unwrap();
+            let green = Green::try_new(vec![], manifest).unwrap();
             assert_eq!(green.add.apt, vec!["libpq-dev".to_owned(), "pkg-config".to_owned()]);
             assert_eq!(green.add.apk, vec!["libpq-dev".to_owned(), "pkgconf".to_owned()]);
         }
@@ -420,7 +1531,7 @@ add.{setting} = [ "" ]
 "#
             ))
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("empty"), "In: {err}");
         }
 
@@ -436,7 +1547,7 @@ add.{setting} = [ "'a'" ]
 "#
             ))
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("quotes"), "In: {err}");
         }
 
@@ -452,7 +1563,7 @@ add.{setting} = [ "a b" ]
 "#
             ))
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("space"), "In: {err}");
         }
 
@@ -468,7 +1579,7 @@ add.{setting} = [ "a", "b", "a" ]
             "#
             ))
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("duplicates"), "In: {err}");
         }
     }
@@ -488,7 +1599,7 @@ set-envs = [ "GIT_AUTH_TOKEN", "TYPENUM_BUILD_CONSTS", "TYPENUM_BUILD_OP" ]
 "#,
             )
             .unwrap();
-            let green = Green::try_new(manifest).unwrap();
+            let green = Green::try_new(vec![], manifest).unwrap();
             assert_eq!(
                 green.set_envs,
                 vec![
@@ -511,7 +1622,7 @@ set-envs = [ "" ]
 "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("empty name"), "In: {err}");
         }
 
@@ -527,7 +1638,7 @@ set-envs = [ "'a'" ]
 "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("quotes"), "In: {err}");
         }
 
@@ -543,7 +1654,7 @@ set-envs = [ "A B" ]
 "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("space"), "In: {err}");
         }
 
@@ -559,7 +1670,7 @@ set-envs = [ "CARGOGREEN_LOG" ]
 "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("CARGOGREEN"), "In: {err}");
         }
 
@@ -575,7 +1686,7 @@ set-envs = [ "A", "B", "A" ]
 "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("duplicates"), "In: {err}");
         }
     }
@@ -596,7 +1707,7 @@ base-image = "docker-image://docker.io/library/ubuntu:latest"
 "#,
             )
             .unwrap();
-            let green = Green::try_new(manifest).unwrap();
+            let green = Green::try_new(vec![], manifest).unwrap();
             assert_eq!(
                 green.base,
                 BaseImage { image: ImageUri::std("ubuntu:latest"), ..Default::default() }
@@ -616,7 +1727,7 @@ base-image = "docker-image://docker.io/library/ubuntu:latest"
 "#,
             )
             .unwrap();
-            let green = Green::try_new(manifest).unwrap();
+            let green = Green::try_new(vec![], manifest).unwrap();
             assert_eq!(
                 green.base,
                 BaseImage {
@@ -639,7 +1750,7 @@ base-image = ""
 "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("scheme"), "In: {err}");
         }
 
@@ -655,7 +1766,7 @@ base-image = "docker.io/library/ubuntu:latest"
 "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("scheme"), "In: {err}");
         }
 
@@ -671,7 +1782,7 @@ base-image = " docker-image://docker.io/library/ubuntu:latest  "
 "#,
             )
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("space"), "In: {err}");
         }
     }
@@ -695,7 +1806,7 @@ name = "test-package"
 "#,
             ))
             .unwrap();
-            let green = Green::try_new(manifest).unwrap();
+            let green = Green::try_new(vec![], manifest).unwrap();
             assert_eq!(
                 match setting {
                     "cache-images" => green.cache.images,
@@ -726,7 +1837,7 @@ name = "test-package"
 "#,
             ))
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("duplicates"), "In: {err}");
         }
 
@@ -742,7 +1853,7 @@ name = "test-package"
 "#,
     ))
     .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("names"), "In: {err}");
         }
 
@@ -758,7 +1869,7 @@ name = "test-package"
 "#,
             ))
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("scheme"), "In: {err}");
         }
 
@@ -774,7 +1885,7 @@ name = "test-package"
 "#,
             ))
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("registry"), "In: {err}");
         }
 
@@ -790,7 +1901,7 @@ name = "test-package"
 "#,
             ))
             .unwrap();
-            let err = Green::try_new(manifest).err().unwrap().to_string();
+            let err = Green::try_new(vec![], manifest).err().unwrap().to_string();
             assert!(err.contains("tag"), "In: {err}");
         }
     }
@@ -1,22 +1,11 @@
-use std::{
-    error::Error as StdError,
-    sync::{LazyLock, Once},
-    time::Duration,
-};
+use std::sync::LazyLock;
 
 use anyhow::{Error, Result, anyhow, bail};
-use camino::Utf8Path;
-use log::info;
 use nutype::nutype;
-use reqwest::{Client as ReqwestClient, Request};
-use serde::Deserialize;
 
 use crate::{
-    du::lock_from_builder_cache,
-    ext::CommandExt,
-    green::Green,
-    retrier::Retrier,
-    runner::{DOCKER_HOST, Runner},
+    du::lock_from_builder_cache, ext::CommandExt, green::Green, registry::fetch_manifest_digest,
+    runner::DOCKER_HOST,
 };
 
 pub(crate) const BAD_CHARS: &[char] = &[' ', '\'', '"', ';', '\\', ','];
@@ -365,17 +354,20 @@ impl Green {
     ///
     /// <https://docs.docker.com/dhi/core-concepts/digests/>
     async fn maybe_lock_from_image_cache(&self, img: &ImageUri) -> Result<Option<ImageUri>> {
-        if self.runner.is_none() {
-            info!("Skipping inspecting image cache (runner:{})", self.runner);
-            return Ok(None);
-        }
+        self.backend().inspect_image(self, img).await
+    }
+
+    /// Reads `img`'s digest from the runner's image store.
+    pub(crate) async fn inspect_image_store(&self, img: &ImageUri) -> Result<Option<ImageUri>> {
         let mut cmd = self.cmd()?;
         cmd.arg("inspect").arg("--format={{index .RepoDigests 0}}").arg(img.noscheme());
 
         let (succeeded, stdout, stderr) = cmd.exec().await?;
         if !succeeded {
             let stderr = String::from_utf8_lossy(&stderr);
-            if stderr.to_lowercase().contains("no such object") {
+            // `nerdctl` says "no such image"
+            if ["no such object", "no such image"].iter().any(|e| stderr.to_lowercase().contains(e))
+            {
                 return Ok(None);
             }
 
@@ -405,86 +397,22 @@ Maybe have a look at
     }
 }
 
-/// If given an un-pinned image URI, query remote image API for its digest.
-///
-/// No-op for an already locked image URI.
-pub(crate) async fn fetch_digest(runner: &Runner, img: &ImageUri) -> Result<ImageUri> {
-    // TODO: add+impl traits on runner (fetch_digest, do_build, ..) Maybe on Green?
-    if runner.is_none() {
-        info!("Skipping fetching image digest (runner:{runner})");
-        return Ok(img.to_owned());
-    }
-
-    if img.locked() {
-        return Ok(img.to_owned());
-    }
-
-    const DOMAIN: &str = "registry.hub.docker.com";
-
-    fn request(img: &ImageUri) -> Result<(ReqwestClient, Request)> {
-        let (path, tag) = img.path_and_tag();
-        let (ns, slug) = match Utf8Path::new(path).iter().collect::<Vec<_>>()[..] {
-            ["docker.io", ns, slug] => (ns, slug),
-            _ => bail!("BUG: unhandled registry {img:?}"),
-        };
-
-        let (client, req) = ReqwestClient::builder()
-            .connect_timeout(Duration::from_secs(4))
-            .build()
-            .map_err(|e| anyhow!("HTTP client's config/TLS failed: {e}"))?
-            .get(format!("https://{DOMAIN}/v2/repositories/{ns}/{slug}/tags/{tag}"))
-            .build_split();
-        let req = req.map_err(|e| {
-            // e.source(): try to be a bit more helpful than just "error sending request for url"
-            anyhow!("Failed to build a request against {DOMAIN}: {e} ({:?})", e.source())
-        })?;
-        Ok((client, req))
-    }
-
-    async fn actual(img: &ImageUri) -> Result<ImageUri> {
-        let show = Once::new();
-        let mut retrier = Retrier::with_max_attempts(5);
-        let txt;
-        loop {
-            let (client, req) = request(img)?;
-            show.call_once(|| {
-                info!("GETing {}", req.url());
-                eprintln!("GETing {}", req.url());
-            });
-
-            // Eg.: error sending request for url (https://registry.hub.docker.com/v2/repositories/moby/buildkit/tags/latest)
-            let req = match client.execute(req).await {
-                Ok(req) => req,
-                Err(e) if retrier.continues() => {
-                    retrier.backoff("connection", e.into()).await;
-                    continue;
-                }
-                Err(e) => {
-                    let e = anyhow!("retried {} times: {e}", retrier.max());
-                    bail!("Failed to reach {DOMAIN}'s registry: {e}")
-                }
-            };
-
-            txt = req
-                .text()
-                .await
-                .map_err(|e| anyhow!("Failed to read response from {DOMAIN} registry: {e}"))?;
-            break;
-        }
-
-        #[derive(Deserialize)]
-        struct RegistryResponse {
-            digest: String,
+impl Green {
+    /// If given an un-pinned image URI, query remote image API for its digest.
+    ///
+    /// No-op for an already locked image URI.
+    pub(crate) async fn fetch_digest(&self, img: &ImageUri) -> Result<ImageUri> {
+        if img.locked() {
+            return Ok(img.to_owned());
         }
-        let RegistryResponse { digest } = serde_json::from_str(&txt)
-            // NOTE: library images can take a few days to appear, after a Rust release:
-            // Error: Failed to decode response from registry: missing field `digest` at line 1 column 130
-            // {"message":"httperror 404: tag '1.89.0-slim' not found","errinfo":{"namespace":"library","repository":"rust","tag":"1.89.0-slim"}}
-            .map_err(|e| anyhow!("Failed to decode response from registry: {e}\n{txt}"))?;
-        // digest ~ sha256:..
+        self.backend().fetch_digest(self, img).await
+    }
 
+    /// Locks `img` through its registry, honoring its `registries` settings
+    pub(crate) async fn lock_from_registry(&self, img: &ImageUri) -> Result<ImageUri> {
+        let digest = fetch_manifest_digest(img, self.builder.registries.get(img.host()))
+            .await
+            .map_err(|e| anyhow!("Failed getting digest for {img}: {e}"))?;
         Ok(img.lock(&digest))
     }
-
-    actual(img).await.map_err(|e| anyhow!("Failed getting digest for {img}: {e}"))
 }
@@ -27,6 +27,11 @@ pub(crate) async fn locked_crates(
     Ok(packages)
 }
 
+/// Counts all locked packages, local ones included
+pub(crate) fn count_packages(lockfile: &Utf8Path) -> Result<usize> {
+    Ok(Lockfile::load(lockfile)?.packages.len())
+}
+
 pub(crate) async fn find_lockfile() -> Result<Utf8PathBuf> {
     let manifest_path = find_manifest_path().await?;
     let candidate = manifest_path.with_extension("lock");
@@ -66,6 +71,18 @@ pub(crate) async fn find_manifest_path() -> Result<Utf8PathBuf> {
     cargo_metadata(false).await
 }
 
+/// Finds the manifest holding the `[workspace]` that contains the given package manifest.
+///
+/// That may be the package manifest itself.
+pub(crate) async fn find_workspace_manifest_path(manifest_path: &Utf8Path) -> Result<Utf8PathBuf> {
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .manifest_path(manifest_path)
+        .no_deps()
+        .exec()
+        .map_err(|e| anyhow!("Failed running cargo metadata: {e}"))?;
+    Ok(metadata.workspace_root.join("Cargo.toml"))
+}
+
 // TODO: memoize call "for speed"
 async fn cargo_metadata(of_workspace: bool) -> Result<Utf8PathBuf> {
     let metadata = cargo_metadata::MetadataCommand::new()
@@ -22,31 +22,43 @@ mod green;
 #[macro_use]
 mod logging;
 #[macro_use]
+mod progress;
+#[macro_use]
 mod runner;
 #[macro_use]
 mod wrap;
 
+mod backend;
 mod build;
+mod buildctl;
 mod buildkitd;
 mod cargo_green;
 mod checkouts;
 mod containerfile;
 mod cratesio;
+mod cross;
 mod dirs;
+mod doctor;
 mod du;
 mod ext;
 mod image_uri;
 mod lockfile;
 mod md;
+mod nerdctl;
 mod network;
+mod packages;
+mod podman;
 mod rechrome;
+mod registry;
 mod relative;
+mod remotes;
 mod retrier;
 mod rustc_arguments;
 mod rustup;
 mod stage;
 mod supergreen;
 mod target_dir;
+mod update_images;
 
 const PKG: &str = env!("CARGO_PKG_NAME");
 const REPO: &str = env!("CARGO_PKG_REPOSITORY");
@@ -161,8 +173,6 @@ async fn actual_main() -> Result<()> {
     }
     cmd.env("RUSTC_WRAPPER", arg0);
 
-    // TODO: TUI above cargo output (? https://docs.rs/prodash )
-
     if let Ok(log) = env::var(ENV_LOG!()) {
         cmd.env(ENV_LOG!(), log);
         let var = ENV_LOG_PATH!();
@@ -185,13 +195,15 @@ async fn actual_main() -> Result<()> {
         return Ok(());
     }
 
-    let green = cargo_green::main(is_install).await?;
-    cmd.env(ENV_ROOT_PACKAGE_SETTINGS, serde_json::to_string(&green)?);
+    let green = cargo_green::main(is_install).await;
 
     if command.as_deref() == Some("supergreen") {
         return supergreen::main(green).await;
     }
 
+    let green = green?;
+    cmd.env(ENV_ROOT_PACKAGE_SETTINGS, serde_json::to_string(&green)?);
+
     if command.as_deref() == Some("fetch") {
         // Runs actual `cargo fetch`
         if !cmd.status().await?.success() {
@@ -206,7 +218,7 @@ async fn actual_main() -> Result<()> {
     // SAFETY: environment access only happens in single-threaded code.
     unsafe { env::set_var("CARGO_TARGET_DIR", target_dir) };
 
-    if !cmd.status().await?.success() {
+    if !progress::status(&mut cmd).await?.success() {
         bail!(EEXIT)
     }
     Ok(())
@@ -11,19 +11,33 @@ use crate::md::{Md, MdId};
 #[derive(Debug)]
 pub(crate) struct Mds {
     target_path: Utf8PathBuf,
+    /// When cross-compiling: where host artifacts' Mds are
+    host_target_path: Option<Utf8PathBuf>,
     cache: HashMap<MdId, Rc<Md>>,
 }
 
 impl Mds {
     pub(crate) fn new(path: &Utf8Path) -> Self {
-        Self { target_path: path.to_owned(), cache: HashMap::default() }
+        Self { target_path: path.to_owned(), host_target_path: None, cache: HashMap::default() }
+    }
+
+    #[must_use]
+    pub(crate) fn with_host(mut self, host_target_path: Option<&Utf8Path>) -> Self {
+        self.host_target_path = host_target_path.map(ToOwned::to_owned);
+        self
     }
 
     pub(crate) fn load(&mut self, mdid: MdId) -> Result<Rc<Md>> {
         if let Some(md) = self.cache.get(&mdid) {
             return Ok(Rc::clone(md));
         }
-        let md = Md::from_file(&mdid.path(&self.target_path))?;
+        let mut path = mdid.path(&self.target_path);
+        if let Some(ref host_target_path) = self.host_target_path
+            && !path.exists()
+        {
+            path = mdid.path(host_target_path);
+        }
+        let md = Md::from_file(&path)?;
         let md = Rc::new(md);
         let _ = self.cache.insert(mdid, Rc::clone(&md));
         Ok(md)
@@ -14,6 +14,7 @@ use crate::{
     build::SOURCE_DATE_EPOCH,
     green::Green,
     logging::maybe_log,
+    packages::PackageOverride,
     stage::{AsBlock, AsStage, NamedStage, RST, Script, Stage},
     target_dir::virtual_target_dir,
 };
@@ -61,6 +62,10 @@ pub(crate) struct Md {
     #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
     pub(crate) set_envs: IndexMap<String, String>,
 
+    /// Per-package settings that applied to this crate
+    #[serde(default, skip_serializing_if = "PackageOverride::is_empty")]
+    pub(crate) overrides: PackageOverride,
+
     ///
 
     /// Out-of-build directories that get mounted (eg. crate code under $PWD)
@@ -101,6 +106,7 @@ impl From<MdId> for Md {
             writes_to: None,
             mounts: IndexSet::new(),
             set_envs: IndexMap::new(),
+            overrides: PackageOverride::default(),
             contexts: IndexSet::new(),
             stages: IndexSet::new(),
             writes: vec![],
@@ -252,8 +258,9 @@ impl Md {
         externs: IndexSet<String>,
         out_dir_var: Option<Utf8PathBuf>,
         target_path: &Utf8Path,
+        host_target_path: Option<&Utf8Path>,
     ) -> Result<Vec<Rc<Self>>> {
-        let mut mds = Mds::new(target_path);
+        let mut mds = Mds::new(target_path).with_host(host_target_path);
         let has_rmetas = externs.iter().any(|xtern| xtern.ends_with(".rmeta"));
 
         let (buildrs_results, mounts, extern_mdids) = walk_transitives(&mut mds, externs)?;
@@ -441,6 +448,7 @@ fn md_ser() {
         writes_to: None,
         mounts: [].into(),
         set_envs: [].into(),
+        overrides: PackageOverride::default(),
         contexts: [BuildContext {
             name: "rust".try_into().unwrap(),
             uri: "/some/local/path".into(),
@@ -0,0 +1,169 @@
+//! Builds through `nerdctl build`, for hosts running containerd without dockerd.
+//!
+//! `nerdctl` embeds no builder: it drives the BuildKit daemon of its containerd namespace
+//! (`$BUILDKIT_HOST`, else `/run/buildkit-<namespace>/buildkitd.sock`) through `buildctl`.
+//!
+//! Compared to [`Green::with_docker_args`]:
+//! * `--cache-from=`, `--cache-to=`, `--build-context=`, `--network=`, `--no-cache` and `--target=` map 1:1
+//! * `--cache-to=` always uses `mode=max`: there is no `docker` driver, so no inline cache, `--tag` nor `--load`
+//! * `--platform=local` is dropped as `nerdctl` has no such value, other platforms map 1:1
+//! * `--pull=false` is dropped: BuildKit's default already resolves images through its cache
+//! * `--output=type=tar` becomes `--output=type=tar,dest=-`: `nerdctl` loads into containerd otherwise
+//! * `--output=type=cacheonly` becomes `--output=type=tar,dest=/dev/null`: there is no cache-only exporter
+//! * `-` (Dockerfile on STDIN) becomes `--file=` and an empty context directory
+
+use anyhow::Result;
+use log::info;
+use tokio::process::Command;
+
+use crate::{
+    build::BuildRequest, dirs::empty_context, ext::CommandExt, green::Green, md::BuildContext,
+    progress,
+};
+
+impl Green {
+    /// The `nerdctl build` equivalent of [`Self::with_docker_args`]
+    pub(crate) fn with_nerdctl_args(
+        &self,
+        cmd: &mut Command,
+        req: &BuildRequest<'_>,
+    ) -> Result<(String, String)> {
+        let BuildRequest { containerfile, target, contexts, out_dir, export, tui } = *req;
+
+        cmd.arg(format!("--progress={}", if tui { "auto" } else { "plain" }));
+
+        if self.repro() {
+            cmd.arg("--no-cache");
+        }
+
+        for img in self.cache.from_images.iter().chain(self.cache.images.iter()) {
+            let img = img.noscheme();
+            cmd.arg(format!("--cache-from=type=registry,ref={img}"));
+        }
+        for img in self.cache.to_images.iter().chain(self.cache.images.iter()) {
+            let img = img.noscheme();
+            cmd.arg(format!("--cache-to=type=registry,ref={img},mode=max,ignore-error=false"));
+        }
+
+        cmd.arg(format!("--network={}", self.base.with_network));
+
+        let platform = self.platform();
+        if platform != "local" {
+            cmd.arg(format!("--platform={platform}"));
+        }
+        cmd.arg(format!("--target={target}"));
+
+        if out_dir.is_some() {
+            cmd.arg("--output=type=tar,dest=-");
+        } else {
+            cmd.arg("--output=type=tar,dest=/dev/null");
+        }
+
+        if let Some(dst) = export {
+            cmd.arg(self.builder.export_arg(dst));
+        }
+        if let Some(ref dirs) = self.dirs
+            && self.cachebuildkit()
+            && let Some(src) = dirs.runner_cache(target)
+        {
+            cmd.arg(self.builder.import_arg(&src));
+        }
+
+        for BuildContext { name, uri } in contexts {
+            cmd.arg(format!("--build-context={name}={uri}"));
+        }
+
+        cmd.args(&self.additional_build_arguments);
+
+        // No reading a Dockerfile from STDIN here, and no context to upload.
+        cmd.arg(format!("--file={containerfile}"));
+        cmd.arg(empty_context()?);
+
+        if out_dir.is_some() {
+            cmd.stdout(std::process::Stdio::piped());
+            cmd.stderr(std::process::Stdio::piped());
+        } else if !tui {
+            cmd.stderr(std::process::Stdio::piped());
+        }
+
+        let call = cmd.show();
+        let envs = cmd.envs_string(&self.runner.buildnoop_envs());
+        if !tui {
+            info!("Starting `{envs} {call}`");
+            if !progress::is_shown() {
+                eprintln!("Starting `{envs} {call}`");
+            }
+        }
+        let call = call
+            .split_whitespace()
+            .filter(|flag| !self.runner.buildnoop_flags().any(|prefix| flag.starts_with(prefix)))
+            .filter(|flag| !flag.starts_with("--target=") && *flag != "--network=default")
+            .map(|flag| match flag {
+                _ if flag.starts_with("--output=") => "--output=type=local,dest=.",
+                _ if flag.starts_with("--file=") => "--file=THIS_FILE",
+                _ => flag,
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace(cmd.as_std().get_program().to_str().unwrap(), "nerdctl");
+
+        Ok((call, envs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        image_uri::ImageUri,
+        network::Network,
+        runner::Runner,
+        stage::{RUST, Stage},
+    };
+
+    #[test]
+    fn nerdctl_args() {
+        let mut green = Green { runner: Runner::Nerdctl, ..Default::default() };
+        green.cache.images = vec![ImageUri::try_new("docker-image://my.org/team/cache").unwrap()];
+        green.base.with_network = Network::None;
+
+        let name = Stage::new("input_src").unwrap();
+        let contexts = [BuildContext { name, uri: "/some/src".into() }].into();
+
+        let mut cmd = Command::new("nerdctl");
+        cmd.arg("build");
+        let req = BuildRequest {
+            containerfile: "/target/debug/x.Dockerfile".into(),
+            target: &RUST,
+            contexts: &contexts,
+            out_dir: None,
+            export: None,
+            tui: false,
+        };
+        let (call, _) = green.with_nerdctl_args(&mut cmd, &req).unwrap();
+
+        let empty = empty_context().unwrap();
+        let args: Vec<_> = cmd.as_std().get_args().map(|arg| arg.to_str().unwrap()).collect();
+        pretty_assertions::assert_eq!(
+            args,
+            [
+                "build",
+                "--progress=plain",
+                "--cache-from=type=registry,ref=my.org/team/cache",
+                "--cache-to=type=registry,ref=my.org/team/cache,mode=max,ignore-error=false",
+                "--network=none",
+                "--target=rust-base",
+                "--output=type=tar,dest=/dev/null",
+                "--build-context=input_src=/some/src",
+                "--file=/target/debug/x.Dockerfile",
+                empty.as_str(),
+            ]
+        );
+        assert_eq!(
+            call,
+            format!(
+                "nerdctl build --progress=plain --network=none --output=type=local,dest=. --build-context=input_src=/some/src --file=THIS_FILE {empty}"
+            )
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+
+use anyhow::{Result, anyhow, bail};
+use cargo_toml::{SemVer, VersionReq};
+use indexmap::IndexMap;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{add::Add, green::Green, image_uri::BAD_CHARS, md::Md, network::Network};
+
+/// Settings that only apply when building or running the build script of some packages.
+///
+/// Set under `[package.metadata.green.packages.<name>.'<version requirement>']`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct PackageOverride {
+    /// Environment variables to pass through, in addition to `set-envs`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) set_envs: Vec<String>,
+
+    /// OS packages to install, in addition to `add.apk` and `add.apt`
+    #[serde(skip_serializing_if = "Add::is_empty")]
+    pub(crate) add: Add,
+
+    /// Network to use instead of `with-network`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) with_network: Option<Network>,
+}
+
+/// Package names to version requirements to overrides
+pub(crate) type Packages = IndexMap<String, IndexMap<String, PackageOverride>>;
+
+impl PackageOverride {
+    #[must_use]
+    pub(crate) fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Layers `other` on top of `self`
+    fn merge(&mut self, other: &Self) {
+        for var in &other.set_envs {
+            if !self.set_envs.contains(var) {
+                self.set_envs.push(var.clone());
+            }
+        }
+        self.add = std::mem::take(&mut self.add).union(&other.add);
+        if other.with_network.is_some() {
+            self.with_network = other.with_network;
+        }
+    }
+}
+
+/// Checks names, version requirements and values of per-package settings.
+pub(crate) fn check_packages(packages: &Packages, origin: &str) -> Result<()> {
+    for (name, reqs) in packages {
+        if name.is_empty() || name.contains(BAD_CHARS) || name.trim() != name {
+            bail!("{origin} contains a bad package name: {name:?}")
+        }
+        for (req, over) in reqs {
+            let origin = format!("{origin} {name}@{req:?}");
+            VersionReq::parse(req).map_err(|e| anyhow!("{origin} has a bad version: {e}"))?;
+
+            let PackageOverride { set_envs, add, with_network: _ } = over;
+            for (field, what) in
+                [(set_envs, "set-envs"), (&add.apk, "add.apk"), (&add.apt, "add.apt")]
+            {
+                if field.iter().any(|x| x.is_empty() || x.contains(BAD_CHARS) || x.trim() != x) {
+                    bail!(
+                        "{origin} {what} contains empty names, whitespace, quotes or bad characters"
+                    )
+                }
+                if field.len() != field.iter().collect::<HashSet<_>>().len() {
+                    bail!("{origin} {what} contains duplicates")
+                }
+            }
+            if set_envs.iter().any(|var| var.starts_with("CARGOGREEN_")) {
+                bail!("{origin} set-envs contains CARGOGREEN_* names")
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Green {
+    /// Applies the package's overrides to the stage `block` being written, and to `self`.
+    ///
+    /// Returns the names of the environment variables to pass through.
+    pub(crate) fn apply_package_override(
+        &mut self,
+        md: &mut Md,
+        (name, version): (&str, &str),
+        block: &mut String,
+    ) -> Result<Vec<String>> {
+        let over = self.package_override(name, version)?;
+
+        let mut set_envs = self.set_envs.clone();
+        if over.is_empty() {
+            return Ok(set_envs);
+        }
+        info!("applying overrides to {name}@{version}: {over:?}");
+
+        for var in &over.set_envs {
+            if !set_envs.contains(var) {
+                set_envs.push(var.clone());
+            }
+        }
+
+        if !over.add.is_empty() {
+            // NOTE: relies on the xx stage being part of the base block
+            block.push_str(&over.add.as_install());
+        }
+
+        if let Some(with_network) = over.with_network {
+            self.base.with_network = with_network;
+        }
+
+        md.overrides = over;
+        Ok(set_envs)
+    }
+
+    /// Sums all the overrides that match the given package, in the order they were declared.
+    pub(crate) fn package_override(&self, name: &str, version: &str) -> Result<PackageOverride> {
+        let mut applied = PackageOverride::default();
+        let Some(reqs) = self.packages.get(name) else { return Ok(applied) };
+
+        let version = SemVer::parse(version)
+            .map_err(|e| anyhow!("Failed parsing {name}'s version {version:?}: {e}"))?;
+        for (req, over) in reqs {
+            let req = VersionReq::parse(req).map_err(|e| anyhow!("BUG: unchecked {req:?}: {e}"))?;
+            if req.matches(&version) {
+                applied.merge(over);
+            }
+        }
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn green(packages: &str) -> Green {
+        let packages: Packages = toml::from_str(packages).unwrap();
+        check_packages(&packages, "[packages]").unwrap();
+        Green { packages, ..Default::default() }
+    }
+
+    #[test]
+    fn matching() {
+        let green = green(
+            r#"
+[typenum.'*']
+set-envs = [ "TYPENUM_BUILD_OP" ]
+
+[typenum.'>=1.17']
+set-envs = [ "TYPENUM_BUILD_CONSTS", "TYPENUM_BUILD_OP" ]
+add.apt = [ "libfoo-dev" ]
+
+[typenum.'=1.16.0']
+with-network = "default"
+"#,
+        );
+
+        let over = green.package_override("typenum", "1.18.0").unwrap();
+        assert_eq!(
+            over,
+            PackageOverride {
+                set_envs: vec!["TYPENUM_BUILD_OP".to_owned(), "TYPENUM_BUILD_CONSTS".to_owned()],
+                add: Add { apt: vec!["libfoo-dev".to_owned()], ..Default::default() },
+                with_network: None,
+            }
+        );
+
+        let over = green.package_override("typenum", "1.16.0").unwrap();
+        assert_eq!(over.set_envs, vec!["TYPENUM_BUILD_OP".to_owned()]);
+        assert_eq!(over.with_network, Some(Network::Default));
+
+        assert!(green.package_override("other", "1.0.0").unwrap().is_empty());
+    }
+
+    #[test]
+    fn bad_version_req() {
+        let packages: Packages = toml::from_str("[typenum.'not a version']").unwrap();
+        let err = check_packages(&packages, "[packages]").err().unwrap().to_string();
+        assert!(err.contains("typenum"), "In: {err}");
+        assert!(err.contains("version"), "In: {err}");
+    }
+
+    #[test]
+    fn our_vars() {
+        let packages: Packages =
+            toml::from_str("[typenum.'*']\nset-envs = [ \"CARGOGREEN_LOG\" ]").unwrap();
+        let err = check_packages(&packages, "[packages]").err().unwrap().to_string();
+        assert!(err.contains("CARGOGREEN"), "In: {err}");
+    }
+}
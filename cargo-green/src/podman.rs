@@ -0,0 +1,266 @@
+//! Builds through `podman build`, which runs Buildah rather than BuildKit.
+//!
+//! Compared to [`Green::with_docker_args`]:
+//! * `--build-context=`, `--no-cache`, `--platform=` and `--target=` map 1:1
+//! * `--cache-from=type=registry,ref=R` becomes `--cache-from=R` (same for `--cache-to=`),
+//!   along with `--layers`: intermediate layers are what gets cached
+//! * `--network=default` is dropped, `none` and `host` map 1:1
+//! * `--pull=false` is dropped: podman's default only pulls missing images
+//! * `--output=type=tar` becomes `--output=type=tar,dest=-`
+//! * `--output=type=cacheonly` is dropped: the result is committed to the local store,
+//!   which doubles as podman's layer cache
+//! * `-` (Dockerfile on STDIN) becomes `--file=` and an empty context directory
+//! * Exporting to / importing from a local BuildKit cache directory is refused
+//!
+//! There is no builder to manage: podman builds in-process.
+
+use std::process::Stdio;
+
+use anyhow::{Result, anyhow, bail};
+use futures::stream::{StreamExt, TryStreamExt, iter};
+use log::info;
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::{
+    build::BuildRequest, dirs::empty_context, ext::CommandExt, r#final::is_primary, green::Green,
+    md::BuildContext, network::Network, progress,
+};
+
+impl Green {
+    /// The `podman build` equivalent of [`Self::with_docker_args`]
+    pub(crate) fn with_podman_args(
+        &self,
+        cmd: &mut Command,
+        req: &BuildRequest<'_>,
+    ) -> Result<(String, String)> {
+        let BuildRequest { containerfile, target, contexts, out_dir, export, tui } = *req;
+
+        if let Some(dst) = export {
+            bail!("Runner {} cannot export its cache to {dst}", self.runner)
+        }
+
+        if self.repro() {
+            cmd.arg("--no-cache");
+        }
+
+        cmd.arg("--layers");
+        for img in self.cache.from_images.iter().chain(self.cache.images.iter()) {
+            cmd.arg(format!("--cache-from={}", img.noscheme()));
+        }
+        for img in self.cache.to_images.iter().chain(self.cache.images.iter()) {
+            let img = img.noscheme();
+            cmd.arg(format!("--cache-to={img}"));
+
+            // For `supergreen push`
+            cmd.arg(format!("--tag={img}:{target}"));
+            if is_primary() {
+                cmd.arg(format!("--tag={img}:latest"));
+            }
+        }
+
+        if self.base.with_network != Network::Default {
+            cmd.arg(format!("--network={}", self.base.with_network));
+        }
+
+        let platform = self.platform();
+        if platform != "local" {
+            cmd.arg(format!("--platform={platform}"));
+        }
+        cmd.arg(format!("--target={target}"));
+
+        if out_dir.is_some() {
+            cmd.arg("--output=type=tar,dest=-");
+        }
+
+        for BuildContext { name, uri } in contexts {
+            cmd.arg(format!("--build-context={name}={uri}"));
+        }
+
+        cmd.args(&self.additional_build_arguments);
+
+        // No reading a Dockerfile from STDIN here, and no context to upload.
+        cmd.arg(format!("--file={containerfile}"));
+        cmd.arg(empty_context()?);
+
+        if out_dir.is_some() {
+            cmd.stdout(Stdio::piped());
+            cmd.stderr(Stdio::piped());
+        } else if !tui {
+            cmd.stderr(Stdio::piped());
+        }
+
+        let call = cmd.show();
+        let envs = cmd.envs_string(&self.runner.buildnoop_envs());
+        if !tui {
+            info!("Starting `{envs} {call}`");
+            if !progress::is_shown() {
+                eprintln!("Starting `{envs} {call}`");
+            }
+        }
+        let call = call
+            .split_whitespace()
+            .filter(|flag| !self.runner.buildnoop_flags().any(|prefix| flag.starts_with(prefix)))
+            .filter(|flag| !flag.starts_with("--target=") && !flag.starts_with("--tag="))
+            .filter(|flag| *flag != "--layers")
+            .map(|flag| match flag {
+                _ if flag.starts_with("--output=") => "--output=type=local,dest=.",
+                _ if flag.starts_with("--file=") => "--file=THIS_FILE",
+                _ => flag,
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace(cmd.as_std().get_program().to_str().unwrap(), "podman");
+
+        Ok((call, envs))
+    }
+
+    /// Pushes every local tag of `img`, as `podman push` has no `--all-tags`.
+    ///
+    /// <https://github.com/containers/podman/issues/2369>
+    pub(crate) async fn push_podman_tags(&self, img: &str) -> Result<()> {
+        let tags = self.podman_tags_of(img).await?;
+
+        async fn do_push(green: &Green, tag: String, img: &str) -> Result<()> {
+            let tagged = format!("{img}:{tag}");
+            println!("Pushing {tagged}...");
+
+            // Manifest lists (multi-platform images) only go through `podman manifest push`
+            let mut cmd = green.cmd()?;
+            cmd.args(["manifest", "exists", &tagged]).stdout(Stdio::null()).stderr(Stdio::null());
+            let is_list = cmd.status().await.is_ok_and(|o| o.success());
+
+            let mut cmd = green.cmd()?;
+            if is_list {
+                cmd.args(["manifest", "push", "--all", &tagged, &format!("docker://{tagged}")]);
+            } else {
+                cmd.args(["push", &tagged]);
+            }
+            cmd.stdout(Stdio::null()).stderr(Stdio::null());
+
+            if let Ok(mut o) = cmd.spawn()
+                && let Ok(o) = o.wait().await
+                && o.success()
+            {
+                println!("Pushing {tagged}... done!");
+                return Ok(());
+            }
+            bail!("Pushing {tagged} failed!")
+        }
+
+        iter(tags).map(|tag| do_push(self, tag, img)).buffer_unordered(10).try_collect::<()>().await
+    }
+
+    async fn podman_tags_of(&self, img: &str) -> Result<Vec<String>> {
+        let mut cmd = self.cmd()?;
+        cmd.args(["images", "--format=json"]);
+        cmd.arg(format!("--filter=reference={img}"));
+
+        let (succeeded, stdout, stderr) = cmd.exec().await?;
+        if !succeeded {
+            let stderr = String::from_utf8_lossy(&stderr);
+            bail!("Failed to list tags of image {img}: {stderr}")
+        }
+        tags_of(img, &stdout)
+    }
+}
+
+/// An entry of `podman images --format=json`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PodmanImage {
+    #[serde(default)]
+    names: Vec<String>,
+}
+
+/// Unlike `docker image ls`, prints one JSON array, with fully qualified names.
+fn tags_of(img: &str, stdout: &[u8]) -> Result<Vec<String>> {
+    let images: Vec<PodmanImage> = serde_json::from_slice(stdout)
+        .map_err(|e| anyhow!("Failed decoding podman's images of {img}: {e}"))?;
+    let prefix = format!("{img}:");
+    let mut tags: Vec<_> = images
+        .into_iter()
+        .flat_map(|PodmanImage { names }| names)
+        .filter_map(|name| name.strip_prefix(&prefix).map(ToOwned::to_owned))
+        .collect();
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        image_uri::ImageUri,
+        runner::Runner,
+        stage::{RUST, Stage},
+    };
+
+    #[test]
+    fn podman_args() {
+        let mut green = Green { runner: Runner::Podman, ..Default::default() };
+        green.cache.images = vec![ImageUri::try_new("docker-image://my.org/team/cache").unwrap()];
+        green.base.with_network = Network::Host;
+
+        let name = Stage::new("input_src").unwrap();
+        let contexts = [BuildContext { name, uri: "/some/src".into() }].into();
+
+        let mut cmd = Command::new("podman");
+        cmd.arg("build");
+        let req = BuildRequest {
+            containerfile: "/target/debug/x.Dockerfile".into(),
+            target: &RUST,
+            contexts: &contexts,
+            out_dir: Some("/target/debug/deps".into()),
+            export: None,
+            tui: false,
+        };
+        let (call, _) = green.with_podman_args(&mut cmd, &req).unwrap();
+
+        let empty = empty_context().unwrap();
+        let args: Vec<_> = cmd.as_std().get_args().map(|arg| arg.to_str().unwrap()).collect();
+        pretty_assertions::assert_eq!(
+            args,
+            [
+                "build",
+                "--layers",
+                "--cache-from=my.org/team/cache",
+                "--cache-to=my.org/team/cache",
+                "--tag=my.org/team/cache:rust-base",
+                "--network=host",
+                "--target=rust-base",
+                "--output=type=tar,dest=-",
+                "--build-context=input_src=/some/src",
+                "--file=/target/debug/x.Dockerfile",
+                empty.as_str(),
+            ]
+        );
+        assert_eq!(
+            call,
+            format!(
+                "podman build --network=host --output=type=local,dest=. --build-context=input_src=/some/src --file=THIS_FILE {empty}"
+            )
+        );
+
+        let dst = "/tmp/cache".into();
+        let req = BuildRequest { out_dir: None, export: Some(dst), ..req };
+        let err = green.with_podman_args(&mut Command::new("podman"), &req).unwrap_err();
+        assert_eq!(err.to_string(), "Runner podman cannot export its cache to /tmp/cache");
+    }
+
+    #[test]
+    fn podman_tags() {
+        let stdout = br#"[
+  {"Id":"0a1b","Names":["my.org/team/cache:rust-base","my.org/team/cache:latest"],"Digest":"sha256:0a1b"},
+  {"Id":"2c3d","Names":["my.org/team/cache:cratesio-rand-0.8.5","my.org/team/cache-other:rust-base"]},
+  {"Id":"4e5f"}
+]"#;
+        assert_eq!(
+            tags_of("my.org/team/cache", stdout).unwrap(),
+            ["cratesio-rand-0.8.5", "latest", "rust-base"]
+        );
+        assert!(tags_of("my.org/team/cache", b"[]").unwrap().is_empty());
+        assert!(tags_of("my.org/team/cache", b"garbage").is_err());
+    }
+}
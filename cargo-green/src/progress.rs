@@ -0,0 +1,569 @@
+//! A live display of what each crate's build is up to, drawn above cargo's own output.
+//!
+//! The display owns the top rows of the terminal: a scroll region keeps cargo's lines
+//! scrolling beneath it, so it gets redrawn in place without touching them.
+//!
+//! Wrapper processes append one JSON [`Event`] per line to a file that the `cargo green`
+//! process tails, while it forwards cargo's STDERR below the display.
+
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, IsTerminal, Read, Write},
+    process::{ExitStatus, Stdio},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow, bail};
+use camino::Utf8Path;
+use indexmap::IndexMap;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    select,
+    time::interval,
+};
+
+use crate::{
+    PKG,
+    dirs::tmp,
+    lockfile::{count_packages, find_lockfile},
+};
+
+macro_rules! ENV_PROGRESS {
+    () => {
+        "CARGOGREEN_PROGRESS"
+    };
+}
+
+/// Internal env used to tell wrappers where to report their progress
+const ENV_PROGRESS_PATH: &str = "CARGOGREEN_PROGRESS_PATH_";
+
+/// How many crates get a line of their own
+const ROWS: usize = 10;
+
+const TICK: Duration = Duration::from_millis(100);
+
+/// What a crate's build is up to, in about the order it happens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Phase {
+    /// Wrapper started, assembling its Containerfile
+    Queued,
+    /// Runner is fetching images or crates
+    Pulling,
+    /// Runner is building in BuildKit (or Buildah)
+    Building,
+    /// Runner is exporting cache
+    Exporting,
+    /// Result is being extracted into cargo's target directory
+    Untarring,
+    /// Result was found among previous results
+    Reused,
+    Done,
+    Failed,
+}
+
+impl Phase {
+    const ALL: [Self; 8] = [
+        Self::Queued,
+        Self::Pulling,
+        Self::Building,
+        Self::Exporting,
+        Self::Untarring,
+        Self::Reused,
+        Self::Done,
+        Self::Failed,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Pulling => "pulling",
+            Self::Building => "building",
+            Self::Exporting => "exporting",
+            Self::Untarring => "untarring",
+            Self::Reused => "reused",
+            Self::Done => "done",
+            Self::Failed => "failed",
+        }
+    }
+
+    fn is_over(&self) -> bool {
+        matches!(self, Self::Reused | Self::Done | Self::Failed)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct Event {
+    /// Tells apart builds of a same crate
+    id: String,
+    /// E.g. `serde 1.0.219`
+    name: String,
+    phase: Phase,
+}
+
+static CRATE: OnceLock<(String, String)> = OnceLock::new();
+
+/// Names the crate this wrapper process reports about
+pub(crate) fn setup(id: &str, name: &str) {
+    let _ = CRATE.set((id.to_owned(), name.to_owned()));
+    report(Phase::Queued);
+}
+
+/// Tells the `cargo green` process what this wrapper is up to, if it is listening.
+pub(crate) fn report(phase: Phase) {
+    let Some((id, name)) = CRATE.get() else { return };
+    let Ok(path) = env::var(ENV_PROGRESS_PATH) else { return };
+    let event = Event { id: id.clone(), name: name.clone(), phase };
+    let Ok(mut line) = serde_json::to_string(&event) else { return };
+    line.push('\n');
+    // A single small O_APPEND write: lines from concurrent wrappers don't interleave
+    if let Err(e) = OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+    {
+        warn!("Failed reporting progress to {path}: {e}");
+    }
+}
+
+/// Reports how a wrapper ended
+pub(crate) fn done<T>(res: Result<T>) -> Result<T> {
+    report(if res.is_ok() { Phase::Done } else { Phase::Failed });
+    res
+}
+
+/// Whether this wrapper's chatter would only get in the way of the display
+#[must_use]
+pub(crate) fn is_shown() -> bool {
+    env::var_os(ENV_PROGRESS_PATH).is_some()
+}
+
+/// Guesses what a build is up to from a line of its runner's (plain) progress output
+#[must_use]
+pub(crate) fn phase_of(line: &str) -> Option<Phase> {
+    // e.g. `#21 exporting cache to registry` or `#21 preparing build cache for export`
+    if line.contains("exporting cache") || line.contains("preparing build cache for export") {
+        return Some(Phase::Exporting);
+    }
+    // e.g. `#5 resolve docker.io/library/rust:1-slim@sha256:..`, `#5 extracting sha256:..`
+    // or podman's `Copying blob sha256:..`
+    if line.contains(" resolve ")
+        || line.contains(" extracting sha256:")
+        || line.contains("https://static.crates.io/")
+        || line.starts_with("Copying blob ")
+    {
+        return Some(Phase::Pulling);
+    }
+    // e.g. `#9 [dep-n-serde-1.0.219-0123456789abcdef 2/3] RUN ..` or podman's `STEP 3/5: RUN ..`
+    if line.contains("] RUN ") || (line.starts_with("STEP ") && line.contains(": RUN ")) {
+        return Some(Phase::Building);
+    }
+    None
+}
+
+fn enabled() -> Result<bool> {
+    let var = ENV_PROGRESS!();
+    match env::var(var).as_deref() {
+        Err(_) | Ok("never") => Ok(false),
+        Ok("auto") => Ok(io::stderr().is_terminal() && env::var_os("CI").is_none()),
+        Ok(val) => bail!("${var} must be either \"auto\" or \"never\", got {val:?}"),
+    }
+}
+
+/// Runs cargo, maybe showing progress above its output
+pub(crate) async fn status(cmd: &mut Command) -> Result<ExitStatus> {
+    if !enabled()? {
+        return cmd.status().await.map_err(Into::into);
+    }
+
+    let path = tmp().join(format!("{PKG}-progress-{}.jsonl", uuid::Uuid::new_v4()));
+    fs::write(&path, "").map_err(|e| anyhow!("Failed creating {path}: {e}"))?;
+    info!("showing progress from {path}");
+
+    let total = match find_lockfile().await {
+        Ok(lockfile) => count_packages(&lockfile).ok(),
+        Err(_) => None,
+    };
+
+    cmd.env(ENV_PROGRESS_PATH, &path);
+    // Cargo's own progress bar would fight with ours, and a pipe would make it drop colors
+    cmd.env("CARGO_TERM_PROGRESS_WHEN", "never");
+    if env::var_os("CARGO_TERM_COLOR").is_none() {
+        cmd.env("CARGO_TERM_COLOR", "always");
+    }
+    cmd.stderr(Stdio::piped());
+
+    let res = show(cmd, &path, total).await;
+    let _ = fs::remove_file(&path);
+    res
+}
+
+async fn show(cmd: &mut Command, path: &Utf8Path, total: Option<usize>) -> Result<ExitStatus> {
+    let mut child = cmd.spawn().map_err(|e| anyhow!("Failed to spawn cargo: {e}"))?;
+    let stderr = child.stderr.take().expect("piped");
+    // Split on bytes: build scripts may print anything, and cargo must never block on a full pipe
+    let mut lines = BufReader::new(stderr).split(b'\n');
+
+    let mut events = Tail::new(path)?;
+    let mut board = Board::new(total, Instant::now());
+    let mut screen = Screen::new();
+    let mut ticks = interval(TICK);
+
+    loop {
+        select! {
+            line = lines.next_segment() => match line {
+                Ok(Some(line)) => {
+                    let line = line.strip_suffix(b"\r").unwrap_or(&line);
+                    eprintln!("{}", String::from_utf8_lossy(line));
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Failed reading cargo's STDERR: {e}");
+                    break;
+                }
+            },
+            _ = ticks.tick() => {
+                let now = Instant::now();
+                for event in events.read()? {
+                    board.apply(event, now);
+                }
+                screen.draw(&board);
+            }
+        }
+    }
+    // Closes our end of the pipe, should cargo still be writing to it
+    drop(lines);
+    let status = child.wait().await?;
+
+    for event in events.read()? {
+        board.apply(event, Instant::now());
+    }
+    drop(screen);
+    if let Some(summary) = board.summary(Instant::now()) {
+        eprintln!("{summary}");
+    }
+    Ok(status)
+}
+
+/// The terminal, its top rows reserved for the display
+struct Screen {
+    /// Terminal's width and height
+    size: (u16, u16),
+    /// How many rows the display gets
+    reserved: usize,
+}
+
+impl Screen {
+    /// Pushes what the terminal shows into its scrollback, then reserves the top rows
+    fn new() -> Self {
+        let size = termimad::terminal_size();
+        eprint!("{}", "\n".repeat(usize::from(size.1).saturating_sub(1)));
+        let mut screen = Self { size, reserved: 0 };
+        screen.reserve();
+        eprint!("\x1b[{};1H", screen.reserved + 1);
+        screen
+    }
+
+    /// Has cargo's lines scroll below the reserved rows only
+    fn reserve(&mut self) {
+        let (_, height) = self.size;
+        self.reserved = (ROWS + 2).min(usize::from(height) / 2).max(1);
+        // Setting the region moves the cursor: put it back
+        eprint!("\x1b7\x1b[{};{height}r\x1b8", self.reserved + 1);
+    }
+
+    /// Redraws the reserved rows, leaving the cursor where cargo's next line goes
+    fn draw(&mut self, board: &Board) {
+        let size = termimad::terminal_size();
+        if size != self.size {
+            self.size = size;
+            self.reserve();
+        }
+        let rows = fit(board.render(Instant::now(), size.0.into()), self.reserved);
+        self.paint(&rows);
+    }
+
+    fn paint(&self, rows: &[String]) {
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\x1b7");
+        for i in 0..self.reserved {
+            let row = rows.get(i).map(String::as_str).unwrap_or_default();
+            let _ = write!(stderr, "\x1b[{};1H\x1b[2K{row}", i + 1);
+        }
+        let _ = write!(stderr, "\x1b8");
+        let _ = stderr.flush();
+    }
+}
+
+impl Drop for Screen {
+    /// Blanks the display and gives the whole terminal back
+    fn drop(&mut self) {
+        self.paint(&[]);
+        eprint!("\x1b7\x1b[r\x1b8");
+    }
+}
+
+/// Keeps the first rows that fit, and always the last one: the counters
+fn fit(mut rows: Vec<String>, height: usize) -> Vec<String> {
+    if rows.len() > height {
+        let counters = rows.pop().expect("PROOF: more rows than height");
+        rows.truncate(height.saturating_sub(1));
+        rows.push(counters);
+    }
+    rows
+}
+
+/// Reads the events appended to a file since the last read
+struct Tail {
+    file: fs::File,
+    partial: String,
+}
+
+impl Tail {
+    fn new(path: &Utf8Path) -> Result<Self> {
+        let file = fs::File::open(path).map_err(|e| anyhow!("Failed opening (RO) {path}: {e}"))?;
+        Ok(Self { file, partial: String::new() })
+    }
+
+    fn read(&mut self) -> Result<Vec<Event>> {
+        let mut buf = String::new();
+        self.file.read_to_string(&mut buf)?;
+        self.partial.push_str(&buf);
+
+        let Some(end) = self.partial.rfind('\n') else { return Ok(vec![]) };
+        let complete: String = self.partial.drain(..=end).collect();
+        Ok(complete.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+}
+
+struct Crate {
+    name: String,
+    phase: Phase,
+    /// When the current phase started
+    since: Instant,
+}
+
+/// The state of all the crates seen so far
+struct Board {
+    /// Packages in `Cargo.lock`, if any: roughly how many crates to expect
+    total: Option<usize>,
+    start: Instant,
+    crates: IndexMap<String, Crate>,
+}
+
+impl Board {
+    fn new(total: Option<usize>, start: Instant) -> Self {
+        Self { total, start, crates: IndexMap::new() }
+    }
+
+    fn apply(&mut self, Event { id, name, phase }: Event, now: Instant) {
+        let krate = self.crates.entry(id).or_insert(Crate { name, phase, since: now });
+        if krate.phase == phase || (krate.phase == Phase::Reused && phase == Phase::Done) {
+            return;
+        }
+        krate.phase = phase;
+        krate.since = now;
+    }
+
+    fn count(&self, phase: Phase) -> usize {
+        self.crates.values().filter(|krate| krate.phase == phase).count()
+    }
+
+    fn finished(&self) -> usize {
+        self.crates.values().filter(|krate| krate.phase.is_over()).count()
+    }
+
+    /// Extrapolates from the pace of finished crates
+    fn eta(&self, now: Instant) -> Option<Duration> {
+        let total = self.total?.max(self.crates.len());
+        let finished = self.finished();
+        if finished == 0 {
+            return None;
+        }
+        let pace = now.duration_since(self.start).as_secs_f64() / finished as f64;
+        Some(Duration::from_secs_f64(pace * total.saturating_sub(finished) as f64))
+    }
+
+    /// One row per ongoing crate (longest-running first), then aggregate counters
+    fn render(&self, now: Instant, width: usize) -> Vec<String> {
+        let mut ongoing: Vec<_> =
+            self.crates.values().filter(|krate| !krate.phase.is_over()).collect();
+        ongoing.sort_by_key(|krate| krate.since);
+
+        let mut rows: Vec<_> = ongoing
+            .iter()
+            .take(ROWS)
+            .map(|Crate { name, phase, since }| {
+                let secs = human(now.duration_since(*since));
+                format!("{:>9} {name} {secs}", phase.as_str())
+            })
+            .collect();
+        if ongoing.len() > ROWS {
+            rows.push(format!("{:>9} ...and {} more", "", ongoing.len() - ROWS));
+        }
+
+        let total = self.total.map(|total| format!("/~{}", total.max(self.crates.len())));
+        let mut counters = format!("[{}{}]", self.finished(), total.unwrap_or_default());
+        for phase in Phase::ALL {
+            let count = self.count(phase);
+            if count != 0 {
+                counters.push_str(&format!(" {count} {}", phase.as_str()));
+            }
+        }
+        if let Some(eta) = self.eta(now) {
+            counters.push_str(&format!(" ETA {}", human(eta)));
+        }
+        rows.push(counters);
+
+        rows.into_iter().map(|row| row.chars().take(width.max(1) - 1).collect()).collect()
+    }
+
+    fn summary(&self, now: Instant) -> Option<String> {
+        if self.crates.is_empty() {
+            return None;
+        }
+        let elapsed = human(now.duration_since(self.start));
+        let (reused, failed) = (self.count(Phase::Reused), self.count(Phase::Failed));
+        let n = self.crates.len();
+        Some(format!("{PKG}: {n} crates in {elapsed} ({reused} reused, {failed} failed)"))
+    }
+}
+
+/// E.g. `1h02m`, `3m07s` or `12s`
+fn human(d: Duration) -> String {
+    let secs = d.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s:02}s"),
+        (h, m, _) => format!("{h}h{m:02}m"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Forwards non-UTF-8 output, then the rest of it, until cargo exits
+    #[tokio::test]
+    async fn forwards_any_bytes() {
+        let path = tmp().join(format!("{PKG}-progress-{}.jsonl", uuid::Uuid::new_v4()));
+        fs::write(&path, "").unwrap();
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(r"printf 'bad \377 byte\n' >&2; head -c 200000 /dev/zero | tr '\0' x >&2; exit 3")
+            .stderr(Stdio::piped());
+        let status = tokio::time::timeout(Duration::from_secs(10), show(&mut cmd, &path, None))
+            .await
+            .expect("no hang")
+            .unwrap();
+        assert_eq!(status.code(), Some(3));
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn event(id: &str, phase: Phase) -> Event {
+        Event { id: id.to_owned(), name: format!("{id} 1.0.0"), phase }
+    }
+
+    #[test]
+    fn board() {
+        let start = Instant::now();
+        let secs = |n| start + Duration::from_secs(n);
+        let mut board = Board::new(Some(4), start);
+        assert_eq!(board.render(start, 80), ["[0/~4]"]);
+
+        board.apply(event("a", Phase::Queued), secs(0));
+        board.apply(event("b", Phase::Queued), secs(1));
+        board.apply(event("a", Phase::Building), secs(2));
+        board.apply(event("b", Phase::Pulling), secs(3));
+        board.apply(event("c", Phase::Reused), secs(4));
+        board.apply(event("c", Phase::Done), secs(4));
+        assert_eq!(
+            board.render(secs(10), 80),
+            [
+                " building a 1.0.0 8s",
+                "  pulling b 1.0.0 7s",
+                "[1/~4] 1 pulling 1 building 1 reused ETA 30s",
+            ]
+        );
+        assert_eq!(board.render(secs(10), 12), [" building a", "  pulling b", "[1/~4] 1 pu"]);
+
+        board.apply(event("a", Phase::Done), secs(20));
+        board.apply(event("b", Phase::Failed), secs(20));
+        assert_eq!(board.render(secs(20), 80), ["[3/~4] 1 reused 1 done 1 failed ETA 6s"]);
+        assert_eq!(
+            board.summary(secs(200)).unwrap(),
+            format!("{PKG}: 3 crates in 3m20s (1 reused, 1 failed)")
+        );
+    }
+
+    #[test]
+    fn many_crates() {
+        let start = Instant::now();
+        let mut board = Board::new(None, start);
+        for i in 0..(ROWS + 3) {
+            board.apply(event(&format!("k{i:02}"), Phase::Building), start);
+        }
+        let rows = board.render(start, 80);
+        assert_eq!(rows.len(), ROWS + 2);
+        assert_eq!(rows[ROWS], "          ...and 3 more");
+        assert_eq!(rows[ROWS + 1], "[0] 13 building");
+    }
+
+    #[test]
+    fn fits_rows() {
+        let rows: Vec<_> = ["a", "b", "c", "[0] 3 building"].map(ToOwned::to_owned).into();
+        assert_eq!(fit(rows.clone(), 12), rows);
+        assert_eq!(fit(rows.clone(), 2), ["a", "[0] 3 building"]);
+        assert_eq!(fit(rows, 1), ["[0] 3 building"]);
+    }
+
+    #[test]
+    fn tails_events() {
+        let path = tmp().join(format!("{PKG}-progress-{}.jsonl", uuid::Uuid::new_v4()));
+        fs::write(&path, "").unwrap();
+        let mut tail = Tail::new(&path).unwrap();
+        assert_eq!(tail.read().unwrap(), []);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"id\":\"a\",\"name\":\"a 1.0.0\",\"phase\":\"queued\"}}\n{{\"id\":")
+            .unwrap();
+        assert_eq!(tail.read().unwrap(), [event("a", Phase::Queued)]);
+        writeln!(file, "\"a\",\"name\":\"a 1.0.0\",\"phase\":\"exporting\"}}").unwrap();
+        assert_eq!(tail.read().unwrap(), [event("a", Phase::Exporting)]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn phases_of_lines() {
+        for (line, phase) in [
+            ("#5 resolve docker.io/docker/dockerfile:1@sha256:4c68 done", Some(Phase::Pulling)),
+            ("#6 extracting sha256:0a1b 0.3s done", Some(Phase::Pulling)),
+            ("#7 https://static.crates.io/crates/rand/rand-0.8.5.crate", Some(Phase::Pulling)),
+            ("Copying blob sha256:0a1b", Some(Phase::Pulling)),
+            (
+                "#9 [dep-n-rand-0.8.5-0123456789abcdef 2/3] RUN --mount=from=..",
+                Some(Phase::Building),
+            ),
+            ("STEP 3/5: RUN --mount=from=..", Some(Phase::Building)),
+            ("#21 exporting cache to registry", Some(Phase::Exporting)),
+            ("#21 preparing build cache for export 0.2s done", Some(Phase::Exporting)),
+            ("#12 exporting to client tarball", None),
+            ("#9 CACHED", None),
+        ] {
+            assert_eq!(phase_of(line), phase, "{line}");
+        }
+    }
+
+    #[test]
+    fn humans() {
+        assert_eq!(human(Duration::from_millis(12_300)), "12s");
+        assert_eq!(human(Duration::from_secs(187)), "3m07s");
+        assert_eq!(human(Duration::from_secs(3720)), "1h02m");
+    }
+}
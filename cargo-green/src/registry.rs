@@ -0,0 +1,511 @@
+use std::{collections::HashMap, error::Error as StdError, fs, process::Stdio, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+use camino::{Utf8Path, Utf8PathBuf};
+use log::{info, warn};
+use reqwest::{
+    Certificate, Client as ReqwestClient, RequestBuilder, Response, StatusCode, Url,
+    header::{ACCEPT, AUTHORIZATION, WWW_AUTHENTICATE},
+};
+use serde::Deserialize;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{buildkitd::Registry, image_uri::ImageUri, retrier::Retrier};
+
+/// Media types of the manifests (or indices) a tag may point to
+const MANIFESTS: &str = "application/vnd.oci.image.index.v1+json, application/vnd.docker.distribution.manifest.list.v2+json, application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+
+const DIGEST_HEADER: &str = "Docker-Content-Digest";
+
+/// Where Docker Hub images are actually served from
+const DOCKER_HUB: &str = "registry-1.docker.io";
+
+/// How Docker Hub credentials are keyed in `config.json`
+const DOCKER_HUB_AUTH: &str = "https://index.docker.io/v1/";
+
+/// Asks `img`'s registry for the digest its tag currently points to.
+///
+/// Implements the [OCI distribution](https://github.com/opencontainers/distribution-spec/blob/main/spec.md)
+/// token flow, using credentials from `$DOCKER_CONFIG/config.json`
+/// and honoring the registry's `registries` settings.
+pub(crate) async fn fetch_manifest_digest(
+    img: &ImageUri,
+    settings: Option<&Registry>,
+) -> Result<String> {
+    fetch_manifest_digest_with(img, settings, docker_config_dir().as_deref()).await
+}
+
+async fn fetch_manifest_digest_with(
+    img: &ImageUri,
+    settings: Option<&Registry>,
+    config: Option<&Utf8Path>,
+) -> Result<String> {
+    let settings = settings.cloned().unwrap_or_default();
+    let Endpoint { base, repository, tag, auth_key } = Endpoint::of(img, settings.http)?;
+    let url = format!("{base}/v2/{repository}/manifests/{tag}");
+
+    let client = client(&settings)?;
+    let head = || client.head(&url).header(ACCEPT, MANIFESTS);
+
+    info!("HEADing {url}");
+    eprintln!("HEADing {url}");
+
+    let mut res = send(head()).await?;
+    if res.status() == StatusCode::UNAUTHORIZED {
+        let creds = match config {
+            Some(config) => credentials(config, &auth_key).await?,
+            None => None,
+        };
+
+        let challenge = res.headers().get(WWW_AUTHENTICATE).and_then(|v| v.to_str().ok());
+        res = match challenge.and_then(Challenge::parse) {
+            Some(Challenge::Basic) => {
+                let Some(creds) = creds else { bail!("{url} requires credentials") };
+                send(creds.apply(head())).await?
+            }
+            Some(Challenge::Bearer { realm, params }) => {
+                let token = token(&client, &realm, &params, creds.as_ref()).await?;
+                send(head().bearer_auth(token)).await?
+            }
+            None => bail!("Unexpected authentication challenge from {url}: {challenge:?}"),
+        };
+    }
+
+    match res.status() {
+        StatusCode::NOT_FOUND => {
+            // NOTE: library images can take a few days to appear, after a Rust release.
+            bail!("Tag {tag:?} not found in {repository}")
+        }
+        status if !status.is_success() => bail!("Unexpected {status} from {url}"),
+        _ => {}
+    }
+
+    let Some(digest) = res.headers().get(DIGEST_HEADER) else {
+        bail!("Missing {DIGEST_HEADER} header in response from {url}")
+    };
+    let digest = digest.to_str().map_err(|e| anyhow!("Bad {DIGEST_HEADER} header: {e}"))?;
+    if !digest.starts_with("sha256:") || digest.len() != "sha256:".len() + 64 {
+        bail!("Unexpected digest from {url}: {digest:?}")
+    }
+    Ok(digest.to_owned())
+}
+
+/// An HTTP client trusting the registry's CAs, or any certificate when `insecure`
+fn client(settings: &Registry) -> Result<ReqwestClient> {
+    let mut client = ReqwestClient::builder()
+        .connect_timeout(Duration::from_secs(4))
+        .danger_accept_invalid_certs(settings.insecure);
+    for ca in &settings.ca {
+        let pem = fs::read(ca).map_err(|e| anyhow!("Failed reading CA {ca}: {e}"))?;
+        let cert = Certificate::from_pem(&pem).map_err(|e| anyhow!("Bad CA {ca}: {e}"))?;
+        client = client.add_root_certificate(cert);
+    }
+    client.build().map_err(|e| anyhow!("HTTP client's config/TLS failed: {e}"))
+}
+
+/// Sends a request, retrying on connection errors
+async fn send(req: RequestBuilder) -> Result<Response> {
+    let mut retrier = Retrier::with_max_attempts(5);
+    loop {
+        let attempt = req.try_clone().expect("PROOF: requests here have no streaming body");
+        match attempt.send().await {
+            Ok(res) => return Ok(res),
+            Err(e) if retrier.continues() => retrier.backoff("connection", e.into()).await,
+            Err(e) => {
+                // e.source(): try to be a bit more helpful than just "error sending request for url"
+                let e = anyhow!("{e} ({:?})", e.source());
+                bail!("Failed to reach registry (retried {} times): {e}", retrier.max())
+            }
+        }
+    }
+}
+
+/// Exchanges (maybe anonymous) credentials for a bearer token
+async fn token(
+    client: &ReqwestClient,
+    realm: &str,
+    params: &[(String, String)],
+    creds: Option<&Credentials>,
+) -> Result<String> {
+    let mut url = Url::parse(realm).map_err(|e| anyhow!("Bad token realm {realm:?}: {e}"))?;
+    url.query_pairs_mut().extend_pairs(params);
+
+    let mut req = client.get(url.clone());
+    if let Some(creds) = creds {
+        req = creds.apply(req);
+    }
+    let res = send(req).await?;
+    if !res.status().is_success() {
+        bail!("Unexpected {} from token endpoint {url}", res.status())
+    }
+
+    #[derive(Deserialize)]
+    struct TokenResponse {
+        token: Option<String>,
+        access_token: Option<String>,
+    }
+    let txt = res.text().await.map_err(|e| anyhow!("Failed reading token from {url}: {e}"))?;
+    let TokenResponse { token, access_token } = serde_json::from_str(&txt)
+        .map_err(|e| anyhow!("Failed to decode token response from {url}: {e}"))?;
+    token.or(access_token).ok_or_else(|| anyhow!("No token in response from {url}"))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Endpoint {
+    /// E.g. `https://ghcr.io`
+    base: String,
+    /// E.g. `library/rust`
+    repository: String,
+    tag: String,
+    /// Key to look up in `config.json`
+    auth_key: String,
+}
+
+impl Endpoint {
+    fn of(img: &ImageUri, http: bool) -> Result<Self> {
+        let (path, tag) = img.path_and_tag();
+        let host = img.host();
+        let Some(repository) = path.strip_prefix(host).and_then(|p| p.strip_prefix('/')) else {
+            bail!("BUG: unhandled image path {img:?}")
+        };
+
+        let (host, repository, auth_key) = match host {
+            "docker.io" | "index.docker.io" | DOCKER_HUB => {
+                let repository = if repository.contains('/') {
+                    repository.to_owned()
+                } else {
+                    format!("library/{repository}")
+                };
+                (DOCKER_HUB, repository, DOCKER_HUB_AUTH.to_owned())
+            }
+            _ => (host, repository.to_owned(), host.to_owned()),
+        };
+
+        Ok(Self { base: api_base(host, http), repository, tag: tag.to_owned(), auth_key })
+    }
+}
+
+/// E.g. `https://ghcr.io`
+fn api_base(host: &str, http: bool) -> String {
+    // Like dockerd, talk plain HTTP to local registries
+    let local = ["localhost", "127.0.0.1", "[::1]"]
+        .iter()
+        .any(|local| host == *local || host.starts_with(&format!("{local}:")));
+    let scheme = if http || local { "http" } else { "https" };
+    format!("{scheme}://{host}")
+}
+
+/// Checks that `host` serves the registry API, honoring its `registries` settings.
+///
+/// Any answer counts, as `401 Unauthorized` is how most registries greet anonymous clients.
+pub(crate) async fn ping(host: &str, settings: Option<&Registry>) -> Result<StatusCode> {
+    let host = match host {
+        "docker.io" | "index.docker.io" => DOCKER_HUB,
+        _ => host,
+    };
+    let settings = settings.cloned().unwrap_or_default();
+    let url = format!("{}/v2/", api_base(host, settings.http));
+
+    let client = client(&settings)?;
+
+    info!("GETting {url}");
+    let res = send(client.get(&url)).await?;
+    match res.status() {
+        status if status.is_success() || status == StatusCode::UNAUTHORIZED => Ok(status),
+        status => bail!("Unexpected {status} from {url}"),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Challenge {
+    Basic,
+    Bearer { realm: String, params: Vec<(String, String)> },
+}
+
+impl Challenge {
+    /// Parses e.g. `Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/rust:pull"`
+    fn parse(header: &str) -> Option<Self> {
+        let (scheme, rest) = header.trim().split_once(' ').unwrap_or((header.trim(), ""));
+        if scheme.eq_ignore_ascii_case("basic") {
+            return Some(Self::Basic);
+        }
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return None;
+        }
+
+        let mut realm = None;
+        let mut params = vec![];
+        let mut rest = rest.trim();
+        while let Some((key, value)) = rest.split_once('=') {
+            let key = key.trim().trim_start_matches(',').trim().to_lowercase();
+            let (value, tail) = if let Some(value) = value.strip_prefix('"') {
+                value.split_once('"')?
+            } else {
+                value.split_once(',').unwrap_or((value, ""))
+            };
+            if key == "realm" {
+                realm = Some(value.to_owned());
+            } else {
+                params.push((key, value.to_owned()));
+            }
+            rest = tail;
+        }
+        Some(Self::Bearer { realm: realm?, params })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Credentials {
+    /// base64 of `username:password`, as found in `config.json`'s `auths`
+    Encoded(String),
+    Plain {
+        username: String,
+        secret: String,
+    },
+}
+
+impl Credentials {
+    fn apply(&self, req: RequestBuilder) -> RequestBuilder {
+        match self {
+            Self::Encoded(auth) => req.header(AUTHORIZATION, format!("Basic {auth}")),
+            Self::Plain { username, secret } => req.basic_auth(username, Some(secret)),
+        }
+    }
+}
+
+/// `$DOCKER_CONFIG`, defaulting to `~/.docker`
+fn docker_config_dir() -> Option<Utf8PathBuf> {
+    if let Ok(dir) = std::env::var("DOCKER_CONFIG") {
+        return Some(dir.into());
+    }
+    let home = home::home_dir()?;
+    Utf8PathBuf::try_from(home).ok().map(|home| home.join(".docker"))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, AuthEntry>,
+    #[serde(default)]
+    cred_helpers: HashMap<String, String>,
+    creds_store: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AuthEntry {
+    auth: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+/// Looks up credentials for a registry, the way the docker CLI does:
+/// per-registry credential helper, then inline `auths`, then the default credentials store.
+async fn credentials(config: &Utf8Path, key: &str) -> Result<Option<Credentials>> {
+    let path = config.join("config.json");
+    let txt = match std::fs::read_to_string(&path) {
+        Ok(txt) => txt,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => bail!("Failed reading {path}: {e}"),
+    };
+    let config: DockerConfig =
+        serde_json::from_str(&txt).map_err(|e| anyhow!("Failed parsing {path}: {e}"))?;
+
+    if let Some(helper) = config.cred_helpers.get(key) {
+        return credential_helper(helper, key).await;
+    }
+
+    let entry = config.auths.iter().find_map(|(k, entry)| {
+        let k = k.trim_start_matches("https://").trim_start_matches("http://");
+        let k = k.trim_end_matches('/');
+        let key = key.trim_start_matches("https://").trim_end_matches('/');
+        (k == key).then_some(entry)
+    });
+    match entry {
+        Some(AuthEntry { auth: Some(auth), .. }) if !auth.is_empty() => {
+            return Ok(Some(Credentials::Encoded(auth.to_owned())));
+        }
+        Some(AuthEntry { username: Some(username), password: Some(secret), .. }) => {
+            let (username, secret) = (username.to_owned(), secret.to_owned());
+            return Ok(Some(Credentials::Plain { username, secret }));
+        }
+        _ => {}
+    }
+
+    if let Some(ref store) = config.creds_store {
+        return credential_helper(store, key).await;
+    }
+    Ok(None)
+}
+
+/// Calls `docker-credential-<helper> get`
+///
+/// <https://github.com/docker/docker-credential-helpers#development>
+async fn credential_helper(helper: &str, key: &str) -> Result<Option<Credentials>> {
+    let bin = format!("docker-credential-{helper}");
+    info!("Calling {bin} get for {key}");
+
+    let mut child = Command::new(&bin)
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow!("Failed to spawn {bin}: {e}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(key.as_bytes()).await?;
+    }
+    let output =
+        child.wait_with_output().await.map_err(|e| anyhow!("Failed running {bin}: {e}"))?;
+
+    if !output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stdout.contains("credentials not found") {
+            return Ok(None);
+        }
+        warn!("{bin} failed: {stdout} {}", String::from_utf8_lossy(&output.stderr));
+        return Ok(None);
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct HelperResponse {
+        username: String,
+        secret: String,
+    }
+    let HelperResponse { username, secret } = serde_json::from_slice(&output.stdout)
+        .map_err(|e| anyhow!("Failed to decode {bin} response: {e}"))?;
+    Ok(Some(Credentials::Plain { username, secret }))
+}
+
+#[cfg(test)]
+#[test_case::test_case("docker-image://docker.io/library/rust:1", false, "https://registry-1.docker.io", "library/rust", "1", DOCKER_HUB_AUTH; "docker hub library")]
+#[test_case::test_case("docker-image://docker.io/moby/buildkit", false, "https://registry-1.docker.io", "moby/buildkit", "latest", DOCKER_HUB_AUTH; "docker hub")]
+#[test_case::test_case("docker-image://ghcr.io/fenollp/supergreen:v1", false, "https://ghcr.io", "fenollp/supergreen", "v1", "ghcr.io"; "ghcr")]
+#[test_case::test_case("docker-image://localhost:5000/some/img:v1", false, "http://localhost:5000", "some/img", "v1", "localhost:5000"; "local")]
+#[test_case::test_case("docker-image://registry.internal:5000/some/img:v1", true, "http://registry.internal:5000", "some/img", "v1", "registry.internal:5000"; "plain http")]
+fn endpoints(img: &str, http: bool, base: &str, repository: &str, tag: &str, auth_key: &str) {
+    let img = ImageUri::try_new(img).unwrap();
+    assert_eq!(
+        Endpoint::of(&img, http).unwrap(),
+        Endpoint {
+            base: base.to_owned(),
+            repository: repository.to_owned(),
+            tag: tag.to_owned(),
+            auth_key: auth_key.to_owned()
+        }
+    );
+}
+
+#[test]
+fn challenges() {
+    assert_eq!(Challenge::parse(r#"Basic realm="Registry""#), Some(Challenge::Basic));
+    assert_eq!(
+        Challenge::parse(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/rust:pull""#
+        ),
+        Some(Challenge::Bearer {
+            realm: "https://auth.docker.io/token".to_owned(),
+            params: vec![
+                ("service".to_owned(), "registry.docker.io".to_owned()),
+                ("scope".to_owned(), "repository:library/rust:pull".to_owned()),
+            ]
+        })
+    );
+    assert_eq!(Challenge::parse(r#"Bearer service="no realm""#), None);
+    assert_eq!(Challenge::parse("Negotiate"), None);
+}
+
+#[tokio::test]
+async fn credentials_from_config() {
+    let dir = crate::dirs::tmp().join(format!("docker-config-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("config.json"),
+        r#"{"auths":{"https://index.docker.io/v1/":{"auth":"dXNlcjpwYXNz"},"ghcr.io":{},"my.org":{"username":"me","password":"pw"}}}"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        credentials(&dir, DOCKER_HUB_AUTH).await.unwrap(),
+        Some(Credentials::Encoded("dXNlcjpwYXNz".to_owned()))
+    );
+    assert_eq!(
+        credentials(&dir, "my.org").await.unwrap(),
+        Some(Credentials::Plain { username: "me".to_owned(), secret: "pw".to_owned() })
+    );
+    assert_eq!(credentials(&dir, "ghcr.io").await.unwrap(), None);
+    assert_eq!(credentials(&dir.join("nope"), "ghcr.io").await.unwrap(), None);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Runs the token flow against a tiny stand-in for a `registry:2` behind a token server
+#[tokio::test]
+async fn digest_from_local_registry() {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    const DIGEST: &str = "sha256:27086352fd5e1907ea2b934eb1023f217c5ae087992eb59fde121dce9c9ff21e";
+
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+            let request = lines.next().unwrap().unwrap();
+            let headers: Vec<_> =
+                lines.map_while(Result::ok).take_while(|l| !l.is_empty()).collect();
+            let has = |h: &str| headers.iter().any(|l| l.eq_ignore_ascii_case(h));
+
+            let response = if request.starts_with("GET /token?") {
+                assert!(request.contains("scope=repository%3Asome%2Fimg%3Apull"), "{request}");
+                if has("authorization: Basic dXNlcjpwYXNz") {
+                    "200 OK\r\nContent-Length: 17\r\n\r\n{\"token\":\"t0k3n\"}".to_owned()
+                } else {
+                    "401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_owned()
+                }
+            } else if request.starts_with("HEAD /v2/some/img/manifests/v1 ") {
+                if has("authorization: Bearer t0k3n") {
+                    format!(
+                        "200 OK\r\nDocker-Content-Digest: {DIGEST}\r\nContent-Length: 0\r\n\r\n"
+                    )
+                } else {
+                    format!(
+                        "401 Unauthorized\r\nWWW-Authenticate: Bearer realm=\"http://127.0.0.1:{port}/token\",service=\"test\",scope=\"repository:some/img:pull\"\r\nContent-Length: 0\r\n\r\n"
+                    )
+                }
+            } else {
+                "404 Not Found\r\nContent-Length: 0\r\n\r\n".to_owned()
+            };
+            write!(stream, "HTTP/1.1 {response}").unwrap();
+        }
+    });
+
+    let dir = crate::dirs::tmp().join(format!("docker-config-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("config.json"),
+        format!(r#"{{"auths":{{"127.0.0.1:{port}":{{"auth":"dXNlcjpwYXNz"}}}}}}"#),
+    )
+    .unwrap();
+
+    let img = ImageUri::try_new(format!("docker-image://127.0.0.1:{port}/some/img:v1")).unwrap();
+    assert_eq!(fetch_manifest_digest_with(&img, None, Some(&dir)).await.unwrap(), DIGEST);
+
+    let img = ImageUri::try_new(format!("docker-image://127.0.0.1:{port}/some/img:v1")).unwrap();
+    let err = fetch_manifest_digest_with(&img, None, None).await.unwrap_err().to_string();
+    assert!(err.contains("401"), "{err}");
+
+    let settings = Registry { ca: vec![dir.join("nope.pem")], ..Default::default() };
+    let err = fetch_manifest_digest_with(&img, Some(&settings), Some(&dir)).await.unwrap_err();
+    assert!(err.to_string().starts_with("Failed reading CA "), "{err}");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,250 @@
+//! Builders spanning remote machines, each reached through its own Docker context.
+//!
+//! * <https://docs.docker.com/build/ci/github-actions/configure-builder/#append-additional-nodes-to-the-builder>
+//! * <https://dustinrue.com/2021/12/using-a-remote-docker-engine-with-buildx/>
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, anyhow, bail};
+use camino::Utf8PathBuf;
+use indexmap::IndexSet;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{PKG, ext::CommandExt, green::Green};
+
+/// A builder node, on a remote Docker host
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Remote {
+    /// Names both the Docker context and the builder node
+    pub(crate) name: String,
+
+    /// `ssh://`, `tcp://` or `unix://` address of the Docker daemon
+    pub(crate) host: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ca: Option<Utf8PathBuf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) cert: Option<Utf8PathBuf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) key: Option<Utf8PathBuf>,
+
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    #[serde(rename = "skip-tls-verify")]
+    pub(crate) skip_tls_verify: bool,
+
+    /// Builds for these platforms land on this node
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) platforms: Vec<String>,
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "name={},host={}", self.name, self.host)?;
+        if let Some(ref ca) = self.ca {
+            write!(f, ",ca={ca}")?;
+        }
+        if let Some(ref cert) = self.cert {
+            write!(f, ",cert={cert}")?;
+        }
+        if let Some(ref key) = self.key {
+            write!(f, ",key={key}")?;
+        }
+        if self.skip_tls_verify {
+            write!(f, ",skip-tls-verify=true")?;
+        }
+        for platform in &self.platforms {
+            write!(f, ",platform={platform}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Remote {
+    type Err = anyhow::Error;
+
+    /// Either just a host, or comma-separated `key=value`s
+    fn from_str(s: &str) -> Result<Self> {
+        let mut remote = Self {
+            name: String::new(),
+            host: String::new(),
+            ca: None,
+            cert: None,
+            key: None,
+            skip_tls_verify: false,
+            platforms: vec![],
+        };
+
+        for (i, kv) in s.split(',').map(str::trim).enumerate() {
+            let (k, v) = match kv.split_once('=') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None if i == 0 => ("host", kv),
+                None => bail!("Expected key=value, got {kv:?}"),
+            };
+            if v.is_empty() {
+                bail!("Empty value for {k:?}")
+            }
+            match k {
+                "name" => remote.name = v.to_owned(),
+                "host" => remote.host = v.to_owned(),
+                "ca" => remote.ca = Some(v.into()),
+                "cert" => remote.cert = Some(v.into()),
+                "key" => remote.key = Some(v.into()),
+                "skip-tls-verify" => {
+                    remote.skip_tls_verify =
+                        v.parse().map_err(|_| anyhow!("skip-tls-verify must be true or false"))?;
+                }
+                "platform" => remote.platforms.push(v.to_owned()),
+                _ => bail!("Unexpected key {k:?}"),
+            }
+        }
+
+        if remote.host.is_empty() {
+            bail!("Missing host")
+        }
+        if !["ssh://", "tcp://", "unix://"].iter().any(|scheme| remote.host.starts_with(scheme)) {
+            bail!("Host must start with ssh://, tcp:// or unix:// (got {:?})", remote.host)
+        }
+        if remote.name.is_empty() {
+            remote.name = format!("supergreen-{:08x}", crc32fast::hash(remote.host.as_bytes()));
+        }
+        Ok(remote)
+    }
+}
+
+/// Reads `;`-separated remotes
+pub(crate) fn parse_remotes(s: &str) -> Result<Vec<Remote>> {
+    let remotes = s
+        .split(';')
+        .map(str::trim)
+        .filter(|remote| !remote.is_empty())
+        .map(|remote| remote.parse().map_err(|e| anyhow!("Bad remote {remote:?}: {e}")))
+        .collect::<Result<Vec<Remote>>>()?;
+    if remotes.is_empty() {
+        bail!("No remotes given")
+    }
+    if remotes.iter().map(|r| &r.name).collect::<IndexSet<_>>().len() != remotes.len() {
+        bail!("Remotes must have distinct names")
+    }
+    Ok(remotes)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContextInspect {
+    endpoints: ContextEndpoints,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContextEndpoints {
+    docker: ContextEndpoint,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContextEndpoint {
+    host: String,
+    #[serde(rename = "SkipTLSVerify", default)]
+    skip_tls_verify: bool,
+}
+
+impl Green {
+    /// Creates missing Docker contexts, or errors if existing ones point elsewhere.
+    ///
+    /// NOTE: TLS material already stored in a context is not compared.
+    pub(crate) async fn ensure_remote_contexts(&self) -> Result<()> {
+        for remote in &self.builder.remotes {
+            let Remote { name, host, .. } = remote;
+
+            let mut cmd = self.cmd()?;
+            cmd.args(["context", "inspect", name]);
+            let (succeeded, stdout, _) = cmd.exec().await?;
+            if succeeded {
+                let inspect: Vec<ContextInspect> = serde_json::from_slice(&stdout)
+                    .map_err(|e| anyhow!("Failed decoding context {name}: {e}"))?;
+                let Some(ContextInspect { endpoints }) = inspect.into_iter().next() else {
+                    bail!("BUG: no such context {name}")
+                };
+                let ContextEndpoint { host: existing, skip_tls_verify } = endpoints.docker;
+                if existing != *host || skip_tls_verify != remote.skip_tls_verify {
+                    bail!(
+                        "Existing Docker context {name:?} points to {existing:?}, not {host:?}: remove it or rename remote"
+                    )
+                }
+                info!("reusing Docker context {name} for {host}");
+                continue;
+            }
+
+            let mut endpoint = format!("host={host}");
+            for (k, v) in [("ca", &remote.ca), ("cert", &remote.cert), ("key", &remote.key)] {
+                if let Some(v) = v {
+                    endpoint.push_str(&format!(",{k}={v}"));
+                }
+            }
+            if remote.skip_tls_verify {
+                endpoint.push_str(",skip-tls-verify=true");
+            }
+
+            let mut cmd = self.cmd()?;
+            cmd.args(["context", "create", name, "--docker", &endpoint]);
+            cmd.args(["--description", &format!("Created by {PKG}")]);
+            let (succeeded, _, stderr) = cmd.exec().await?;
+            if !succeeded {
+                let stderr = String::from_utf8_lossy(&stderr);
+                bail!("Failed creating Docker context {name}: {stderr}")
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn parses_remotes() {
+    let remotes = parse_remotes(
+        "ssh://me@amd64-box ; name=arm,host=tcp://arm64-box:2376,ca=/c/ca.pem,cert=/c/cert.pem,key=/c/key.pem,platform=linux/arm64,platform=linux/arm/v7;",
+    )
+    .unwrap();
+    assert_eq!(
+        remotes,
+        [
+            Remote {
+                name: format!("supergreen-{:08x}", crc32fast::hash(b"ssh://me@amd64-box")),
+                host: "ssh://me@amd64-box".to_owned(),
+                ca: None,
+                cert: None,
+                key: None,
+                skip_tls_verify: false,
+                platforms: vec![],
+            },
+            Remote {
+                name: "arm".to_owned(),
+                host: "tcp://arm64-box:2376".to_owned(),
+                ca: Some("/c/ca.pem".into()),
+                cert: Some("/c/cert.pem".into()),
+                key: Some("/c/key.pem".into()),
+                skip_tls_verify: false,
+                platforms: vec!["linux/arm64".to_owned(), "linux/arm/v7".to_owned()],
+            },
+        ]
+    );
+    assert_eq!(
+        remotes[1].to_string(),
+        "name=arm,host=tcp://arm64-box:2376,ca=/c/ca.pem,cert=/c/cert.pem,key=/c/key.pem,platform=linux/arm64,platform=linux/arm/v7"
+    );
+    assert_eq!(parse_remotes(&remotes[1].to_string()).unwrap(), [remotes[1].clone()]);
+}
+
+#[cfg(test)]
+#[test_case::test_case("", "No remotes given"; "empty")]
+#[test_case::test_case("me@box", r#"Bad remote "me@box": Host must start with ssh://, tcp:// or unix:// (got "me@box")"#; "no scheme")]
+#[test_case::test_case("name=a", r#"Bad remote "name=a": Missing host"#; "no host")]
+#[test_case::test_case("ssh://a,b", r#"Bad remote "ssh://a,b": Expected key=value, got "b""#; "bare value")]
+#[test_case::test_case("ssh://a,port=22", r#"Bad remote "ssh://a,port=22": Unexpected key "port""#; "unknown key")]
+#[test_case::test_case("ssh://a,skip-tls-verify=yes", r#"Bad remote "ssh://a,skip-tls-verify=yes": skip-tls-verify must be true or false"#; "bad bool")]
+#[test_case::test_case("name=a,host=ssh://a;name=a,host=ssh://b", "Remotes must have distinct names"; "same names")]
+fn bad_remotes(remotes: &str, err: &str) {
+    assert_eq!(parse_remotes(remotes).unwrap_err().to_string(), err);
+}
@@ -17,18 +17,25 @@ macro_rules! ENV_RUNNER {
     };
 }
 
-// Envs from BuildKit/Buildx/Docker/Podman that we read
+// Envs from BuildKit/Buildx/Docker/Podman/nerdctl that we read
 const BUILDKIT_COLORS: &str = "BUILDKIT_COLORS";
 pub(crate) const BUILDKIT_HOST: &str = "BUILDKIT_HOST";
 const BUILDKIT_PROGRESS: &str = "BUILDKIT_PROGRESS";
 const BUILDKIT_TTY_LOG_LINES: &str = "BUILDKIT_TTY_LOG_LINES";
 const BUILDX_CPU_PROFILE: &str = "BUILDX_CPU_PROFILE";
 const BUILDX_MEM_PROFILE: &str = "BUILDX_MEM_PROFILE";
+const CONTAINER_CONNECTION: &str = "CONTAINER_CONNECTION";
+const CONTAINER_HOST: &str = "CONTAINER_HOST";
+const CONTAINERD_ADDRESS: &str = "CONTAINERD_ADDRESS";
+const CONTAINERD_NAMESPACE: &str = "CONTAINERD_NAMESPACE";
+const CONTAINERD_SNAPSHOTTER: &str = "CONTAINERD_SNAPSHOTTER";
 pub(crate) const DOCKER_BUILDKIT: &str = "DOCKER_BUILDKIT";
+pub(crate) const DOCKER_CERT_PATH: &str = "DOCKER_CERT_PATH";
 pub(crate) const DOCKER_CONTEXT: &str = "DOCKER_CONTEXT";
 const DOCKER_DEFAULT_PLATFORM: &str = "DOCKER_DEFAULT_PLATFORM";
 const DOCKER_HIDE_LEGACY_COMMANDS: &str = "DOCKER_HIDE_LEGACY_COMMANDS";
 pub(crate) const DOCKER_HOST: &str = "DOCKER_HOST";
+const NERDCTL_TOML: &str = "NERDCTL_TOML";
 
 #[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "kebab-case")]
@@ -36,6 +43,8 @@ pub(crate) enum Runner {
     #[default]
     Docker,
     Podman,
+    Buildkitd,
+    Nerdctl,
     None,
 }
 
@@ -44,6 +53,8 @@ impl fmt::Display for Runner {
         match self {
             Self::Docker => write!(f, "docker"),
             Self::Podman => write!(f, "podman"),
+            Self::Buildkitd => write!(f, "buildkitd"),
+            Self::Nerdctl => write!(f, "nerdctl"),
             Self::None => write!(f, "none"),
         }
     }
@@ -56,12 +67,15 @@ impl FromStr for Runner {
         match s {
             "docker" => Ok(Self::Docker),
             "podman" => Ok(Self::Podman),
+            "buildkitd" => Ok(Self::Buildkitd),
+            "nerdctl" => Ok(Self::Nerdctl),
             "none" => Ok(Self::None),
             _ => {
-                let all: Vec<_> = [Self::Docker, Self::Podman, Self::None]
-                    .iter()
-                    .map(ToString::to_string)
-                    .collect();
+                let all: Vec<_> =
+                    [Self::Docker, Self::Podman, Self::Buildkitd, Self::Nerdctl, Self::None]
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect();
                 bail!("Runner must be one of {all:?}")
             }
         }
@@ -76,7 +90,13 @@ impl Runner {
 
     #[must_use]
     pub(crate) fn is_buildkit(&self) -> bool {
-        matches!(self, Self::Docker | Self::Podman)
+        matches!(self, Self::Docker | Self::Buildkitd | Self::Nerdctl)
+    }
+
+    /// True for runners driving builds through `buildx`
+    #[must_use]
+    pub(crate) fn is_buildx(&self) -> bool {
+        matches!(self, Self::Docker)
     }
 
     /// Resolve to an executable binary.
@@ -86,7 +106,9 @@ impl Runner {
             return Ok(exe);
         }
 
-        let runner = self.to_string();
+        // Talking to buildkitd goes through its client
+        let runner =
+            if *self == Self::Buildkitd { "buildctl".to_owned() } else { self.to_string() };
         let exe = which::which(&runner).map_err(|e| anyhow!("No such {self} runner: {e}"))?;
         let exe = exe.try_into().map_err(|e| anyhow!("Path to {runner} is not utf-8: {e}"))?;
 
@@ -98,6 +120,8 @@ impl Runner {
     ///
     /// * <https://docs.docker.com/engine/reference/commandline/cli/#environment-variables>
     /// * <https://docs.docker.com/build/building/variables/#build-tool-configuration-variables>
+    /// * <https://github.com/containerd/nerdctl/blob/main/docs/config.md>
+    /// * <https://docs.podman.io/en/latest/markdown/podman.1.html#environment-variables>
     pub(crate) fn envs(&self) -> HashMap<String, String> {
         [
             BUILDKIT_COLORS,
@@ -120,8 +144,14 @@ impl Runner {
             "BUILDX_METADATA_WARNINGS",
             "BUILDX_NO_DEFAULT_ATTESTATIONS",
             "BUILDX_NO_DEFAULT_LOAD",
+            CONTAINER_CONNECTION,
+            CONTAINER_HOST,
+            "CONTAINERS_CONF",
+            CONTAINERD_ADDRESS,
+            CONTAINERD_NAMESPACE,
+            CONTAINERD_SNAPSHOTTER,
             "DOCKER_API_VERSION",
-            "DOCKER_CERT_PATH",
+            DOCKER_CERT_PATH,
             "DOCKER_CONFIG",
             "DOCKER_CONTENT_TRUST",
             "DOCKER_CONTENT_TRUST_SERVER",
@@ -134,8 +164,10 @@ impl Runner {
             "EXPERIMENTAL_BUILDKIT_SOURCE_POLICY",
             "HTTP_PROXY",  //TODO: hinders reproducibility
             "HTTPS_PROXY", //TODO: hinders reproducibility
-            "NO_PROXY",    //TODO: hinders reproducibility
-            "PATH",        // Required at least on macOS
+            NERDCTL_TOML,
+            "NO_PROXY", //TODO: hinders reproducibility
+            "PATH",     // Required at least on macOS
+            "REGISTRY_AUTH_FILE",
         ]
         .into_iter()
         .filter_map(|k| env::var(k).ok().map(|v| (k.to_owned(), v)))
@@ -144,6 +176,32 @@ impl Runner {
 
     /// Strip out envs that don't affect a build's outputs:
     pub(crate) fn buildnoop_envs(&self) -> Vec<&OsStr> {
+        if *self == Self::Buildkitd {
+            return [BUILDKIT_COLORS, BUILDKIT_HOST, DOCKER_CERT_PATH, "PATH"]
+                .into_iter()
+                .map(OsStr::new)
+                .collect();
+        }
+        if *self == Self::Nerdctl {
+            return [
+                BUILDKIT_COLORS,
+                BUILDKIT_HOST,
+                CONTAINERD_ADDRESS,
+                CONTAINERD_NAMESPACE,
+                CONTAINERD_SNAPSHOTTER,
+                NERDCTL_TOML,
+                "PATH",
+            ]
+            .into_iter()
+            .map(OsStr::new)
+            .collect();
+        }
+        if *self == Self::Podman {
+            return [CONTAINER_CONNECTION, CONTAINER_HOST, "PATH"]
+                .into_iter()
+                .map(OsStr::new)
+                .collect();
+        }
         if *self == Self::Docker {
             [
                 BUILDKIT_COLORS,
@@ -178,6 +236,9 @@ impl Green {
         let mut cmd = Command::new(self.runner.executable()?);
         cmd.kill_on_drop(true); // Underlying OS process dies with us
         cmd.stdin(Stdio::null());
+        if self.runner == Runner::Buildkitd {
+            self.buildctl_global_args(&mut cmd);
+        }
         if false {
             cmd.arg("--debug");
         }
@@ -206,7 +267,18 @@ fn runner_is_what() {
     assert!(!Runner::Podman.is_none());
     assert!(Runner::None.is_none());
 
+    assert!(!Runner::Buildkitd.is_none());
+    assert!(!Runner::Nerdctl.is_none());
+
     assert!(Runner::Docker.is_buildkit());
-    assert!(Runner::Podman.is_buildkit());
+    assert!(!Runner::Podman.is_buildkit());
+    assert!(Runner::Buildkitd.is_buildkit());
+    assert!(Runner::Nerdctl.is_buildkit());
     assert!(!Runner::None.is_buildkit());
+
+    assert!(Runner::Docker.is_buildx());
+    assert!(!Runner::Podman.is_buildx());
+    assert!(!Runner::Buildkitd.is_buildx());
+    assert!(!Runner::Nerdctl.is_buildx());
+    assert!(!Runner::None.is_buildx());
 }
@@ -26,6 +26,9 @@ pub(crate) struct RustcArgs {
 
     /// Target path:
     pub(crate) target_path: Utf8PathBuf,
+
+    /// 0|1: --target TARGET (when cross-compiling)
+    pub(crate) target: Option<String>,
 }
 
 pub(crate) fn as_rustc(
@@ -42,6 +45,7 @@ pub(crate) fn as_rustc(
         input: "".into(),
         out_dir: "".into(),
         target_path: "".into(),
+        target: None,
     };
 
     let mut s_e = true;
@@ -142,6 +146,10 @@ pub(crate) fn as_rustc(
                     bail!("BUG: {xtern} has no file name")
                 }
             }
+            "--target" => {
+                assert_eq!(state.target, None);
+                state.target = Some(val.clone());
+            }
             "--out-dir" => {
                 assert_eq!(state.out_dir, "");
                 state.out_dir = val.clone().into();
@@ -285,6 +293,7 @@ mod tests {
                 input: as_argument("src/main.rs").into(),
                 out_dir: as_argument("$PWD/target/debug/deps").into(),
                 target_path: as_argument("$PWD/target/debug").into(),
+                target: None,
             }
         );
 
@@ -367,6 +376,7 @@ mod tests {
                 input: as_argument("src/main.rs").into(),
                 out_dir: as_argument("$PWD/target/debug/deps").into(),
                 target_path: as_argument("$PWD/target/debug").into(),
+                target: None,
             }
         );
 
@@ -397,6 +407,46 @@ mod tests {
              ]), args);
     }
 
+    #[test]
+    fn args_when_cross_compiling() {
+        #[rustfmt::skip]
+        let arguments = as_arguments(&[
+            "$PWD/./dbg/debug/rustcbuildx",                                                   // this
+            "$HOME/.rustup/toolchains/stable-x86_64-unknown-linux-gnu/bin/rustc",             // rustc
+            "--crate-name", "rustcbuildx",                                                    // crate_name
+            "--edition=2021",
+            "src/main.rs",                                                                    // state.input
+            "--crate-type", "bin",
+            "-C", "metadata=710b4516f388a5e4",
+            "-C", "extra-filename=-710b4516f388a5e4",                                         // state.mdid
+            "--out-dir", "$PWD/target/aarch64-unknown-linux-gnu/debug/deps",                  // state.out_dir =+> state.target_path
+            "--target", "aarch64-unknown-linux-gnu",                                          // state.target
+            "-L", "dependency=$PWD/target/aarch64-unknown-linux-gnu/debug/deps",
+            "-L", "dependency=$PWD/target/debug/deps",
+            "--extern", "anyhow=$PWD/target/aarch64-unknown-linux-gnu/debug/deps/libanyhow-f96497119bad6f50.rlib", // state.externs
+            "--extern", "serde_derive=$PWD/target/debug/deps/libserde_derive-7e2d283f6e473671.so",                 // state.externs
+        ]);
+
+        let (st, args) = as_rustc(PWD.into(), &arguments, None).unwrap();
+
+        assert_eq!(
+            st,
+            RustcArgs {
+                externs: ["libanyhow-f96497119bad6f50.rlib", "libserde_derive-7e2d283f6e473671.so"]
+                    .into_iter()
+                    .map(ToOwned::to_owned)
+                    .collect(),
+                mdid: Some("710b4516f388a5e4".into()),
+                incremental: None,
+                input: as_argument("src/main.rs").into(),
+                out_dir: as_argument("$PWD/target/aarch64-unknown-linux-gnu/debug/deps").into(),
+                target_path: as_argument("$PWD/target/aarch64-unknown-linux-gnu/debug").into(),
+                target: Some("aarch64-unknown-linux-gnu".to_owned()),
+            }
+        );
+        assert!(args.windows(2).any(|kv| kv == ["--target", "aarch64-unknown-linux-gnu"]));
+    }
+
     #[test]
     fn args_when_building_build_script() {
         #[rustfmt::skip]
@@ -438,6 +488,7 @@ mod tests {
                 input: as_argument("$HOME/.cargo/registry/src/index.crates.io-6f17d22bba15001f/rustix-0.38.20/build.rs").into(),
                 out_dir: as_argument("$PWD/target/debug/build/rustix-c7101a3d6c8e4dce").into(),
                 target_path: as_argument("$PWD/target/debug").into(),
+                target: None,
             }
         );
 
@@ -508,6 +559,7 @@ mod tests {
                 input: as_argument("$HOME/.cargo/registry/src/index.crates.io-6f17d22bba15001f/time-macros-0.2.14/src/lib.rs").into(),
                 out_dir: as_argument("/tmp/wfrefwef__cargo-deny_0-14-3/release/deps").into(),
                 target_path: as_argument("/tmp/wfrefwef__cargo-deny_0-14-3/release").into(),
+                target: None,
             }
         );
 
@@ -577,6 +629,7 @@ mod tests {
                 )
                 .into(),
                 target_path: as_argument("/tmp/wfrefwef__cross@0.2.5/release").into(),
+                target: None,
             }
         );
 
@@ -645,6 +698,7 @@ mod tests {
                 )
                 .into(),
                 target_path: as_argument("$HOME/instst/release").into(),
+                target: None,
             }
         );
 
@@ -736,6 +790,7 @@ mod tests {
                 input: "".into(),
                 out_dir: "".into(),
                 target_path: "$HOME/work/supergreen/supergreen/target/debug".into(),
+                target: None,
             }
         );
         assert_eq!(Vec::<String>::new(), args);
@@ -1,3 +1,17 @@
+use std::{fs, io::Write, process::Command, str::FromStr, sync::Once, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+use atomic_write_file::AtomicWriteFile;
+use camino::{Utf8Path, Utf8PathBuf};
+use log::{debug, info};
+use rustup_toolchain_manifest::{
+    InstallSpec, Manifest, Toolchain,
+    manifest::{Compression, Digest, Package, RemoteBinary},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{dirs::hash, image_uri::BAD_CHARS, retrier::Retrier};
+
 pub(crate) const VERSION: &str = "1.29.0";
 
 pub(crate) static CHECKSUMS: phf::Map<&'static str, &'static str> = phf::phf_map! {
@@ -5,3 +19,480 @@ pub(crate) static CHECKSUMS: phf::Map<&'static str, &'static str> = phf::phf_map
     "aarch64-unknown-linux-gnu" => "9732d6c5e2a098d3521fca8145d826ae0aaa067ef2385ead08e6feac88fa5792",
     "x86_64-unknown-linux-gnu"  => "4acc9acc76d5079515b46346a485974457b5a79893cfb01112423c89aeb5aa10",
 };
+
+/// The parts of a `rust-toolchain.toml` that shape the base image
+/// <https://rust-lang.github.io/rustup/overrides.html#the-toolchain-file>
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub(crate) struct ToolchainFile {
+    pub(crate) channel: Option<String>,
+    pub(crate) profile: Option<String>,
+    pub(crate) components: Vec<String>,
+    pub(crate) targets: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ToolchainFileToml {
+    toolchain: ToolchainFile,
+}
+
+impl ToolchainFile {
+    /// Reads the toolchain file rustup would pick from within `dir`, if it applies to `toolchain`.
+    pub(crate) fn find(dir: &Utf8Path, toolchain: &str) -> Result<Self> {
+        for dir in dir.ancestors() {
+            for name in ["rust-toolchain", "rust-toolchain.toml"] {
+                let path = dir.join(name);
+                if !path.is_file() {
+                    continue;
+                }
+                let txt = fs::read_to_string(&path)
+                    .map_err(|e| anyhow!("Failed reading toolchain file {path}: {e}"))?;
+                let file = Self::parse(&txt).map_err(|e| anyhow!("{path}: {e}"))?;
+                if !file.applies_to(toolchain) {
+                    info!("ignoring {path} as it does not apply to {toolchain}");
+                    return Ok(Self::default());
+                }
+                info!("using toolchain file {path}");
+                return Ok(file);
+            }
+        }
+        Ok(Self::default())
+    }
+
+    fn parse(txt: &str) -> Result<Self> {
+        let txt = txt.trim();
+        if !txt.contains('\n') && !txt.contains('=') {
+            // Legacy format: a bare channel name
+            return Ok(Self { channel: Some(txt.to_owned()), ..Default::default() });
+        }
+
+        let ToolchainFileToml { toolchain: file } =
+            toml::from_str(txt).map_err(|e| anyhow!("Failed parsing toolchain file: {e}"))?;
+
+        if let Some(ref profile) = file.profile
+            && !PROFILES.contains(&profile.as_str())
+        {
+            bail!("Unexpected toolchain profile {profile:?}, expected one of {PROFILES:?}")
+        }
+        for (field, name) in [(&file.components, "components"), (&file.targets, "targets")] {
+            if field.iter().any(|x| x.is_empty() || x.contains(BAD_CHARS)) {
+                bail!("Toolchain {name} contain empty names, whitespace, quotes or bad characters")
+            }
+        }
+        Ok(file)
+    }
+
+    /// Whether `$RUSTUP_TOOLCHAIN` was resolved from this file (e.g. not `cargo +nightly ..`)
+    fn applies_to(&self, toolchain: &str) -> bool {
+        match self.channel.as_deref() {
+            None => true,
+            Some(channel) => toolchain == channel || toolchain.starts_with(&format!("{channel}-")),
+        }
+    }
+}
+
+/// <https://rust-lang.github.io/rustup/concepts/profiles.html>
+const PROFILES: &[&str] = &["minimal", "default", "complete"];
+
+impl ToolchainFile {
+    /// The profile to install, defaulting to rustup's lightest
+    #[must_use]
+    pub(crate) fn profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or("minimal")
+    }
+
+    /// Components from the setting, then the ones only listed in the file
+    #[must_use]
+    pub(crate) fn components(&self, setting: &[String]) -> Vec<String> {
+        let mut components = setting.to_vec();
+        for component in &self.components {
+            if !components.contains(component) {
+                components.push(component.clone());
+            }
+        }
+        components
+    }
+}
+
+pub(crate) fn maybe_get_local_host_triple(toolchain: &str) -> Result<String> {
+    if let Some(host) = parse_toolchain(toolchain)?.host.map(|h| h.target_triple) {
+        Ok(host.to_owned())
+    } else {
+        rustc_host::from_cli().map_err(|e| anyhow!("Failed getting local host triple: {e}"))
+    }
+}
+
+fn parse_toolchain(toolchain: &str) -> Result<Toolchain> {
+    Toolchain::from_str(toolchain)
+        .map_err(|e| anyhow!("Failed parsing $RUSTUP_TOOLCHAIN={toolchain:?}: {e}"))
+}
+
+/// A toolchain tarball, pinned by its channel manifest
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) struct Dist {
+    pub(crate) url: String,
+    pub(crate) sha256: String,
+}
+
+/// Like [`dists`] but only asks the channel manifest once per local toolchain:
+/// the base stage (and so every cache key) then stays the same across runs, network or not.
+///
+/// When `offline`, only previously pinned tarballs are used. Without any, `rustup-init` installs the toolchain.
+pub(crate) async fn pinned_dists(
+    pins: &Utf8Path,
+    toolchain: &str,
+    host: &str,
+    toolchain_file: &ToolchainFile,
+    components: &[String],
+    offline: bool,
+) -> Result<Vec<Dist>> {
+    let pinned = pin_path(pins, toolchain, host, toolchain_file, components)?;
+
+    if pinned.exists() {
+        debug!("reading toolchain tarballs pinned in {pinned}");
+        let txt =
+            fs::read_to_string(&pinned).map_err(|e| anyhow!("Failed reading {pinned}: {e}"))?;
+        return serde_json::from_str(&txt).map_err(|e| anyhow!("Corrupted {pinned}: {e}"));
+    }
+    if offline {
+        info!("no toolchain tarballs pinned yet in {pinned}: falling back to rustup-init");
+        return Ok(vec![]);
+    }
+
+    let dists = dists(toolchain, host, toolchain_file, components).await.map_err(|e| {
+        anyhow!("Failed pinning toolchain {toolchain}: {e} (set $CARGO_NET_OFFLINE=1 to install it with rustup-init)")
+    })?;
+
+    let mut file = AtomicWriteFile::options()
+        .open(&pinned)
+        .map_err(|e| anyhow!("Failed opening atomic {pinned}: {e}"))?;
+    file.write_all(serde_json::to_string_pretty(&dists)?.as_bytes())
+        .map_err(|e| anyhow!("Failed writing {pinned}: {e}"))?;
+    file.commit().map_err(|e| anyhow!("Failed committing {pinned}: {e}"))?;
+    info!("pinned toolchain tarballs in {pinned}");
+    Ok(dists)
+}
+
+fn pin_path(
+    pins: &Utf8Path,
+    toolchain: &str,
+    host: &str,
+    toolchain_file: &ToolchainFile,
+    components: &[String],
+) -> Result<Utf8PathBuf> {
+    let key = format!(
+        "{} {host} {} {components:?} {toolchain_file:?}",
+        local_rustc_version()?,
+        toolchain_file.profile()
+    );
+    Ok(pins.join(format!("{toolchain}-{}.json", hash(&key))))
+}
+
+/// E.g. `rustc 1.90.0 (1159e78c4 2025-09-14)`: tells apart successive installs of a moving channel (`stable`, ...)
+fn local_rustc_version() -> Result<String> {
+    let out = Command::new("rustc")
+        .arg("-V")
+        .output()
+        .map_err(|e| anyhow!("Failed calling `rustc -V`: {e}"))?;
+    if !out.status.success() {
+        bail!("Failed calling `rustc -V`: {}", String::from_utf8_lossy(&out.stderr))
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_owned())
+}
+
+/// Resolves the tarballs `rustup-init` would download, from the toolchain's channel manifest.
+async fn dists(
+    toolchain: &str,
+    host: &str,
+    toolchain_file: &ToolchainFile,
+    components: &[String],
+) -> Result<Vec<Dist>> {
+    let url = parse_toolchain(toolchain)?.manifest_url();
+
+    let show = Once::new();
+    let mut retrier = Retrier::with_max_attempts(3);
+    let txt = loop {
+        show.call_once(|| info!("GETing {url}"));
+        let res = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(4))
+            .build()
+            .map_err(|e| anyhow!("HTTP client's config/TLS failed: {e}"))?
+            .get(&url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        match res {
+            Ok(res) => {
+                break res.text().await.map_err(|e| anyhow!("Failed reading {url}: {e}"))?;
+            }
+            Err(e) if retrier.continues() => retrier.backoff("connection", e.into()).await,
+            Err(e) => bail!("Failed fetching {url} (retried {} times): {e}", retrier.max()),
+        }
+    };
+
+    dists_from_manifest(&txt, toolchain, host, toolchain_file, components)
+}
+
+fn dists_from_manifest(
+    txt: &str,
+    toolchain: &str,
+    host: &str,
+    toolchain_file: &ToolchainFile,
+    components: &[String],
+) -> Result<Vec<Dist>> {
+    let manifest =
+        Manifest::try_from(txt).map_err(|e| anyhow!("Failed parsing channel manifest: {e}"))?;
+
+    let platform = match parse_toolchain(toolchain)?.host {
+        Some(platform) => Some(platform),
+        None => parse_toolchain(&format!("{toolchain}-{host}"))?.host,
+    };
+    let Some(host) = platform else { bail!("Unhandled rustup host {host:?}") };
+
+    let spec = InstallSpec {
+        profile: toolchain_file.profile().to_owned(),
+        components: toolchain_file.components(components).into_iter().collect(),
+        targets: toolchain_file.targets.iter().cloned().collect(),
+    };
+    let packages = manifest
+        .find_downloads_for_install(&host, &spec)
+        .map_err(|e| anyhow!("Failed resolving toolchain packages: {e}"))?;
+
+    let mut dists = packages
+        .into_iter()
+        .map(|Package { name, mut tarballs, .. }| {
+            // Smallest first
+            tarballs.sort_by_key(|(compression, _)| match compression {
+                Compression::Xz => 0,
+                Compression::Gzip => 1,
+                Compression::None => 2,
+            });
+            let Some((_, RemoteBinary { url, digests })) = tarballs.into_iter().next() else {
+                bail!("No tarball available for package {name}")
+            };
+            let Some(sha256) = digests.get(&Digest::Sha256) else {
+                bail!("No SHA-256 digest for package {name}")
+            };
+            Ok(Dist { url, sha256: sha256.to_string() })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    dists.sort();
+    Ok(dists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_toml() {
+        let file = ToolchainFile::parse(
+            r#"
+[toolchain]
+channel = "1.80.0"
+components = [ "rustfmt", "clippy", "llvm-tools" ]
+targets = [ "wasm32-unknown-unknown" ]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            file,
+            ToolchainFile {
+                channel: Some("1.80.0".to_owned()),
+                profile: None,
+                components: vec![
+                    "rustfmt".to_owned(),
+                    "clippy".to_owned(),
+                    "llvm-tools".to_owned()
+                ],
+                targets: vec!["wasm32-unknown-unknown".to_owned()],
+            }
+        );
+        assert!(file.applies_to("1.80.0-x86_64-unknown-linux-gnu"));
+        assert!(!file.applies_to("1.80.1-x86_64-unknown-linux-gnu"));
+        assert!(!file.applies_to("nightly-x86_64-unknown-linux-gnu"));
+    }
+
+    #[test]
+    fn parse_legacy() {
+        let file = ToolchainFile::parse("nightly-2025-09-14\n").unwrap();
+        assert_eq!(file.channel.as_deref(), Some("nightly-2025-09-14"));
+        assert!(file.components.is_empty());
+        assert!(file.applies_to("nightly-2025-09-14-aarch64-apple-darwin"));
+    }
+
+    const MANIFEST: &str = r#"
+manifest-version = "2"
+date = "2024-07-25"
+
+[profiles]
+minimal = ["rustc", "cargo", "rust-std"]
+
+[renames.clippy]
+to = "clippy-preview"
+
+[artifacts]
+
+[pkg.rust]
+version = "1.80.0 (051478957 2024-07-21)"
+git_commit_hash = "051478957371ee0084a7c0913941d2a8c4757bb9"
+[pkg.rust.target.x86_64-unknown-linux-gnu]
+available = true
+components = [
+  { pkg = "rustc", target = "x86_64-unknown-linux-gnu" },
+  { pkg = "cargo", target = "x86_64-unknown-linux-gnu" },
+  { pkg = "rust-std", target = "x86_64-unknown-linux-gnu" },
+]
+extensions = [
+  { pkg = "clippy-preview", target = "x86_64-unknown-linux-gnu" },
+  { pkg = "rust-src", target = "*" },
+  { pkg = "rust-std", target = "aarch64-unknown-linux-gnu" },
+]
+
+[pkg.rustc]
+version = "1.80.0 (051478957 2024-07-21)"
+git_commit_hash = "051478957371ee0084a7c0913941d2a8c4757bb9"
+[pkg.rustc.target.x86_64-unknown-linux-gnu]
+available = true
+url = "https://static.rust-lang.org/dist/rustc.tar.gz"
+hash = "01"
+xz_url = "https://static.rust-lang.org/dist/rustc.tar.xz"
+xz_hash = "02"
+
+[pkg.cargo]
+version = "0.81.0 (2dbb1af80 2024-07-20)"
+git_commit_hash = "051478957371ee0084a7c0913941d2a8c4757bb9"
+[pkg.cargo.target.x86_64-unknown-linux-gnu]
+available = true
+url = "https://static.rust-lang.org/dist/cargo.tar.gz"
+hash = "03"
+
+[pkg.rust-std]
+version = "1.80.0 (051478957 2024-07-21)"
+git_commit_hash = "051478957371ee0084a7c0913941d2a8c4757bb9"
+[pkg.rust-std.target.x86_64-unknown-linux-gnu]
+available = true
+xz_url = "https://static.rust-lang.org/dist/rust-std-x86_64.tar.xz"
+xz_hash = "04"
+[pkg.rust-std.target.aarch64-unknown-linux-gnu]
+available = true
+xz_url = "https://static.rust-lang.org/dist/rust-std-aarch64.tar.xz"
+xz_hash = "05"
+
+[pkg.clippy-preview]
+version = "0.1.80 (051478957 2024-07-21)"
+git_commit_hash = "051478957371ee0084a7c0913941d2a8c4757bb9"
+[pkg.clippy-preview.target.x86_64-unknown-linux-gnu]
+available = true
+xz_url = "https://static.rust-lang.org/dist/clippy.tar.xz"
+xz_hash = "06"
+
+[pkg.rust-src]
+version = "1.80.0 (051478957 2024-07-21)"
+git_commit_hash = "051478957371ee0084a7c0913941d2a8c4757bb9"
+[pkg.rust-src.target."*"]
+available = true
+xz_url = "https://static.rust-lang.org/dist/rust-src.tar.xz"
+xz_hash = "07"
+"#;
+
+    fn dist(name: &str, sha256: &str) -> Dist {
+        let url = format!("https://static.rust-lang.org/dist/{name}");
+        Dist { url, sha256: sha256.to_owned() }
+    }
+
+    #[test]
+    fn dists_minimal() {
+        let dists = dists_from_manifest(
+            MANIFEST,
+            "1.80.0-x86_64-unknown-linux-gnu",
+            "x86_64-unknown-linux-gnu",
+            &ToolchainFile::default(),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            dists,
+            vec![
+                dist("cargo.tar.gz", "03"),
+                dist("rust-std-x86_64.tar.xz", "04"),
+                dist("rustc.tar.xz", "02"),
+            ]
+        );
+    }
+
+    #[test]
+    fn dists_components_and_targets() {
+        let toolchain_file = ToolchainFile {
+            components: vec!["rust-src".to_owned()],
+            targets: vec!["aarch64-unknown-linux-gnu".to_owned()],
+            ..Default::default()
+        };
+        let dists = dists_from_manifest(
+            MANIFEST,
+            "1.80.0",
+            "x86_64-unknown-linux-gnu",
+            &toolchain_file,
+            &["clippy".to_owned()],
+        )
+        .unwrap();
+        assert_eq!(
+            dists,
+            vec![
+                dist("cargo.tar.gz", "03"),
+                dist("clippy.tar.xz", "06"),
+                dist("rust-src.tar.xz", "07"),
+                dist("rust-std-aarch64.tar.xz", "05"),
+                dist("rust-std-x86_64.tar.xz", "04"),
+                dist("rustc.tar.xz", "02"),
+            ]
+        );
+    }
+
+    #[test]
+    fn dists_unknown_component() {
+        let err = dists_from_manifest(
+            MANIFEST,
+            "1.80.0",
+            "x86_64-unknown-linux-gnu",
+            &ToolchainFile::default(),
+            &["miri".to_owned()],
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("miri"), "In: {err}");
+    }
+
+    #[test]
+    fn parse_bad_profile() {
+        let err = ToolchainFile::parse("[toolchain]\nprofile = \"max\"").unwrap_err().to_string();
+        assert!(err.contains("profile"), "In: {err}");
+    }
+
+    #[tokio::test]
+    async fn pins_dists() {
+        let pins: Utf8PathBuf =
+            std::env::temp_dir().join(uuid::Uuid::new_v4().to_string()).try_into().unwrap();
+        fs::create_dir_all(&pins).unwrap();
+        let (toolchain, host) = ("1.80.0", "x86_64-unknown-linux-gnu");
+        let file = ToolchainFile::default();
+
+        let dists = pinned_dists(&pins, toolchain, host, &file, &[], true).await.unwrap();
+        assert_eq!(dists, [], "offline and nothing pinned: rustup-init");
+
+        let pinned = vec![Dist {
+            url: "https://static.rust-lang.org/x.tar.xz".to_owned(),
+            sha256: "c0".to_owned(),
+        }];
+        let path = pin_path(&pins, toolchain, host, &file, &[]).unwrap();
+        fs::write(&path, serde_json::to_string(&pinned).unwrap()).unwrap();
+        for offline in [true, false] {
+            let dists = pinned_dists(&pins, toolchain, host, &file, &[], offline).await.unwrap();
+            assert_eq!(dists, pinned);
+        }
+
+        let other = pin_path(&pins, toolchain, host, &file, &["rust-src".to_owned()]).unwrap();
+        assert_ne!(path, other);
+        fs::remove_dir_all(&pins).unwrap();
+    }
+}
@@ -5,15 +5,21 @@ use std::{
     process::Stdio,
 };
 
-use anyhow::{Result, bail};
+use anyhow::{Result, anyhow, bail};
 use camino::Utf8Path;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use futures::stream::{StreamExt, TryStreamExt, iter};
 use serde_jsonlines::AsyncBufReadJsonLines;
 use tokio::io::BufReader;
 
 use crate::{
-    PKG, REPO, VSN, base_image::CARGO_HOME, ext::CommandExt, green::Green, image_uri::ImageUri,
+    PKG, REPO, VSN,
+    base_image::{CARGO_HOME, show_also_run},
+    builder::show_registries,
+    doctor,
+    ext::CommandExt,
+    green::{Green, env_as_toml},
+    image_uri::ImageUri,
     wrap::safeify,
 };
 
@@ -68,6 +74,12 @@ enum Supergreen {
         vars: Vec<String>,
     },
 
+    /// Show the effective configuration and where each value comes from
+    Config {
+        #[arg(long, value_enum, default_value_t = Format::Toml)]
+        format: Format,
+    },
+
     /// Show base stage in use
     ShowRustBase,
 
@@ -80,11 +92,30 @@ enum Supergreen {
     /// Push cache image (all tags)
     Push,
 
+    /// Resolve latest digests of images in use (optionally writing them to Cargo.toml)
+    UpdateImages {
+        #[arg(long)]
+        write: bool,
+    },
+
     /// Manage local/remote builder
     Builder {
         #[command(subcommand)]
         sub: Option<BuilderSub>,
     },
+
+    /// Check setup, runner, builder, disk and registries (exits non-zero on failures)
+    Doctor {
+        /// Also print versions and settings to attach to bug reports (redacted)
+        #[arg(long)]
+        bundle: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    Toml,
+    Json,
 }
 
 #[derive(Subcommand, Debug)]
@@ -105,6 +136,11 @@ enum BuilderSub {
         #[arg(long)]
         clean: bool,
     },
+    /// Free the builder's cache (internal and frontend records too with --all)
+    Prune {
+        #[arg(long)]
+        all: bool,
+    },
 }
 
 // TODO: tune logging verbosity https://docs.rs/clap-verbosity-flag/latest/clap_verbosity_flag/
@@ -117,7 +153,7 @@ enum BuilderSub {
 
 // TODO: short-hand command to show builder logs cf https://docs.docker.com/engine/daemon/logs/
 
-pub(crate) async fn main(mut green: Green) -> Result<()> {
+pub(crate) async fn main(green: Result<Green>) -> Result<()> {
     let Cli {
         cli: Some(GreenCli::Green { sub: Some(SupergreenCli::Supergreen { sub: Some(cmd) }) }),
     } = Cli::try_parse()?
@@ -125,14 +161,22 @@ pub(crate) async fn main(mut green: Green) -> Result<()> {
         bail!("BUG: unhandled subcommand {:?}", env::args())
     };
 
+    // Reports on failed setups too
+    if let Supergreen::Doctor { bundle } = cmd {
+        return doctor::main(green, bundle).await;
+    }
+    let mut green = green?;
+
     match cmd {
         Supergreen::Setup => { /* done during Green init */ }
         Supergreen::Env { vars } => green.envs(vars)?,
         Supergreen::Doc { vars } => green.docs(vars)?,
+        Supergreen::Config { format } => green.config(format)?,
         Supergreen::ShowRustBase => println!("{}", green.base.image_inline),
         Supergreen::Sync { sub: None } => green.prebuild(false, false).await?,
         Supergreen::Sync { sub: Some(SyncSub::Data) } => sync_data(&green),
         Supergreen::Push => green.push().await?,
+        Supergreen::UpdateImages { write } => green.update_images(write).await?,
         Supergreen::Builder { sub: None } => green.inspect_builder().await?,
         Supergreen::Builder { sub: Some(BuilderSub::Rm { clean }) } => {
             green.rm_builder(!clean).await?
@@ -140,6 +184,10 @@ pub(crate) async fn main(mut green: Green) -> Result<()> {
         Supergreen::Builder { sub: Some(BuilderSub::Recreate { clean }) } => {
             green.recreate_builder(!clean).await?
         }
+        Supergreen::Builder { sub: Some(BuilderSub::Prune { all }) } => {
+            green.backend().prune(&green, all).await?
+        }
+        Supergreen::Doctor { .. } => bail!("BUG: doctor is handled above"),
     }
     Ok(())
 }
@@ -159,11 +207,13 @@ pub(crate) fn just_help() -> bool {
 fn sync_data(green: &Green) {
     if let Some(ref data) = green.builder.data {
         println!("{data}");
+    } else if !green.builder.has_container() {
+        eprintln!("Builder has no local container, hence no data directory");
     }
 }
 
 //TODO: util to inspect + clear (+ push) build cache: docker buildx du --verbose
-//TODO: prune command (use filters) https://github.com/docker/buildx/pull/2473
+//TODO: prune only some records (use filters) https://github.com/docker/buildx/pull/2473
 //      ~ 🤖 docker buildx du --verbose --filter type=frontend
 // ID:     peng2elrcincm360vextha1zz
 // Created at: 2025-03-30 16:26:19.48787607 +0000 UTC
@@ -204,71 +254,56 @@ pub(crate) fn help() {
 
 impl Green {
     async fn inspect_builder(&self) -> Result<()> {
-        assert!(!self.runner.is_none(), "inspect_builder() called with Runner::None");
-        let mut cmd = self.cmd()?;
-        cmd.args(["buildx", "inspect"]);
-
-        let (succeeded, stdout, stderr) = cmd.exec().await?;
-        if !succeeded {
-            let stderr = String::from_utf8_lossy(&stderr);
-            bail!("BUG: failed to inspect builder: {stderr}")
-        }
-
-        let stdout = String::from_utf8_lossy(&stdout);
-        println!("{stdout}");
+        println!("{}", self.backend().show_builder(self).await?);
         Ok(())
     }
 
-    async fn rm_builder(&mut self, keep_state: bool) -> Result<()> {
-        let Some(name) = self.builder.name.clone() else { return Ok(()) };
-        self.remove_builder(&name, keep_state).await?;
-        Ok(())
+    async fn rm_builder(&self, keep_state: bool) -> Result<()> {
+        let Some(ref name) = self.builder.name else { return Ok(()) };
+        self.backend().remove_builder(self, name, keep_state).await
     }
 
     async fn recreate_builder(&mut self, keep_state: bool) -> Result<()> {
         let Some(name) = self.builder.name.clone() else { return Ok(()) };
-        self.remove_builder(&name, keep_state).await?;
-        self.create_builder(&name).await?;
+        let backend = self.backend();
+        backend.remove_builder(self, &name, keep_state).await?;
+        backend.create_builder(self, &name).await?;
         self.inspect_builder().await?;
         Ok(())
     }
 }
 
 impl Green {
-    // TODO: make it work for podman: https://github.com/containers/podman/issues/2369
-    // TODO: have fun with https://github.com/console-rs/indicatif
     async fn push(&self) -> Result<()> {
+        let backend = self.backend();
         for img in self.cache.to_images.iter().chain(self.cache.images.iter()) {
-            let img = img.noscheme();
-            let tags = all_tags_of(self, img).await?;
-
-            async fn do_push(green: &Green, tag: String, img: &str) -> Result<()> {
-                println!("Pushing {img}:{tag}...");
-                assert!(!green.runner.is_none(), "do_push() called with Runner::None");
-                let mut cmd = green.cmd()?;
-                cmd.arg("push")
-                    .arg(format!("{img}:{tag}"))
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::null());
-
-                if let Ok(mut o) = cmd.spawn()
-                    && let Ok(o) = o.wait().await
-                    && o.success()
-                {
-                    println!("Pushing {img}:{tag}... done!");
-                    return Ok(());
-                }
-                bail!("Pushing {img}:{tag} failed!")
-            }
-
-            iter(tags)
-                .map(|tag| do_push(self, tag, img))
-                .buffer_unordered(10)
-                .try_collect::<()>()
-                .await?;
+            backend.push(self, img.noscheme()).await?;
         }
         Ok(())
     }
+
+    // TODO: have fun with https://github.com/console-rs/indicatif
+    pub(crate) async fn push_all_tags(&self, img: &str) -> Result<()> {
+        let tags = all_tags_of(self, img).await?;
+
+        async fn do_push(green: &Green, tag: String, img: &str) -> Result<()> {
+            println!("Pushing {img}:{tag}...");
+            assert!(!green.runner.is_none(), "do_push() called with Runner::None");
+            let mut cmd = green.cmd()?;
+            cmd.arg("push").arg(format!("{img}:{tag}")).stdout(Stdio::null()).stderr(Stdio::null());
+
+            if let Ok(mut o) = cmd.spawn()
+                && let Ok(o) = o.wait().await
+                && o.success()
+            {
+                println!("Pushing {img}:{tag}... done!");
+                return Ok(());
+            }
+            bail!("Pushing {img}:{tag} failed!")
+        }
+
+        iter(tags).map(|tag| do_push(self, tag, img)).buffer_unordered(10).try_collect::<()>().await
+    }
 }
 
 // TODO: test with known tags
@@ -312,23 +347,65 @@ macro_rules! var {
     };
 }
 
-fn all_envs(green: &Green) -> Vec<(&str, &'static str, Option<String>)> {
+pub(crate) fn all_envs(green: &Green) -> Vec<(&str, &'static str, Option<String>)> {
     vec![
         // var!(ENV!(), env::var(ENV!()).ok()),
         var!(ENV_LOG_PATH!(), env::var(ENV_LOG_PATH!()).ok()),
         var!(ENV_LOG!(), env::var(ENV_LOG!()).ok()),
         var!(ENV_LOG_STYLE!(), env::var(ENV_LOG_STYLE!()).ok()),
+        var!(ENV_PROGRESS!(), env::var(ENV_PROGRESS!()).ok()),
+        var!(ENV_PROFILE!(), green.profile.clone()),
         var!(ENV_RUNNER!(), Some(green.runner.to_string())),
         var!(BUILDX_BUILDER!(), green.builder.name.as_deref().map(ToOwned::to_owned)),
         var!(ENV_BUILDER_IMAGE!(), green.builder.image.as_deref().map(ToString::to_string)),
+        var!(ENV_BUILDER_DRIVER!(), green.builder.driver.as_ref().map(ToString::to_string)),
+        var!(
+            ENV_REMOTES!(),
+            (!green.builder.remotes.is_empty()).then(|| {
+                green.builder.remotes.iter().map(ToString::to_string).collect::<Vec<_>>().join(";")
+            })
+        ),
+        var!(
+            ENV_BUILDER_MAX_PARALLELISM!(),
+            green.builder.tuning.max_parallelism.map(|max| max.to_string())
+        ),
+        var!(ENV_BUILDER_GC_KEEP_STORAGE!(), green.builder.tuning.gc_keep_storage.clone()),
+        var!(
+            ENV_BUILDER_GC_POLICIES!(),
+            (!green.builder.tuning.gc_policies.is_empty()).then(|| {
+                green
+                    .builder
+                    .tuning
+                    .gc_policies
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(";")
+            })
+        ),
+        var!(ENV_BUILDER_ENTITLEMENTS!(), csv(&green.builder.tuning.entitlements)),
         var!(ENV_SYNTAX_IMAGE!(), Some(green.syntax.to_string())),
         var!(ENV_REGISTRY_MIRRORS!(), csv(&green.registry_mirrors)),
+        var!(
+            ENV_REGISTRIES!(),
+            (!green.builder.registries.is_empty())
+                .then(|| show_registries(&green.builder.registries))
+        ),
         var!(ENV_CACHE_IMAGES!(), csv_uris(&green.cache.images)),
         var!(ENV_CACHE_FROM_IMAGES!(), csv_uris(&green.cache.from_images)),
         var!(ENV_CACHE_TO_IMAGES!(), csv_uris(&green.cache.to_images)),
         var!(ENV_FINAL_PATH!(), green.r#final.path.as_deref().map(ToString::to_string)),
         var!(ENV_BASE_IMAGE!(), Some(green.base.image.to_string())),
+        var!(
+            ENV_ALSO_RUN!(),
+            (!green.base.also_run.is_empty()).then(|| show_also_run(&green.base.also_run))
+        ),
         var!(ENV_SET_ENVS!(), csv(&green.set_envs)),
+        var!(
+            ENV_ADDITIONAL_BUILD_ARGUMENTS!(),
+            (!green.additional_build_arguments.is_empty())
+                .then(|| green.additional_build_arguments.join(" "))
+        ),
         var!(ENV_WITH_NETWORK!(), Some(green.base.with_network.to_string())),
         var!(ENV_COMPONENTS!(), csv(&green.components)),
         var!(ENV_ADD_APT!(), csv(&green.add.apt)),
@@ -394,6 +471,83 @@ impl Green {
     }
 }
 
+/// Lists the values of a table as pairs of (dotted key path, value)
+fn leaves(table: &toml::Table, prefix: &[String], acc: &mut Vec<(Vec<String>, toml::Value)>) {
+    for (key, value) in table {
+        let mut path = prefix.to_vec();
+        path.push(key.clone());
+        match value {
+            toml::Value::Table(table) => leaves(table, &path, acc),
+            _ => acc.push((path, value.clone())),
+        }
+    }
+}
+
+fn dotted(path: &[String]) -> String {
+    path.iter()
+        .map(|key| {
+            if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                key.clone()
+            } else {
+                toml::Value::String(key.clone()).to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+impl Green {
+    /// The settings users can set, as pairs of (dotted key path, value).
+    ///
+    /// Each of these has an environment variable, and per-package overrides live under `packages`:
+    /// any other key is internal state.
+    fn settings(&self) -> Result<Vec<(Vec<String>, toml::Value)>> {
+        let table =
+            toml::Table::try_from(self).map_err(|e| anyhow!("Failed serializing config: {e}"))?;
+
+        let settable: Vec<_> = all_envs(self)
+            .into_iter()
+            .map(|(var, ..)| env_as_toml(var))
+            .chain(["packages".to_owned()])
+            .collect();
+
+        let mut values = vec![];
+        leaves(&table, &[], &mut values);
+        values.retain(|(path, _)| {
+            let key = path.join(".");
+            settable.iter().any(|settable| {
+                key.strip_prefix(settable.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            })
+        });
+        Ok(values)
+    }
+
+    fn config(&self, format: Format) -> Result<()> {
+        let values = self.settings()?;
+
+        match format {
+            Format::Toml => {
+                for (path, value) in values {
+                    let origin = self.origins.provenance(&path.join("."));
+                    println!("{} = {value} # {origin}", dotted(&path));
+                }
+            }
+            Format::Json => {
+                let values: serde_json::Map<_, _> = values
+                    .into_iter()
+                    .map(|(path, value)| {
+                        let origin = self.origins.provenance(&path.join("."));
+                        (path.join("."), serde_json::json!({"value": value, "origin": origin}))
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&values)?);
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Green {
     pub(crate) fn setup(&self) -> Result<()> {
         let _ = fs::create_dir_all(&self.cargo_home);
@@ -425,3 +579,44 @@ Please try:
         Ok(())
     }
 }
+
+#[test]
+fn shows_only_settings() {
+    use crate::builder::Driver;
+
+    let mut green = Green { cargo_home: "/home/maison/.cargo".into(), ..Default::default() };
+    green.runner_envs.insert("DOCKER_HOST".to_owned(), "ssh://me@example.com".to_owned());
+    green.builder.driver = Some(Driver::DockerContainer);
+    green.builder.id = Some("0123".to_owned());
+    green.base.image_inline = "FROM rust AS rust-base".to_owned();
+    green.add.apt = vec!["libpq-dev".to_owned()];
+
+    let settings = green.settings().unwrap();
+    let keys: Vec<_> = settings.iter().map(|(path, _)| path.join(".")).collect();
+    assert!(keys.contains(&"builder-driver".to_owned()), "{keys:?}");
+    let (_, driver) = settings.iter().find(|(path, _)| path == &["builder-driver"]).unwrap();
+    assert_eq!(driver.to_string(), r#""docker-container""#);
+    assert!(keys.contains(&"add.apt".to_owned()), "{keys:?}");
+    assert!(keys.contains(&"base-image".to_owned()), "{keys:?}");
+    for internal in ["cargo-home", "runner-envs.DOCKER_HOST", "id", "image_inline"] {
+        assert!(!keys.contains(&internal.to_owned()), "{internal} in {keys:?}");
+    }
+}
+
+#[test]
+fn parses_builder_prune() {
+    let parse = |args: &[&str]| {
+        let args = ["cargo", "green", "supergreen", "builder", "prune"].iter().chain(args);
+        match Cli::try_parse_from(args).unwrap().cli {
+            Some(GreenCli::Green {
+                sub:
+                    Some(SupergreenCli::Supergreen {
+                        sub: Some(Supergreen::Builder { sub: Some(BuilderSub::Prune { all }) }),
+                    }),
+            }) => all,
+            cli => panic!("{cli:?}"),
+        }
+    };
+    assert!(!parse(&[]));
+    assert!(parse(&["--all"]));
+}
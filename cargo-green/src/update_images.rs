@@ -0,0 +1,257 @@
+use std::fs;
+
+use anyhow::{Result, anyhow, bail};
+use camino::Utf8PathBuf;
+use indexmap::IndexMap;
+use toml::{Table, Value};
+use toml_edit::{DocumentMut, Item};
+
+use crate::{
+    PKG,
+    add::XX_IMAGE,
+    builder::BUILDKIT_IMAGE,
+    green::{Green, Layer},
+    image_uri::ImageUri,
+    lockfile::{find_manifest_path, find_workspace_manifest_path},
+    registry::fetch_manifest_digest,
+};
+
+/// Settings that can be pinned in `Cargo.toml`
+const WRITABLE: &[&str] = &["base-image"];
+
+impl Green {
+    /// Resolves the current digests of the images in use and shows what changed.
+    ///
+    /// With `write`, locks them in the workspace's or package's `[metadata.green]`.
+    pub(crate) async fn update_images(&self, write: bool) -> Result<()> {
+        let manifest_path = find_manifest_path().await?;
+        let workspace_path = find_workspace_manifest_path(&manifest_path).await?;
+
+        let builder_image = self.builder.image.clone().unwrap_or_else(|| BUILDKIT_IMAGE.clone());
+        let images = [
+            ("syntax", &self.syntax, self.origins.last("syntax")),
+            ("base-image", &self.base.image, self.origins.last("base-image")),
+            ("builder-image", &builder_image, self.origins.last("builder-image")),
+            ("xx", &*XX_IMAGE, None),
+        ];
+
+        let mut edits: IndexMap<Utf8PathBuf, Vec<(&str, &str, ImageUri)>> = IndexMap::new();
+        for (key, current, layer) in images {
+            let tagged = if current.locked() { current.unlocked() } else { current.clone() };
+            if !tagged.tagged() {
+                println!("{key:>13} {current} (no tag to follow, skipping)");
+                continue;
+            }
+            let digest = fetch_manifest_digest(&tagged, self.builder.registries.get(tagged.host()))
+                .await
+                .map_err(|e| anyhow!("Failed getting digest for {tagged}: {e}"))?;
+            let latest = tagged.lock(&digest);
+
+            let place = match layer {
+                _ if !WRITABLE.contains(&key) => None,
+                Some(Layer::Workspace) => {
+                    Some((workspace_path.clone(), "workspace.metadata.green"))
+                }
+                Some(Layer::Package) | None => {
+                    let txt = fs::read_to_string(&manifest_path)?;
+                    let doc: Table = toml::from_str(&txt)
+                        .map_err(|e| anyhow!("Failed parsing {manifest_path}: {e}"))?;
+                    let table = if doc.contains_key("package") {
+                        "package.metadata.green"
+                    } else {
+                        "workspace.metadata.green"
+                    };
+                    Some((manifest_path.clone(), table))
+                }
+                Some(_) => None,
+            };
+
+            // Compare with what is written, as unlocked tags there got locked in memory already
+            let written = match place {
+                Some((ref path, table)) => {
+                    let txt = fs::read_to_string(path)?;
+                    get_in_table(&txt, table, key)?.and_then(|img| ImageUri::try_new(img).ok())
+                }
+                None => None,
+            };
+            let old = written.as_ref().unwrap_or(current);
+
+            if *old == latest {
+                println!("{key:>13} {latest} (up to date)");
+                continue;
+            }
+            println!("{key:>13} {old} -> {latest}");
+            match (place, layer) {
+                (Some((path, table)), _) => {
+                    edits.entry(path).or_default().push((table, key, latest))
+                }
+                (None, _) if key == "syntax" => {
+                    println!("{:>13} (export {}={latest} to use it)", "", ENV_SYNTAX_IMAGE!())
+                }
+                (None, Some(layer)) => println!("{:>13} (set by {layer}, update it there)", ""),
+                (None, None) => println!("{:>13} (built in, upgrade {PKG} instead)", ""),
+            }
+        }
+
+        if edits.is_empty() {
+            return Ok(());
+        }
+        if !write {
+            let paths = edits.keys().map(ToString::to_string).collect::<Vec<_>>().join(" ");
+            println!("Run with --write to update {paths}");
+            return Ok(());
+        }
+
+        for (path, edits) in edits {
+            let mut txt = fs::read_to_string(&path)?;
+            for (table, key, img) in edits {
+                txt = set_in_table(&txt, table, key, img.as_str())
+                    .map_err(|e| anyhow!("Failed updating {path}: {e}"))?;
+            }
+            fs::write(&path, txt).map_err(|e| anyhow!("Failed writing {path}: {e}"))?;
+            println!("Wrote {path}");
+        }
+        Ok(())
+    }
+}
+
+/// Reads the string at `key` of dotted `table`, if any
+fn get_in_table(txt: &str, table: &str, key: &str) -> Result<Option<String>> {
+    let doc: Table = toml::from_str(txt).map_err(|e| anyhow!("Failed parsing TOML: {e}"))?;
+    let mut value = Some(&Value::Table(doc));
+    for name in table.split('.').chain([key]) {
+        value = value.and_then(|value| value.get(name));
+    }
+    Ok(value.and_then(Value::as_str).map(ToOwned::to_owned))
+}
+
+/// Sets `key = "value"` under `[table]`, leaving the rest of the document untouched
+/// (comments, ordering, formatting).
+fn set_in_table(txt: &str, table: &str, key: &str, value: &str) -> Result<String> {
+    let mut doc: DocumentMut = txt.parse().map_err(|e| anyhow!("Failed parsing TOML: {e}"))?;
+
+    let mut item = doc.as_item_mut();
+    for name in table.split('.') {
+        let Some(parent) = item.as_table_like_mut() else {
+            bail!("[{table}] is not a table: please edit {key:?} by hand")
+        };
+        if parent.get(name).is_none() {
+            let mut new = toml_edit::Table::new();
+            new.set_implicit(true);
+            parent.insert(name, Item::Table(new));
+        }
+        item = parent.get_mut(name).expect("PROOF: just inserted");
+    }
+    let Some(table_like) = item.as_table_like_mut() else {
+        bail!("[{table}] is not a table: please edit {key:?} by hand")
+    };
+
+    match table_like.get_mut(key).and_then(Item::as_value_mut) {
+        Some(existing) => {
+            if !existing.is_str() {
+                bail!("Expected a string at [{table}].{key}")
+            }
+            // Keeps whitespace and comments around the value
+            let decor = existing.decor().clone();
+            *existing = value.into();
+            *existing.decor_mut() = decor;
+        }
+        None => {
+            table_like.insert(key, toml_edit::value(value));
+        }
+    }
+    Ok(doc.to_string())
+}
+
+#[cfg(test)]
+const LOCKED: &str = "docker-image://docker.io/library/rust:1-slim@sha256:27086352fd5e1907ea2b934eb1023f217c5ae087992eb59fde121dce9c9ff21e";
+
+#[test]
+fn updates_in_place() {
+    let txt = r#"[package]
+name = "some"
+
+[package.metadata.green]
+# Pinned for reproducibility
+base-image  =  'docker-image://docker.io/library/rust:1-slim' # keep me
+set-envs = [ "A" ]
+
+[dependencies]
+"#;
+    let expected = format!(
+        r#"[package]
+name = "some"
+
+[package.metadata.green]
+# Pinned for reproducibility
+base-image  =  "{LOCKED}" # keep me
+set-envs = [ "A" ]
+
+[dependencies]
+"#
+    );
+    pretty_assertions::assert_eq!(
+        set_in_table(txt, "package.metadata.green", "base-image", LOCKED).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn inserts_in_table() {
+    let txt = "[workspace]\n\n[workspace.metadata.green]\nset-envs = [ \"A\" ]\n\n[profile.dev]\n";
+    let expected = format!(
+        "[workspace]\n\n[workspace.metadata.green]\nset-envs = [ \"A\" ]\nbase-image = \"{LOCKED}\"\n\n[profile.dev]\n"
+    );
+    pretty_assertions::assert_eq!(
+        set_in_table(txt, "workspace.metadata.green", "base-image", LOCKED).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn appends_table() {
+    let txt = "[package]\nname = \"some\"";
+    let expected = format!(
+        "[package]\nname = \"some\"\n\n[package.metadata.green]\nbase-image = \"{LOCKED}\"\n"
+    );
+    pretty_assertions::assert_eq!(
+        set_in_table(txt, "package.metadata.green", "base-image", LOCKED).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn edits_inline_tables() {
+    let txt = "[package]\nname = \"some\"\nmetadata.green = { base-image = \"docker-image://docker.io/library/rust:1-slim\" }\n";
+    let expected =
+        format!("[package]\nname = \"some\"\nmetadata.green = {{ base-image = \"{LOCKED}\" }}\n");
+    pretty_assertions::assert_eq!(
+        set_in_table(txt, "package.metadata.green", "base-image", LOCKED).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn inserts_after_multiline_values() {
+    let txt = r#"[package.metadata.green]
+also-run = [ """
+[ -d /opt ] || mkdir /opt
+""" ]
+
+[dependencies]
+"#;
+    let expected = format!(
+        r#"[package.metadata.green]
+also-run = [ """
+[ -d /opt ] || mkdir /opt
+""" ]
+base-image = "{LOCKED}"
+
+[dependencies]
+"#
+    );
+    pretty_assertions::assert_eq!(
+        set_in_table(txt, "package.metadata.green", "base-image", LOCKED).unwrap(),
+        expected
+    );
+}
@@ -12,6 +12,7 @@ use crate::{
     green::Green,
     logging::{self},
     md::{Md, MdId, Mds},
+    progress,
     stage::{AsStage, RST, RUST, Stage},
     target_dir::virtual_target_dir,
     wrap::call_config,
@@ -86,20 +87,21 @@ pub(crate) async fn exec_build_script(green: Green, exe: Utf8PathBuf) -> Result<
     // Z: for eggZecuting build scripts
     let full_pkg_id = format!("Z {pkg_name} {pkg_version}-{mdid}");
     logging::setup(&full_pkg_id);
+    progress::setup(&full_pkg_id, &format!("{pkg_name} {pkg_version} (build script run)"));
 
     info!("{PKG}@{VSN} original args: {exe:?} green={green:?}");
 
     if green.runner.is_none() {
         if green.reuse_out(&Stage::output(mdid)?, &out_dir_var).await? {
-            return Ok(());
+            return progress::done(Ok(()));
         }
         todo!("fallback()");
     }
 
-    do_exec(
+    let res = do_exec(
         green,
         crate_name.as_deref(),
-        &pkg_name,
+        (&pkg_name, &pkg_version),
         full_pkg_id.replace(' ', "-"),
         out_dir_var,
         exe,
@@ -108,14 +110,15 @@ pub(crate) async fn exec_build_script(green: Green, exe: Utf8PathBuf) -> Result<
         mdid,
     )
     .await
-    .inspect_err(|e| error!("Error: {e}"))
+    .inspect_err(|e| error!("Error: {e}"));
+    progress::done(res)
 }
 
 #[expect(clippy::too_many_arguments)]
 async fn do_exec(
-    green: Green,
+    mut green: Green,
     crate_name: Option<&str>,
-    pkg_name: &str,
+    (pkg_name, pkg_version): (&str, &str),
     crate_id: String,
     out_dir_var: Utf8PathBuf,
     exe: Utf8PathBuf,
@@ -150,6 +153,8 @@ async fn do_exec(
     };
 
     let mut run_block = format!("FROM {RST} AS {run_stage}\n");
+    let set_envs =
+        green.apply_package_override(&mut md, (pkg_name, pkg_version), &mut run_block)?;
 
     run_block.push_str(&format!("WORKDIR {}\n", virtual_target_dir(&out_dir_var)));
     let mut code_stage_mounts = code_stage.mounts();
@@ -161,6 +166,7 @@ async fn do_exec(
     run_block.push_str(&format!("WORKDIR {code_dst}\n"));
 
     run_block.push_str("RUN \\\n");
+    run_block.push_str(&green.run_mounts());
     run_block.push_str(&format!(
         "  --mount=from={previous_out_stage},source={previous_out_dst},dst={exe} \\\n",
         exe = virtual_target_dir(&exe)
@@ -181,7 +187,7 @@ async fn do_exec(
         (&run_stage, run_block),
         crate_name,
         &green.cargo_home,
-        &green.set_envs,
+        &set_envs,
         virtual_target_dir(&exe).as_str(),
         (&out_stage, Some(&out_dir_var)),
     )?;
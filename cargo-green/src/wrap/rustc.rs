@@ -13,10 +13,12 @@ use crate::{
     base_image::rewrite_cargo_home,
     checkouts,
     cratesio::{self, rewrite_cratesio_index},
+    cross,
     dirs::pwd,
     green::Green,
     logging::{self},
     md::{BuildContext, Md, NamedMount},
+    progress::{self, Phase},
     relative,
     rustc_arguments::{RustcArgs, as_rustc},
     stage::{AsStage, RST, RUST, Stage},
@@ -47,20 +49,23 @@ pub(crate) async fn wrap_rustc(
     let full_pkg_id = format!("{kind} {pkg_name} {pkg_version} {mdid}");
 
     logging::setup(&full_pkg_id);
+    let what = if buildrs { " (build script)" } else { "" };
+    progress::setup(&full_pkg_id, &format!("{pkg_name} {pkg_version}{what}"));
 
     info!("{PKG}@{VSN} original args: {arguments:?} pwd={pwd} st={st:?} green={green:?}");
 
     if green.runner.is_none() {
         if green.reuse_out(&Stage::output(mdid)?, &st.out_dir).await? {
-            return Ok(());
+            return progress::done(Ok(()));
         }
-        return fallback.await;
+        progress::report(Phase::Building);
+        return progress::done(fallback.await);
     }
 
-    do_wrap_rustc(
+    let res = do_wrap_rustc(
         green,
         crate_name.as_deref(),
-        &pkg_name,
+        (&pkg_name, &pkg_version),
         &pkg_manifest_dir,
         Stage::dep(&full_pkg_id.replace(' ', "-"))?,
         pwd,
@@ -69,24 +74,28 @@ pub(crate) async fn wrap_rustc(
         st,
     )
     .await
-    .inspect_err(|e| error!("Error: {e}"))
+    .inspect_err(|e| error!("Error: {e}"));
+    progress::done(res)
 }
 
 #[expect(clippy::too_many_arguments)]
 async fn do_wrap_rustc(
-    green: Green,
+    mut green: Green,
     crate_name: Option<&str>,
-    pkg_name: &str,
+    (pkg_name, pkg_version): (&str, &str),
     pkg_manifest_dir: &Utf8Path,
     rustc_stage: Stage,
     pwd: Utf8PathBuf,
     args: Vec<String>,
     out_dir_var: Option<Utf8PathBuf>,
-    RustcArgs { externs, mdid, incremental, input, out_dir, target_path }: RustcArgs,
+    RustcArgs { externs, mdid, incremental, input, out_dir, target_path, target }: RustcArgs,
 ) -> Result<()> {
     let mdid = mdid.expect("mdid set");
     let mut md: Md = mdid.into();
 
+    // Cross-compiled crates are built for their target's platform, others for the local one
+    green.target = target.clone().filter(|target| green.targets.contains(target));
+
     md.buildrs = crate_name.map(is_buildrs_executable).unwrap_or_default();
     md.push_block(&RUST, &green.base.image_inline);
 
@@ -100,6 +109,8 @@ async fn do_wrap_rustc(
     info!("picked {rustc_stage} for {input}");
 
     let mut rustc_block = format!("FROM {RST} AS {rustc_stage}\n");
+    let set_envs =
+        green.apply_package_override(&mut md, (pkg_name, pkg_version), &mut rustc_block)?;
 
     rustc_block.push_str(&format!("WORKDIR {out_dir}\n", out_dir = virtual_target_dir(&out_dir)));
     let not_a_cratesio_crate = !pwd.starts_with(green.cargo_home.join(cratesio::HOME));
@@ -132,6 +143,7 @@ async fn do_wrap_rustc(
     };
     md.push_stage(&code_stage);
     rustc_block.push_str("RUN \\\n");
+    rustc_block.push_str(&green.run_mounts());
     for (src, dst, swappity) in code_stage.mounts() {
         let name = code_stage.name();
         let dst = virtual_target_dir(&dst);
@@ -146,9 +158,20 @@ async fn do_wrap_rustc(
         info!("loading 1 build context");
     }
 
-    let mds = md.assemble_build_dependencies(externs, out_dir_var, &target_path)?;
+    // Cross-compiled crates still depend on host artifacts (e.g. proc-macros)
+    let host_target_path = target.as_deref().and_then(|t| cross::host_target_path(&target_path, t));
+    let mds = md.assemble_build_dependencies(
+        externs,
+        out_dir_var,
+        &target_path,
+        host_target_path.as_deref(),
+    )?;
     for NamedMount { name, mount } in md.externs() {
-        let dst = virtual_target_dir(&target_path).join("deps").join(mount);
+        let deps = match host_target_path {
+            Some(ref host) if !target_path.join("deps").join(mount).exists() => host,
+            _ => &target_path,
+        };
+        let dst = virtual_target_dir(deps).join("deps").join(mount);
         rustc_block.push_str(&format!("  --mount=from={name},dst={dst},source=/{mount} \\\n"));
     }
     for NamedMount { name, mount } in &md.mounts {
@@ -161,6 +184,14 @@ async fn do_wrap_rustc(
         let input = rewrite_cratesio_index(input.as_str());
         let input = rewrite_cargo_home(&green.cargo_home, &input);
 
+        let mut args = args;
+        if let Some(ref target) = target
+            && green.target.as_ref() == Some(target)
+            && !args.windows(2).any(|kv| kv[0] == "-C" && kv[1].starts_with("linker="))
+        {
+            args.extend(["-C".to_owned(), format!("linker={}", cross::linker(target))]);
+        }
+
         let args = args
             .into_iter()
             .map(|ref x| virtual_target_dir_str(x))
@@ -174,7 +205,7 @@ async fn do_wrap_rustc(
         (&rustc_stage, rustc_block),
         crate_name,
         &green.cargo_home,
-        &green.set_envs,
+        &set_envs,
         &call,
         (&out_stage, not_a_cratesio_crate.then_some(&out_dir)),
     )?;
@@ -211,3 +242,86 @@ async fn do_wrap_rustc(
 
     Ok(())
 }
+
+/// Runs `rustc`'s wrapping from its arguments down to untarring its result, against a fake runner.
+#[test]
+fn wraps_rustc_through_a_runner() {
+    use crate::{
+        backend::fake::{Fake, Scripted},
+        rustc_arguments::as_rustc,
+    };
+
+    // Same value as other tests: it is read once per process
+    temp_env::with_var("CARGO_TARGET_DIR", Some("/some/path/"), || {
+        let pwd: Utf8PathBuf =
+            env::temp_dir().join(format!("{PKG}-{}", uuid::Uuid::new_v4())).try_into().unwrap();
+        fs::create_dir_all(pwd.join("src")).unwrap();
+        fs::write(pwd.join("Cargo.toml"), "[package]\nname = \"pkg\"\n").unwrap();
+        fs::write(pwd.join("src/lib.rs"), "pub fn f() {}\n").unwrap();
+        let out_dir = pwd.join("target/debug/deps");
+
+        #[rustfmt::skip]
+        let arguments = [
+            "--crate-name", "pkg",
+            "--edition=2024",
+            "src/lib.rs",
+            "--crate-type", "lib",
+            "--emit=dep-info,metadata,link",
+            "-C", "metadata=0123456789abcdef",
+            "-C", "extra-filename=-0123456789abcdef",
+            "--out-dir", out_dir.as_str(),
+            "-L", &format!("dependency={out_dir}"),
+        ]
+        .map(ToOwned::to_owned);
+        let (st, args) = as_rustc(&pwd, &arguments, None).unwrap();
+        let mdid = st.mdid.unwrap();
+
+        let fake = Fake::new(|built| Scripted {
+            files: if built.out_dir.is_some() {
+                vec![
+                    ("libpkg-0123456789abcdef.rlib".to_owned(), b"RLIB".to_vec()),
+                    ("libpkg-0123456789abcdef.rmeta".to_owned(), b"RMETA".to_vec()),
+                ]
+            } else {
+                vec![]
+            },
+            stderr: "warning: unused\n".to_owned(),
+            ..Default::default()
+        });
+        let green = Green { cargo_home: "/home/maison/.cargo".into(), ..Default::default() };
+        let (green, fake) = fake.into_green(green);
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(do_wrap_rustc(
+                green,
+                Some("pkg"),
+                ("pkg", "0.1.0"),
+                &pwd,
+                Stage::dep("N-pkg-0.1.0-0123456789abcdef").unwrap(),
+                pwd.clone(),
+                args,
+                None,
+                st,
+            ))
+            .unwrap();
+
+        let builds = fake.builds.lock().unwrap();
+        let [built] = &builds[..] else { panic!("{builds:?}") };
+        assert_eq!(built.target, Stage::output(mdid).unwrap());
+        assert_eq!(built.out_dir.as_deref(), Some(out_dir.as_path()));
+        let local = Stage::local(mdid).unwrap();
+        assert_eq!(built.contexts.iter().map(|ctx| &ctx.name).collect::<Vec<_>>(), [&local]);
+        assert!(built.containerfile.contains("rustc --crate-name pkg "), "{}", built.containerfile);
+        assert!(built.containerfile.contains(&format!("FROM scratch AS {}", built.target)));
+
+        assert_eq!(fs::read(out_dir.join("libpkg-0123456789abcdef.rlib")).unwrap(), b"RLIB");
+        assert_eq!(fs::read(out_dir.join("libpkg-0123456789abcdef.rmeta")).unwrap(), b"RMETA");
+        let md = fs::read_to_string(pwd.join(format!("target/debug/{mdid}.toml"))).unwrap();
+        assert!(md.contains("libpkg-0123456789abcdef.rlib"), "{md}");
+
+        fs::remove_dir_all(&pwd).unwrap();
+    });
+}